@@ -0,0 +1,173 @@
+use std::io::Write;
+
+use crate::vm::instructions::Opcode;
+use crate::vm::observer::VmObserver;
+use crate::vm::trap::Trap;
+
+/// Where an instruction was assembled from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineEntry {
+    /// Where the instruction starts in the bytecode
+    pub offset: usize,
+
+    /// The index of the source file in `DebugInfo::files`
+    pub file: usize,
+
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Maps bytecode back to the source it was assembled from, for traps and traces that point at
+/// source lines rather than byte offsets.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DebugInfo {
+    /// The name of every source file, in the order line entries refer to them
+    pub files: Vec<String>,
+
+    /// One entry for every line holding an instruction, sorted by offset. A jump with a target
+    /// expands to two instructions, which share the entry.
+    pub lines: Vec<LineEntry>,
+
+    /// Every label in `.text` along with its offset, sorted by offset
+    pub labels: Vec<(String, usize)>,
+}
+
+impl DebugInfo {
+    /// The line the instruction at `pc` was assembled from.
+    pub fn line(&self, pc: usize) -> Option<&LineEntry> {
+        let index = self.lines.partition_point(|entry| entry.offset <= pc);
+
+        index.checked_sub(1).map(|index| &self.lines[index])
+    }
+
+    /// The closest label at or before `pc`, which is usually the subroutine or loop it is in.
+    pub fn label(&self, pc: usize) -> Option<&str> {
+        let index = self.labels.partition_point(|(_, offset)| *offset <= pc);
+
+        index.checked_sub(1).map(|index| self.labels[index].0.as_str())
+    }
+
+    /// Describes `pc` as `loop.asm:12:5 in `fib_loop``, leaving out whatever is not known.
+    pub fn describe(&self, pc: usize) -> String {
+        let location = match self.line(pc) {
+            Some(entry) => format!("{}:{}:{}", self.files.get(entry.file).map_or("<unknown>", String::as_str), entry.line, entry.column),
+            None => format!("{:#06x}", pc),
+        };
+
+        match self.label(pc) {
+            Some(label) => format!("{} in `{}`", location, label),
+            None => location,
+        }
+    }
+
+    /// Like the trap's `Display`, but pointing at the source line rather than the byte offset.
+    pub fn symbolize(&self, trap: &Trap) -> String {
+        format!("trap at {} (opcode {:#04x}, instruction {}): {}", self.describe(trap.pc), trap.opcode, trap.ic, trap.kind)
+    }
+}
+
+/// Writes a line to `writer` for every instruction executed, naming where it came from in the
+/// source. Write errors are ignored, since there is no one to report them to.
+pub struct Tracer<W: Write> {
+    debug_info: DebugInfo,
+    writer: W,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(debug_info: DebugInfo, writer: W) -> Tracer<W> {
+        Tracer { debug_info, writer }
+    }
+}
+
+impl<W: Write> VmObserver for Tracer<W> {
+    fn before_instruction(&mut self, pc: usize, opcode: Opcode) {
+        let _ = writeln!(self.writer, "{:#06x} {:<6} {}", pc, opcode.instruction(), self.debug_info.describe(pc));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::vm::VM;
+
+    // Lets a test read what a tracer owned by the VM wrote
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn assemble(source: &str) -> VM {
+        let mut assembler = Assembler::default();
+
+        assembler.emit_debug_info = true;
+        assembler.compile_source("loop.asm", source).unwrap();
+
+        VM::from_program(assembler.result)
+    }
+
+    #[test]
+    fn describe_offsets() {
+        let debug_info = DebugInfo {
+            files: vec!["main.asm".to_string()],
+            lines: vec![
+                LineEntry { offset: 0, file: 0, line: 2, column: 5 },
+                LineEntry { offset: 4, file: 0, line: 4, column: 9 },
+            ],
+            labels: vec![("fib_loop".to_string(), 4)],
+        };
+
+        assert_eq!(debug_info.describe(0), "main.asm:2:5");
+        assert_eq!(debug_info.describe(4), "main.asm:4:9 in `fib_loop`");
+        assert_eq!(debug_info.describe(6), "main.asm:4:9 in `fib_loop`");
+        assert_eq!(DebugInfo::default().describe(6), "0x0006");
+    }
+
+    #[test]
+    fn symbolize_traps() {
+        let mut test_vm = assemble("
+            SET $1 3
+        fib_loop:
+            DEC $1
+            DIV $2 $1 $0
+        ");
+
+        let debug_info = test_vm.debug_info.clone().unwrap();
+
+        assert_eq!(debug_info.files, vec!["loop.asm".to_string()]);
+        assert_eq!(debug_info.labels, vec![("fib_loop".to_string(), 4)]);
+
+        let trap = test_vm.run().unwrap_err();
+
+        assert_eq!(debug_info.symbolize(&trap), "trap at loop.asm:5:13 in `fib_loop` (opcode 0x13, instruction 3): divide by zero");
+    }
+
+    #[test]
+    fn trace_execution() {
+        let mut test_vm = assemble("main:\n    INC $0\n    JMP $1 done\n    HLT\ndone:\n    DEC $0");
+
+        let buffer = SharedBuffer::default();
+
+        test_vm.set_observer(Tracer::new(test_vm.debug_info.clone().unwrap(), buffer.clone()));
+        test_vm.run().unwrap();
+
+        assert_eq!(String::from_utf8(buffer.0.borrow().clone()).unwrap(), "\
+0x0000 INC    loop.asm:2:5 in `main`
+0x0002 SET    loop.asm:3:5 in `main`
+0x0006 JMP    loop.asm:3:5 in `main`
+0x0009 DEC    loop.asm:6:5 in `done`
+");
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use crate::vm::instructions::{Instruction, Opcode};
+use crate::vm::operand::OperandKind;
+
+/// An instruction read back from bytecode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisassembledInstruction {
+    /// Where the instruction starts in the bytecode
+    pub offset: usize,
+
+    /// The bytes the instruction was decoded from
+    pub bytes: Vec<u8>,
+
+    /// The opcode, or none for a byte that does not start a valid instruction. Such bytes are kept
+    /// as data so the bytecode can still be assembled again.
+    pub opcode: Option<Opcode>,
+
+    /// Every operand along with its kind, in encoding order
+    pub operands: Vec<(OperandKind, u16)>,
+
+    /// The offset a jump or call goes to, when it can be told from the bytecode alone. A `SET` that
+    /// loads the register a following absolute jump uses has the same target.
+    pub target: Option<usize>,
+}
+
+/// Decodes every instruction in `bytecode`. Jumps through a register get a target when the `SET`
+/// right before them loads that register, which is how the assembler expands `JMP $6 loop`.
+pub fn disassemble(bytecode: &[u8]) -> Vec<DisassembledInstruction> {
+    let mut instructions = vec![];
+    let mut offset = 0;
+
+    while offset < bytecode.len() {
+        let (opcode, end) = match Instruction::decode(bytecode, offset) {
+            Ok((instruction, end)) => (Some(instruction.opcode()), end),
+            Err(_) => (None, offset + 1),
+        };
+
+        let bytes = bytecode[offset..end].to_vec();
+
+        instructions.push(DisassembledInstruction { offset, operands: operands(opcode, &bytes), bytes, opcode, target: None });
+
+        offset = end;
+    }
+
+    let starts: HashSet<usize> = instructions.iter().filter(|instruction| instruction.opcode.is_some()).map(|instruction| instruction.offset).collect();
+
+    // Targets must start an instruction or lie at the end of the bytecode
+    let is_target = |target: usize| starts.contains(&target) || target == bytecode.len();
+
+    for i in 0..instructions.len() {
+        let end = instructions[i].offset + instructions[i].bytes.len();
+
+        let target = match (instructions[i].opcode, instructions[i].operands.as_slice()) {
+            (Some(Opcode::Call), &[(_, target)]) => Some(target as usize),
+            (Some(opcode), &[(OperandKind::Register, register)]) if i > 0 => {
+                let value = match (instructions[i - 1].opcode, instructions[i - 1].operands.as_slice()) {
+                    (Some(Opcode::Set), &[(_, set), (_, value)]) if set == register => value as usize,
+                    _ => continue,
+                };
+
+                match opcode {
+                    Opcode::Jump | Opcode::JumpIfEqual | Opcode::CallRegister => {
+                        if is_target(value) {
+                            instructions[i - 1].target = Some(value);
+                        }
+
+                        Some(value)
+                    },
+                    Opcode::JumpForward => end.checked_add(value),
+                    Opcode::JumpBackward => end.checked_sub(value),
+                    _ => None,
+                }
+            },
+            _ => None,
+        };
+
+        instructions[i].target = target.filter(|&target| is_target(target));
+    }
+
+    instructions
+}
+
+// The operands encoded in `bytes` after the opcode.
+fn operands(opcode: Option<Opcode>, bytes: &[u8]) -> Vec<(OperandKind, u16)> {
+    let kinds = match opcode {
+        Some(opcode) => opcode.operands(),
+        None => return vec![],
+    };
+
+    let mut pos = 1;
+
+    kinds.iter().map(|&kind| {
+        // Little endian, like everything else in the bytecode
+        let value = bytes[pos..pos + kind.size()].iter().rev().fold(0, |value, &byte| value << 8 | u16::from(byte));
+
+        pos += kind.size();
+
+        (kind, value)
+    }).collect()
+}
+
+// The name given to the label at a jump target.
+fn label(target: usize) -> String {
+    format!("L{:04x}", target)
+}
+
+/// Renders instructions as assembly that assembles back to the same bytes. Every line holds the
+/// mnemonic and operands, followed by a comment with the offset, the raw bytes and what the
+/// instruction does. Jump targets get a label, which operands and comments refer to:
+///
+/// ```text
+///     SET $6 L0007           ; 0000: 01 06 07 00  Set $target using constant bytes.
+///     JMP $6                 ; 0004: 60 06        Jump to #byte. -> L0007
+///     HLT                    ; 0006: 00           Stop execution immediately.
+/// L0007:
+///     RET                    ; 0007: 66           Return from the current subroutine.
+/// ```
+pub fn render(instructions: &[DisassembledInstruction]) -> String {
+    let targets: HashSet<usize> = instructions.iter().filter_map(|instruction| instruction.target).collect();
+
+    let mut text = String::new();
+
+    for instruction in instructions {
+        if targets.contains(&instruction.offset) {
+            writeln!(text, "{}:", label(instruction.offset)).unwrap();
+        }
+
+        let mut code = match instruction.opcode {
+            Some(opcode) => opcode.instruction().to_string(),
+            None => format!(".byte {:#04x}", instruction.bytes[0]),
+        };
+
+        for &(kind, value) in &instruction.operands {
+            match kind {
+                _ if kind.is_register() => write!(code, " ${}", value).unwrap(),
+
+                // Addresses a jump or call goes to are written as labels
+                OperandKind::Imm16 if instruction.target == Some(value as usize) => write!(code, " {}", label(value as usize)).unwrap(),
+
+                _ => write!(code, " {}", value).unwrap(),
+            }
+        }
+
+        let bytes: Vec<String> = instruction.bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        write!(text, "    {:<22} ; {:04x}: {:<12}", code, instruction.offset, bytes.join(" ")).unwrap();
+
+        if let Some(opcode) = instruction.opcode {
+            write!(text, " {}", opcode.info()).unwrap();
+        }
+
+        // Jumps through a register only name their target in the comment
+        if let (Some(target), [(OperandKind::Register, _)]) = (instruction.target, instruction.operands.as_slice()) {
+            write!(text, " -> {}", label(target)).unwrap();
+        }
+
+        text.truncate(text.trim_end().len());
+        text.push('\n');
+    }
+
+    // A jump may target the end of the bytecode
+    if let Some(end) = instructions.last().map(|instruction| instruction.offset + instruction.bytes.len()) {
+        if targets.contains(&end) {
+            writeln!(text, "{}:", label(end)).unwrap();
+        }
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    fn assemble(source: &str) -> Vec<u8> {
+        let mut assembler = Assembler::default();
+
+        assembler.compile(source).unwrap();
+
+        assembler.result.bytecode
+    }
+
+    #[test]
+    fn disassemble_instructions() {
+        let instructions = disassemble(&assemble("SET $0 500\nADDF $1 $2 $3\nCALL end\nend: HLT"));
+
+        assert_eq!(instructions, vec![
+            DisassembledInstruction {
+                offset: 0,
+                bytes: vec![Opcode::Set.byte(), 0, 244, 1],
+                opcode: Some(Opcode::Set),
+                operands: vec![(OperandKind::Register, 0), (OperandKind::Imm16, 500)],
+                target: None,
+            },
+            DisassembledInstruction {
+                offset: 4,
+                bytes: vec![Opcode::AddF64.byte(), 1, 2, 3],
+                opcode: Some(Opcode::AddF64),
+                operands: vec![(OperandKind::FloatRegister, 1), (OperandKind::FloatRegister, 2), (OperandKind::FloatRegister, 3)],
+                target: None,
+            },
+            DisassembledInstruction {
+                offset: 8,
+                bytes: vec![Opcode::Call.byte(), 11, 0],
+                opcode: Some(Opcode::Call),
+                operands: vec![(OperandKind::Imm16, 11)],
+                target: Some(11),
+            },
+            DisassembledInstruction {
+                offset: 11,
+                bytes: vec![Opcode::Halt.byte()],
+                opcode: Some(Opcode::Halt),
+                operands: vec![],
+                target: None,
+            },
+        ]);
+
+        // Bytes that do not decode are kept one at a time
+        let instructions = disassemble(&[0xff, 0xfe, Opcode::Set.byte(), 1]);
+
+        assert_eq!(instructions.len(), 4);
+        assert!(instructions.iter().all(|instruction| instruction.opcode.is_none() && instruction.bytes.len() == 1));
+    }
+
+    #[test]
+    fn render_listing() {
+        let bytecode = assemble("
+                JMP $6 end
+            loop:
+                DEC $0
+                JMPB $7 loop
+                CALL end
+            end:
+                HLT
+        ");
+
+        assert_eq!(render(&disassemble(&bytecode)), "    SET $6 L0011           ; 0000: 01 06 11 00  Set $target using constant bytes.
+    JMP $6                 ; 0004: 60 06        Jump to #byte. -> L0011
+L0006:
+    DEC $0                 ; 0006: 1a 00        Decrement $target by 1.
+    SET $7 8               ; 0008: 01 07 08 00  Set $target using constant bytes.
+    JMPB $7                ; 000c: 62 07        Jump backward $bytes. -> L0006
+    CALL L0011             ; 000e: 64 11 00     Call the subroutine at the constant byte offset. $8-$15 are restored on return.
+L0011:
+    HLT                    ; 0011: 00           Stop execution immediately.
+");
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytecode = assemble("
+            .equ COUNT 10
+
+                SET $1 COUNT
+                SETF $2 3
+            loop:
+                DEC $1
+                EQ $1 $0
+                JEQ $3 done
+                JMPB $4 loop
+                CALLR $5 sub
+            done:
+                HLT
+            sub:
+                PUSH $8
+                POP $8
+                RET
+        ");
+
+        let mut corrupted = bytecode.clone();
+
+        // Invalid opcodes, bad registers and truncated instructions survive as data
+        corrupted.extend_from_slice(&[0xff, Opcode::Add.byte(), 16, 0, 0, Opcode::Set.byte(), 1]);
+
+        for bytecode in &[bytecode, corrupted] {
+            assert_eq!(&assemble(&render(&disassemble(bytecode))), bytecode);
+        }
+    }
+}
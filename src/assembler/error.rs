@@ -0,0 +1,314 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::assembler::preprocessor::MAX_MACRO_DEPTH;
+use crate::vm::call::{ARGUMENT_REGISTERS, FLOAT_ARGUMENT_REGISTERS};
+use crate::vm::instructions::Opcode;
+use crate::vm::operand::OperandKind;
+
+/// A range of characters on a single line of source, used to point errors at what caused them.
+/// Lines and columns start at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    /// Index of the file among those read by the assembler, where 0 is the one being compiled
+    pub file: usize,
+
+    pub line: usize,
+    pub column: usize,
+
+    /// The number of characters covered, at least 1
+    pub len: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AssemblerErrorKind {
+    UnexpectedCharacter(char),
+    InvalidInteger(String),
+    ExpectedMnemonic,
+    UnknownMnemonic { name: String, suggestion: Option<&'static str> },
+    UnexpectedToken(String),
+    UnterminatedString,
+    InvalidEscape(char),
+
+    /// A character literal that is empty, unclosed or holds more than one character
+    InvalidCharacterLiteral,
+
+    UnclosedParenthesis,
+
+    /// An instruction or directive, named as written, was given the wrong number of operands
+    WrongOperandCount { name: String, expected: usize, found: usize },
+    ExpectedOperand(OperandKind),
+    BadRegister { register: u32, kind: OperandKind },
+    OutOfRange { value: i64, kind: OperandKind },
+    DuplicateLabel { name: String, first_line: usize },
+    UndefinedLabel(String),
+
+    /// A `JMPF` target lies behind the jump, or a `JMPB` target ahead of it
+    WrongJumpDirection(Opcode),
+
+    DivisionByZero,
+
+    /// A constant expression does not fit in 64 bits
+    ArithmeticOverflow,
+
+    /// A size or alignment depends on the address of a label, which is not known until the program
+    /// is laid out
+    NotConstant,
+
+    /// A value depends on the address of a label in a way linking cannot patch
+    NotRelocatable,
+
+    /// A constant, named here, is used before the `.equ` that defines it
+    ConstantUsedBeforeDefinition(String),
+
+    UnknownDirective(String),
+    ExpectedArgument(&'static str),
+    DataOutOfRange { value: i64, size: usize },
+    InvalidAlignment(i64),
+    InvalidSize(i64),
+    InstructionOutsideText,
+
+    /// A directive that emits data was used in `.bss`
+    DataInBss(String),
+
+    /// `.entry` was used more than once
+    DuplicateEntry { first_line: usize },
+
+    /// The entry point, named here, is not a label in `.text`
+    EntryOutsideText(String),
+
+    /// A signature given to `.export`, as written, does not parse
+    InvalidSignature(String),
+
+    /// A function is exported to the host more than once
+    DuplicateExport { name: String, first_line: usize },
+
+    /// A label exported to the host, named here, is not in `.text`
+    ExportOutsideText(String),
+
+    /// A `.macro`, named here, was not closed with `.endm` in the same file
+    UnterminatedMacro(String),
+    UnmatchedEndm,
+    NestedMacro,
+    DuplicateMacro { name: String, first_line: usize },
+    MacroShadowsMnemonic(String),
+
+    /// A macro, named here, expanded more than `MAX_MACRO_DEPTH` levels deep
+    MacroRecursion(String),
+
+    /// The file at `path` could not be read, for the given reason
+    IncludeFailed { path: String, reason: io::ErrorKind },
+
+    /// A file, named here, includes itself through the files it includes
+    IncludeCycle(String),
+}
+
+impl AssemblerErrorKind {
+    /// Advice on how to fix the error, shown below the source it points at.
+    pub fn hint(&self) -> Option<String> {
+        match self {
+            AssemblerErrorKind::ExpectedMnemonic => Some("lines hold an optional `label:` followed by an instruction or directive".to_string()),
+            AssemblerErrorKind::UnknownMnemonic { suggestion: Some(suggestion), .. } => Some(format!("did you mean `{}`?", suggestion)),
+            AssemblerErrorKind::UnterminatedString => Some("strings must be closed with `\"` on the same line".to_string()),
+            AssemblerErrorKind::InvalidEscape(_) => Some("supported escapes are \\n, \\t, \\r, \\0, \\\\, \\\" and \\'".to_string()),
+            AssemblerErrorKind::InvalidCharacterLiteral => Some("character literals hold a single character, as in 'a' or '\\n'".to_string()),
+            AssemblerErrorKind::WrongOperandCount { name, .. } => {
+                let opcode = Opcode::from_instruction(name)?;
+
+                let mut operands: Vec<String> = opcode.operands().iter().map(|kind| kind.to_string()).collect();
+
+                let usage = match operands.pop() {
+                    None => format!("{} takes no operands", opcode.instruction()),
+                    Some(last) if operands.is_empty() => format!("{} takes {}", opcode.instruction(), last),
+                    Some(last) => format!("{} takes {} and {}", opcode.instruction(), operands.join(", "), last),
+                };
+
+                match opcode {
+                    Opcode::Jump | Opcode::JumpIfEqual | Opcode::CallRegister | Opcode::JumpForward | Opcode::JumpBackward => Some(format!("{}, optionally followed by a jump target", usage)),
+                    _ => Some(usage),
+                }
+            },
+            AssemblerErrorKind::ExpectedOperand(OperandKind::Register) | AssemblerErrorKind::ExpectedOperand(OperandKind::FloatRegister) => Some("registers are written like `$3`".to_string()),
+            AssemblerErrorKind::BadRegister { kind: kind @ OperandKind::FloatRegister, .. } => Some(format!("float registers are $0-${}", kind.max())),
+            AssemblerErrorKind::BadRegister { kind, .. } => Some(format!("integer registers are $0-${}", kind.max())),
+            AssemblerErrorKind::OutOfRange { kind, .. } => Some(format!("the value must lie between 0 and {}", kind.max())),
+            AssemblerErrorKind::DuplicateLabel { first_line, .. } => Some(format!("first defined on line {}", first_line)),
+            AssemblerErrorKind::UndefinedLabel(_) => Some("labels are defined by writing `name:` in front of an instruction or directive, and constants with `.equ name value`".to_string()),
+            AssemblerErrorKind::WrongJumpDirection(Opcode::JumpForward) => Some("use JMPB to jump backward".to_string()),
+            AssemblerErrorKind::WrongJumpDirection(_) => Some("use JMPF to jump forward".to_string()),
+            AssemblerErrorKind::NotConstant => Some("only numbers and constants that do not use labels can be used here".to_string()),
+            AssemblerErrorKind::NotRelocatable => Some("only a label plus or minus a number, or the distance between labels in the same section, can be used here; JMPF and JMPB cannot reach labels from `.extern`".to_string()),
+            AssemblerErrorKind::ConstantUsedBeforeDefinition(_) => Some("move the `.equ` above its first use".to_string()),
+            AssemblerErrorKind::UnknownDirective(_) => Some("known directives are .text, .rodata, .data, .bss, .byte, .word, .dword, .f64, .string, .asciiz, .align, .space, .equ, .entry, .global, .extern, .export, .macro, .endm and .include".to_string()),
+            AssemblerErrorKind::DataOutOfRange { size, .. } => {
+                let bits = *size as u32 * 8;
+
+                Some(format!("the value must lie between {} and {}", -(1i128 << (bits - 1)), (1i128 << bits) - 1))
+            },
+            AssemblerErrorKind::InvalidAlignment(_) => Some("alignments must be powers of two, such as 4 or 8".to_string()),
+            AssemblerErrorKind::InstructionOutsideText => Some("switch back to code with `.text`".to_string()),
+            AssemblerErrorKind::DataInBss(_) => Some("reserve space in .bss with `.space`, or move the data to .data".to_string()),
+            AssemblerErrorKind::DuplicateEntry { first_line } => Some(format!("first set on line {}", first_line)),
+            AssemblerErrorKind::EntryOutsideText(_) => Some("execution can only start at a label in .text".to_string()),
+            AssemblerErrorKind::InvalidSignature(_) => Some(format!("signatures list argument and result types, as in \"i32, f64 -> i32\", with at most {} of type i32 and {} of type f64 on each side", ARGUMENT_REGISTERS.len(), FLOAT_ARGUMENT_REGISTERS.len())),
+            AssemblerErrorKind::DuplicateExport { first_line, .. } => Some(format!("first exported on line {}", first_line)),
+            AssemblerErrorKind::ExportOutsideText(_) => Some("only labels in .text can be called".to_string()),
+            AssemblerErrorKind::UnterminatedMacro(_) => Some("close the macro with `.endm`".to_string()),
+            AssemblerErrorKind::NestedMacro => Some("define the inner macro before this one instead".to_string()),
+            AssemblerErrorKind::DuplicateMacro { first_line, .. } => Some(format!("first defined on line {}", first_line)),
+            AssemblerErrorKind::MacroShadowsMnemonic(_) => Some("macros cannot share a name with an instruction".to_string()),
+            AssemblerErrorKind::MacroRecursion(_) => Some(format!("macros can only expand {} levels deep", MAX_MACRO_DEPTH)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AssemblerErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssemblerErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character `{}`", c),
+            AssemblerErrorKind::InvalidInteger(text) => write!(f, "invalid integer `{}`", text),
+            AssemblerErrorKind::ExpectedMnemonic => write!(f, "expected a mnemonic"),
+            AssemblerErrorKind::UnknownMnemonic { name, .. } => write!(f, "unknown mnemonic `{}`", name),
+            AssemblerErrorKind::UnexpectedToken(token) => write!(f, "unexpected {}", token),
+            AssemblerErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            AssemblerErrorKind::InvalidEscape(c) => write!(f, "unknown escape sequence `\\{}`", c),
+            AssemblerErrorKind::InvalidCharacterLiteral => write!(f, "invalid character literal"),
+            AssemblerErrorKind::UnclosedParenthesis => write!(f, "unclosed parenthesis"),
+            AssemblerErrorKind::WrongOperandCount { name, expected: 1, found } => write!(f, "{} expects 1 operand, found {}", name, found),
+            AssemblerErrorKind::WrongOperandCount { name, expected, found } => write!(f, "{} expects {} operands, found {}", name, expected, found),
+            AssemblerErrorKind::ExpectedOperand(kind) => write!(f, "expected {}", kind),
+            AssemblerErrorKind::BadRegister { register, .. } => write!(f, "register ${} out of range", register),
+            AssemblerErrorKind::OutOfRange { value, kind } => write!(f, "{} does not fit in {}", value, kind),
+            AssemblerErrorKind::DuplicateLabel { name, .. } => write!(f, "symbol `{}` is defined more than once", name),
+            AssemblerErrorKind::UndefinedLabel(name) => write!(f, "label `{}` is not defined", name),
+            AssemblerErrorKind::WrongJumpDirection(opcode) => write!(f, "target of {} lies in the wrong direction", opcode.instruction()),
+            AssemblerErrorKind::DivisionByZero => write!(f, "division by zero"),
+            AssemblerErrorKind::ArithmeticOverflow => write!(f, "arithmetic overflow"),
+            AssemblerErrorKind::NotConstant => write!(f, "value must be known before the program is laid out"),
+            AssemblerErrorKind::NotRelocatable => write!(f, "value cannot be patched when the program is linked"),
+            AssemblerErrorKind::ConstantUsedBeforeDefinition(name) => write!(f, "constant `{}` is used before it is defined", name),
+            AssemblerErrorKind::UnknownDirective(name) => write!(f, "unknown directive `.{}`", name),
+            AssemblerErrorKind::ExpectedArgument(expected) => write!(f, "expected {}", expected),
+            AssemblerErrorKind::DataOutOfRange { value, size } => write!(f, "{} does not fit in {} bytes", value, size),
+            AssemblerErrorKind::InvalidAlignment(value) => write!(f, "alignment {} is not a power of two", value),
+            AssemblerErrorKind::InvalidSize(value) => write!(f, "size {} is negative", value),
+            AssemblerErrorKind::InstructionOutsideText => write!(f, "instructions can only be placed in .text"),
+            AssemblerErrorKind::DataInBss(name) => write!(f, "`.{}` cannot be used in .bss, which only reserves space", name),
+            AssemblerErrorKind::DuplicateEntry { .. } => write!(f, "the entry point is set more than once"),
+            AssemblerErrorKind::EntryOutsideText(name) => write!(f, "entry point `{}` is not code", name),
+            AssemblerErrorKind::InvalidSignature(signature) => write!(f, "invalid signature \"{}\"", signature),
+            AssemblerErrorKind::DuplicateExport { name, .. } => write!(f, "function `{}` is exported more than once", name),
+            AssemblerErrorKind::ExportOutsideText(name) => write!(f, "exported function `{}` is not code", name),
+            AssemblerErrorKind::UnterminatedMacro(name) => write!(f, "macro `{}` is never closed", name),
+            AssemblerErrorKind::UnmatchedEndm => write!(f, "`.endm` without a `.macro`"),
+            AssemblerErrorKind::NestedMacro => write!(f, "macros cannot be defined inside another macro"),
+            AssemblerErrorKind::DuplicateMacro { name, .. } => write!(f, "macro `{}` is defined more than once", name),
+            AssemblerErrorKind::MacroShadowsMnemonic(name) => write!(f, "macro `{}` has the name of an instruction", name),
+            AssemblerErrorKind::MacroRecursion(name) => write!(f, "macro `{}` expands without end", name),
+            AssemblerErrorKind::IncludeFailed { path, reason } => write!(f, "could not read `{}`: {}", path, reason),
+            AssemblerErrorKind::IncludeCycle(path) => write!(f, "`{}` includes itself", path),
+        }
+    }
+}
+
+/// An error in assembly source, rendered in the style of rustc:
+///
+/// ```text
+/// error: register $17 out of range
+///  --> fib.asm:3:9
+///   |
+/// 3 |     ADD $17 $3 $4
+///   |         ^^^
+///   = help: integer registers are $0-$15
+/// ```
+///
+/// Errors about a whole file rather than a place in it have a span on line 0, and are rendered
+/// without source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblerError {
+    pub kind: AssemblerErrorKind,
+    pub span: Span,
+
+    /// The name of the file the error is in
+    pub file: String,
+
+    /// The line of source `span` points into
+    pub source_line: String,
+}
+
+impl AssemblerError {
+    /// Creates an error without a file or source line, which the assembler fills in once it is done.
+    pub fn new(kind: AssemblerErrorKind, span: Span) -> AssemblerError {
+        AssemblerError { kind, span, file: String::new(), source_line: String::new() }
+    }
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let gutter = " ".repeat(self.span.line.to_string().len());
+
+        writeln!(f, "error: {}", self.kind)?;
+
+        if self.span.line == 0 {
+            write!(f, "{}--> {}", gutter, self.file)?;
+
+            if let Some(hint) = self.kind.hint() {
+                write!(f, "\n{} = help: {}", gutter, hint)?;
+            }
+
+            return Ok(());
+        }
+
+        writeln!(f, "{}--> {}:{}:{}", gutter, self.file, self.span.line, self.span.column)?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.span.line, self.source_line)?;
+
+        // Keep tabs so the caret lines up with the source above it
+        let padding: String = self.source_line.chars()
+            .take(self.span.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        write!(f, "{} | {}{}", gutter, padding, "^".repeat(self.span.len.max(1)))?;
+
+        if let Some(hint) = self.kind.hint() {
+            write!(f, "\n{} = help: {}", gutter, hint)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for AssemblerError { }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_error() {
+        let mut error = AssemblerError::new(AssemblerErrorKind::BadRegister { register: 17, kind: OperandKind::Register }, Span { file: 0, line: 3, column: 6, len: 3 });
+
+        error.file = "fib.asm".to_string();
+        error.source_line = "\tADD $17 $3 $4".to_string();
+
+        assert_eq!(error.to_string(), "\
+error: register $17 out of range
+ --> fib.asm:3:6
+  |
+3 | \tADD $17 $3 $4
+  | \t    ^^^
+  = help: integer registers are $0-$15");
+    }
+
+    #[test]
+    fn hint_at_operands() {
+        let error = AssemblerErrorKind::WrongOperandCount { name: "setf".to_string(), expected: 2, found: 1 };
+
+        assert_eq!(error.to_string(), "setf expects 2 operands, found 1");
+        assert_eq!(error.hint().unwrap(), "SETF takes a float register and a 16-bit immediate");
+    }
+}
@@ -1,65 +1,339 @@
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
 use byteorder::ByteOrder;
 use byteorder::LittleEndian;
 
+use crate::assembler::checksum::crc32;
+
 pub const MAIGC_NUMBER: [u8; 5] = [ 0x6c, 0x75, 0x78, 0x0d, 0x0a ];
 
-pub const HEADER_LENGTH: usize = 64;
+/// The length of the fixed part of the header, which the section table follows.
+pub const HEADER_LENGTH: usize = 16;
+
+/// The length of every entry in the section table.
+pub const SECTION_ENTRY_LENGTH: usize = 16;
+
+/// Version 2 added a checksum to every section, and version 1 the section table. Version 0 files
+/// held the entry point in a zero-padded 64-byte block, followed by the read only data and the
+/// bytecode.
+pub const VERSION: u16 = 2;
+
+/// The section is mapped into the VM when the program runs
+pub const SECTION_LOADED: u8 = 1;
 
-pub const VERSION: u16 = 0;
+/// The section takes no space in the file, and is filled with zeros when loaded
+pub const SECTION_ZEROED: u8 = 2;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[non_exhaustive]
 pub enum HeaderErrorKind {
     MagicNumber,
-    OutdatedVersion
+
+    /// The file was made by an older version of the assembler
+    OutdatedVersion,
+
+    /// The file was made by a newer version of the assembler, whose version is given
+    UnsupportedVersion(u16),
+
+    /// The input ends before the header or one of the sections it describes
+    Truncated,
+
+    /// The contents of a section, named here, do not match their checksum
+    Corrupted(SectionKind),
+
+    /// The section table holds a section kind, given here, that does not exist
+    UnknownSection(u8),
+
+    /// A section appears more than once, or its contents cannot be decoded
+    InvalidSection(SectionKind),
+
+    /// The entry point lies past the end of the bytecode
+    InvalidEntryPoint,
+
+    /// The program needs a larger heap than a VM has by default
+    TooLarge,
+
+    /// The input could not be read
+    Io(io::ErrorKind),
+}
+
+impl HeaderErrorKind {
+    /// A suggestion on how to fix the error, if there is one.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            HeaderErrorKind::OutdatedVersion => Some("assemble the program again from its source to upgrade it"),
+            HeaderErrorKind::UnsupportedVersion(_) => Some("run the program with the version that made it, or a newer one"),
+            HeaderErrorKind::Truncated | HeaderErrorKind::Corrupted(_) => Some("the file was damaged, copy or assemble it again"),
+            _ => None,
+        }
+    }
 }
 
+/// An error found while decoding a header, or the program file it belongs to.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DecodeHeaderError {
     kind: HeaderErrorKind,
 }
 
+impl DecodeHeaderError {
+    pub fn new(kind: HeaderErrorKind) -> DecodeHeaderError {
+        DecodeHeaderError { kind }
+    }
+
+    pub fn kind(&self) -> &HeaderErrorKind {
+        &self.kind
+    }
+}
+
+impl From<io::Error> for DecodeHeaderError {
+    fn from(error: io::Error) -> Self {
+        DecodeHeaderError::new(HeaderErrorKind::Io(error.kind()))
+    }
+}
+
+impl fmt::Display for DecodeHeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            HeaderErrorKind::MagicNumber => write!(f, "not a program file"),
+            HeaderErrorKind::OutdatedVersion => write!(f, "program file has an outdated format"),
+            HeaderErrorKind::UnsupportedVersion(version) => write!(f, "program file has version {}, newer than the supported {}", version, VERSION),
+            HeaderErrorKind::Truncated => write!(f, "program file is truncated"),
+            HeaderErrorKind::Corrupted(kind) => write!(f, "{} section does not match its checksum", kind),
+            HeaderErrorKind::UnknownSection(kind) => write!(f, "unknown section kind {}", kind),
+            HeaderErrorKind::InvalidSection(kind) => write!(f, "invalid {} section", kind),
+            HeaderErrorKind::InvalidEntryPoint => write!(f, "entry point lies outside of the bytecode"),
+            HeaderErrorKind::TooLarge => write!(f, "program needs more heap than is available"),
+            HeaderErrorKind::Io(kind) => write!(f, "could not read program: {:?}", kind),
+        }
+    }
+}
+
+impl Error for DecodeHeaderError { }
+
+/// An error found while encoding a program file. A value, such as the length of a section or of a
+/// name, is too large for the field holding it, and writing it anyway would make an unreadable file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodeError {
+    section: Option<SectionKind>,
+}
+
+impl EncodeError {
+    pub fn new(section: Option<SectionKind>) -> EncodeError {
+        EncodeError { section }
+    }
+
+    /// The section holding the value, or `None` if it is in the header.
+    pub fn section(&self) -> Option<SectionKind> {
+        self.section
+    }
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.section {
+            Some(kind) => write!(f, "{} section holds a value too large for the program file format", kind),
+            None => write!(f, "header holds a value too large for the program file format"),
+        }
+    }
+}
+
+impl Error for EncodeError { }
+
+impl From<EncodeError> for io::Error {
+    fn from(error: EncodeError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
+
+// Converts `value` to the type of the field holding it, failing rather than truncating it.
+pub(crate) fn narrow<T: TryFrom<usize>>(value: usize, section: Option<SectionKind>) -> Result<T, EncodeError> {
+    T::try_from(value).map_err(|_| EncodeError::new(section))
+}
+
+/// What a section of a program file holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SectionKind {
+    /// `.rodata` and `.data`, mapped onto the heap at address 0
+    ReadOnly = 1,
+
+    Bytecode = 2,
+
+    /// The symbol table and relocations of an object, along with its alignment
+    Symbols = 3,
+
+    /// Information for debuggers, which running the program does not need
+    Debug = 4,
+
+    /// Zeroed heap following the read only data. Only its length is stored.
+    Bss = 5,
+
+    /// Functions the host can call by name, along with their signatures
+    Exports = 6,
+}
+
+impl TryFrom<u8> for SectionKind {
+    type Error = HeaderErrorKind;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        match byte {
+            1 => Ok(SectionKind::ReadOnly),
+            2 => Ok(SectionKind::Bytecode),
+            3 => Ok(SectionKind::Symbols),
+            4 => Ok(SectionKind::Debug),
+            5 => Ok(SectionKind::Bss),
+            6 => Ok(SectionKind::Exports),
+            _ => Err(HeaderErrorKind::UnknownSection(byte)),
+        }
+    }
+}
+
+impl fmt::Display for SectionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SectionKind::ReadOnly => write!(f, "read only data"),
+            SectionKind::Bytecode => write!(f, "bytecode"),
+            SectionKind::Symbols => write!(f, "symbols"),
+            SectionKind::Debug => write!(f, "debug info"),
+            SectionKind::Bss => write!(f, "bss"),
+            SectionKind::Exports => write!(f, "exports"),
+        }
+    }
+}
+
+/// An entry in the section table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionHeader {
+    pub kind: SectionKind,
+
+    /// `SECTION_LOADED` and `SECTION_ZEROED`
+    pub flags: u8,
+
+    /// Where the contents start in the file. Always 0 for zeroed sections.
+    pub offset: usize,
+
+    /// The number of bytes in the section
+    pub length: usize,
+
+    /// The CRC-32 of the contents. Always 0 for zeroed sections.
+    pub checksum: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Header {
     version: u16,
+
+    /// The offset into the bytecode execution starts at
     pub entry_point: usize,
+
+    /// Every section in the file, in the order their contents appear
+    pub sections: Vec<SectionHeader>,
 }
 
 impl Default for Header {
     fn default() -> Self {
         Header {
             version: VERSION,
-            entry_point: 0
+            entry_point: 0,
+            sections: vec![],
         }
     }
 }
 
 impl Header {
+    /// Decodes the header at the start of `bytes`, which holds the whole file. Every section must lie
+    /// within `bytes` and match its checksum.
     pub fn decode(bytes: Vec<u8>) -> Result<Header, DecodeHeaderError> {
         let mut i: usize = 0;
 
+        let error = |kind| Err(DecodeHeaderError { kind });
+
+        // Versions are checked before the length, since version 0 headers are longer
+        if bytes.len() < MAIGC_NUMBER.len() + 2 {
+            return error(HeaderErrorKind::Truncated);
+        }
+
         // Verify the magic number
         if bytes[0..MAIGC_NUMBER.len()] != MAIGC_NUMBER {
-            return Err(DecodeHeaderError { kind: HeaderErrorKind::MagicNumber })
+            return error(HeaderErrorKind::MagicNumber);
         }
 
         i += MAIGC_NUMBER.len();
 
-        let mut header = Header::default();
+        let version = LittleEndian::read_u16(&bytes[i..i + 2]);
 
-        header.version = LittleEndian::read_u16(&bytes[i..i + 2]);
+        if version < VERSION {
+            return error(HeaderErrorKind::OutdatedVersion);
+        }
 
-        if header.version != VERSION {
-            return Err(DecodeHeaderError { kind: HeaderErrorKind::OutdatedVersion })
+        if version > VERSION {
+            return error(HeaderErrorKind::UnsupportedVersion(version));
         }
 
         i += 2;
 
-        header.entry_point = LittleEndian::read_u32(&bytes[i..i + 4]) as usize;
+        if bytes.len() < HEADER_LENGTH {
+            return error(HeaderErrorKind::Truncated);
+        }
+
+        let entry_point = LittleEndian::read_u32(&bytes[i..i + 4]) as usize;
 
-        Ok(header)
+        i += 4;
+
+        let count = LittleEndian::read_u16(&bytes[i..i + 2]) as usize;
+
+        if bytes.len() < HEADER_LENGTH + count * SECTION_ENTRY_LENGTH {
+            return error(HeaderErrorKind::Truncated);
+        }
+
+        let mut sections: Vec<SectionHeader> = vec![];
+
+        for entry in bytes[HEADER_LENGTH..].chunks(SECTION_ENTRY_LENGTH).take(count) {
+            let kind = match SectionKind::try_from(entry[0]) {
+                Ok(kind) => kind,
+                Err(kind) => return error(kind),
+            };
+
+            if sections.iter().any(|section| section.kind == kind) {
+                return error(HeaderErrorKind::InvalidSection(kind));
+            }
+
+            let flags = entry[1];
+            let offset = LittleEndian::read_u32(&entry[4..8]) as usize;
+            let length = LittleEndian::read_u32(&entry[8..12]) as usize;
+            let checksum = LittleEndian::read_u32(&entry[12..16]);
+
+            if flags & SECTION_ZEROED == 0 {
+                let contents = match offset.checked_add(length) {
+                    Some(end) if end <= bytes.len() => &bytes[offset..end],
+                    _ => return error(HeaderErrorKind::Truncated),
+                };
+
+                if crc32(contents) != checksum {
+                    return error(HeaderErrorKind::Corrupted(kind));
+                }
+            }
+
+            sections.push(SectionHeader { kind, flags, offset, length, checksum });
+        }
+
+        Ok(Header { version, entry_point, sections })
+    }
+
+    /// The section of `kind`, if the file has one.
+    pub fn section(&self, kind: SectionKind) -> Option<&SectionHeader> {
+        self.sections.iter().find(|section| section.kind == kind)
+    }
+
+    /// The number of bytes `bytes` returns: the fixed part followed by the section table.
+    pub fn encoded_len(&self) -> usize {
+        HEADER_LENGTH + self.sections.len() * SECTION_ENTRY_LENGTH
     }
 
-    pub fn bytes(&mut self) -> Vec<u8> {
+    /// Encodes the header, failing if the entry point or a section does not fit in the section table.
+    pub fn bytes(&self) -> Result<Vec<u8>, EncodeError> {
         let mut header = vec![];
     
         header.append(&mut MAIGC_NUMBER[..].to_vec());
@@ -69,16 +343,29 @@ impl Header {
         header.append(&mut buf);
 
         buf = vec![0; 4];
-        LittleEndian::write_u32(&mut buf, self.entry_point as u32);
+        LittleEndian::write_u32(&mut buf, narrow(self.entry_point, None)?);
         header.append(&mut buf);
 
-        println!("{}", buf.len());
+        buf = vec![0; 2];
+        LittleEndian::write_u16(&mut buf, narrow(self.sections.len(), None)?);
+        header.append(&mut buf);
+
+        // Reserved
+        header.resize(HEADER_LENGTH, 0);
+
+        for section in &self.sections {
+            let mut entry = vec![0; SECTION_ENTRY_LENGTH];
+
+            entry[0] = section.kind as u8;
+            entry[1] = section.flags;
+            LittleEndian::write_u32(&mut entry[4..8], narrow(section.offset, Some(section.kind))?);
+            LittleEndian::write_u32(&mut entry[8..12], narrow(section.length, Some(section.kind))?);
+            LittleEndian::write_u32(&mut entry[12..16], section.checksum);
 
-        while header.len() < HEADER_LENGTH {
-            header.push(0 as u8);
+            header.append(&mut entry);
         }
     
-        header
+        Ok(header)
     }
 }
 
@@ -98,43 +385,123 @@ mod tests {
         let mut header = Header::default();
 
         header.entry_point = 69;
+        header.sections = vec![
+            SectionHeader { kind: SectionKind::Bytecode, flags: SECTION_LOADED, offset: 48, length: 4, checksum: crc32(&[1, 2, 3, 4]) },
+            SectionHeader { kind: SectionKind::Bss, flags: SECTION_LOADED | SECTION_ZEROED, offset: 0, length: 100, checksum: 0 },
+        ];
 
-        let bytes: Vec<u8> = header.bytes().to_vec();
+        let mut bytes: Vec<u8> = header.bytes().unwrap();
         
-        assert_eq!(bytes.len(), 64);
+        assert_eq!(bytes.len(), 48);
+        assert_eq!(bytes.len(), header.encoded_len());
+
+        bytes.extend_from_slice(&[1, 2, 3, 4]);
 
         match Header::decode(bytes) {
-            Ok(header) => {
-                assert_eq!(header.version, VERSION);
+            Ok(decoded) => {
+                assert_eq!(decoded.version, VERSION);
 
-                assert_eq!(header.entry_point, 69);
+                assert_eq!(decoded.entry_point, 69);
+                assert_eq!(decoded.sections, header.sections);
+                assert_eq!(decoded.section(SectionKind::Bss).map(|section| section.length), Some(100));
+                assert_eq!(decoded.section(SectionKind::Debug), None);
             },
-            Err(e) => panic!(e)
+            Err(e) => panic!("{:?}", e)
         }
     }
 
     #[test]
     fn fail_on_invalid_header_magic_number() {
-        let mut bytes: Vec<u8> = Header::default().bytes().to_vec();
+        let mut bytes: Vec<u8> = Header::default().bytes().unwrap();
 
         bytes[0] = 0;
 
-        match Header::decode(bytes) {
-            Ok(_) => panic!("Magic number not validated correctly!"),
-            Err(_) => { }
+        if Header::decode(bytes).is_ok() {
+            panic!("Magic number not validated correctly!");
         }
     }
 
+    #[test]
+    fn fail_on_truncated_header() {
+        let mut bytes: Vec<u8> = Header::default().bytes().unwrap();
+
+        bytes.truncate(MAIGC_NUMBER.len() + 1);
+
+        assert_eq!(Header::decode(bytes).unwrap_err().kind(), &HeaderErrorKind::Truncated);
+        assert_eq!(Header::decode(vec![]).unwrap_err().kind(), &HeaderErrorKind::Truncated);
+    }
+
+    #[test]
+    fn fail_on_invalid_section_table() {
+        let mut header = Header::default();
+
+        header.sections = vec![SectionHeader { kind: SectionKind::Bytecode, flags: 0, offset: 0, length: 0, checksum: 0 }];
+
+        let mut bytes = header.bytes().unwrap();
+
+        assert_eq!(Header::decode(bytes[..HEADER_LENGTH + 4].to_vec()).unwrap_err().kind(), &HeaderErrorKind::Truncated);
+
+        bytes.extend_from_within(HEADER_LENGTH..);
+        bytes[MAIGC_NUMBER.len() + 6] = 2;
+
+        assert_eq!(Header::decode(bytes.clone()).unwrap_err().kind(), &HeaderErrorKind::InvalidSection(SectionKind::Bytecode));
+
+        bytes[HEADER_LENGTH] = 9;
+
+        assert_eq!(Header::decode(bytes).unwrap_err().kind(), &HeaderErrorKind::UnknownSection(9));
+    }
+
+    #[test]
+    fn fail_on_corrupted_section() {
+        let mut header = Header::default();
+
+        header.sections = vec![
+            SectionHeader { kind: SectionKind::ReadOnly, flags: SECTION_LOADED, offset: 48, length: 2, checksum: crc32(b"ab") },
+            SectionHeader { kind: SectionKind::Bytecode, flags: SECTION_LOADED, offset: 50, length: 2, checksum: crc32(b"cd") },
+        ];
+
+        let mut bytes = header.bytes().unwrap();
+
+        bytes.extend_from_slice(b"ab");
+        bytes.extend_from_slice(b"cd");
+
+        assert!(Header::decode(bytes.clone()).is_ok());
+
+        let last = bytes.len() - 1;
+
+        bytes[last] ^= 0x10;
+
+        let error = Header::decode(bytes.clone()).unwrap_err();
+
+        assert_eq!(error.kind(), &HeaderErrorKind::Corrupted(SectionKind::Bytecode));
+        assert_eq!(error.to_string(), "bytecode section does not match its checksum");
+
+        // A section cut short is truncated rather than corrupted
+        assert_eq!(Header::decode(bytes[..last].to_vec()).unwrap_err().kind(), &HeaderErrorKind::Truncated);
+    }
+
+    #[test]
+    fn fail_on_outdated_header() {
+        // A version 0 header: the magic number, the version and the entry point, zero padded
+        let mut bytes = MAIGC_NUMBER.to_vec();
+
+        bytes.resize(64, 0);
+
+        let error = Header::decode(bytes).unwrap_err();
+
+        assert_eq!(error.kind(), &HeaderErrorKind::OutdatedVersion);
+        assert!(error.kind().hint().is_some());
+    }
+
     #[test]
     fn fail_on_invalid_header_version() {
-        let mut bytes: Vec<u8> = Header::default().bytes().to_vec();
+        let mut bytes: Vec<u8> = Header::default().bytes().unwrap();
 
         bytes[MAIGC_NUMBER.len()] += 1;
         bytes[MAIGC_NUMBER.len() + 1] += 1;
 
-        match Header::decode(bytes) {
-            Ok(_) => panic!("Version not validated correctly!"),
-            Err(_) => { }
+        if Header::decode(bytes).is_ok() {
+            panic!("Version not validated correctly!");
         }
     }
 }
\ No newline at end of file
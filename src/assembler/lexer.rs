@@ -0,0 +1,464 @@
+use std::fmt;
+
+use crate::assembler::error::{AssemblerError, AssemblerErrorKind, Span};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    /// A mnemonic such as `ADD`, or the name of a label
+    Identifier(String),
+
+    /// A register such as `$5`, either integer or float depending on the instruction
+    Register(u32),
+
+    Integer(i64),
+    Float(f64),
+
+    /// A string literal with its escapes already replaced
+    String(String),
+
+    /// An assembler directive such as `.data`, without the leading dot
+    Directive(String),
+
+    /// Separates arguments, which may also be separated by whitespace alone
+    Comma,
+
+    /// Follows the name of a label where it is defined
+    Colon,
+
+    /// An arithmetic operator in a constant expression
+    Operator(Operator),
+
+    OpenParen,
+    CloseParen,
+
+    /// The end of a line, which also ends the current statement
+    Newline,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+
+    /// Subtracts, or negates when there is nothing on its left
+    Subtract,
+
+    Multiply,
+    Divide,
+    Remainder,
+    ShiftLeft,
+    ShiftRight,
+    And,
+    Or,
+    Xor,
+
+    /// Inverts every bit of what is on its right
+    Not,
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+            Operator::Remainder => "%",
+            Operator::ShiftLeft => "<<",
+            Operator::ShiftRight => ">>",
+            Operator::And => "&",
+            Operator::Or => "|",
+            Operator::Xor => "^",
+            Operator::Not => "~",
+        };
+
+        write!(f, "{}", symbol)
+    }
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenKind::Identifier(name) => write!(f, "`{}`", name),
+            TokenKind::Register(register) => write!(f, "`${}`", register),
+            TokenKind::Integer(value) => write!(f, "`{}`", value),
+            TokenKind::Float(value) => write!(f, "`{}`", value),
+            TokenKind::String(value) => write!(f, "{:?}", value),
+            TokenKind::Directive(name) => write!(f, "`.{}`", name),
+            TokenKind::Comma => write!(f, "`,`"),
+            TokenKind::Colon => write!(f, "`:`"),
+            TokenKind::Operator(operator) => write!(f, "`{}`", operator),
+            TokenKind::OpenParen => write!(f, "`(`"),
+            TokenKind::CloseParen => write!(f, "`)`"),
+            TokenKind::Newline => write!(f, "end of line"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// Splits source text into tokens. Comments start with `;` and run to the end of the line. Integers
+/// are written in decimal, in hex as `0x1f`, in binary as `0b101` or as a character such as `'a'`,
+/// and are never negative: a leading `-` is an operator, whatever the whitespace around it, so
+/// `X -1` is `X - 1`. Lines that fail to tokenize are left out entirely, so every error in the
+/// source is reported at once. Spans point into `file`, the index of the source among those read
+/// by the assembler.
+pub fn tokenize(source: &str, file: usize) -> Result<Vec<Token>, Vec<AssemblerError>> {
+    let mut tokens = vec![];
+    let mut errors = vec![];
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let start = tokens.len();
+
+        let chars: Vec<char> = text.chars().collect();
+
+        if let Err(error) = tokenize_line(&chars, file, line, &mut tokens) {
+            tokens.truncate(start);
+            errors.push(error);
+        }
+
+        tokens.push(Token { kind: TokenKind::Newline, span: Span { file, line, column: chars.len() + 1, len: 1 } });
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(tokens)
+}
+
+fn tokenize_line(chars: &[char], file: usize, line: usize, tokens: &mut Vec<Token>) -> Result<(), AssemblerError> {
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c == ';' {
+            break;
+        }
+
+        if c.is_whitespace() {
+            i += 1;
+
+            continue;
+        }
+
+        let start = i;
+
+        // Covers the characters from the start of the token up to `end`
+        let span = |end: usize| Span { file, line, column: start + 1, len: end - start };
+
+        let kind = if c.is_ascii_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+
+            TokenKind::Identifier(chars[start..i].iter().collect())
+        } else if c == '$' {
+            i += 1;
+
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+
+            let digits: String = chars[start + 1..i].iter().collect();
+
+            if digits.is_empty() {
+                return Err(AssemblerError::new(AssemblerErrorKind::UnexpectedCharacter('$'), span(i)));
+            }
+
+            match digits.parse::<u32>() {
+                Ok(register) => TokenKind::Register(register),
+                Err(_) => return Err(AssemblerError::new(AssemblerErrorKind::InvalidInteger(digits), span(i))),
+            }
+        } else if c == '0' && matches!(chars.get(i + 1), Some('x') | Some('X') | Some('b') | Some('B')) {
+            let radix = if chars[i + 1].eq_ignore_ascii_case(&'x') { 16 } else { 2 };
+
+            i += 2;
+
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+
+            let digits: String = chars[start + 2..i].iter().collect();
+
+            // Values up to u64::MAX are allowed so any 64-bit pattern can be written
+            match u64::from_str_radix(&digits, radix) {
+                Ok(value) => TokenKind::Integer(value as i64),
+                Err(_) => return Err(AssemblerError::new(AssemblerErrorKind::InvalidInteger(chars[start..i].iter().collect()), span(i))),
+            }
+        } else if c.is_ascii_digit() {
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+
+            let is_float = i + 1 < chars.len() && chars[i] == '.' && chars[i + 1].is_ascii_digit();
+
+            if is_float {
+                i += 1;
+
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+
+            let digits: String = chars[start..i].iter().collect();
+
+            match (is_float, digits.parse::<u64>(), digits.parse::<f64>()) {
+                (false, Ok(value), _) => TokenKind::Integer(value as i64),
+                (true, _, Ok(value)) => TokenKind::Float(value),
+                _ => return Err(AssemblerError::new(AssemblerErrorKind::InvalidInteger(digits), span(i))),
+            }
+        } else if c == '\'' {
+            i += 1;
+
+            let value = match chars.get(i) {
+                Some('\\') => {
+                    let value = escape(chars, i, file, line)?;
+
+                    i += 2;
+
+                    value
+                },
+                Some(&c) if c != '\'' => {
+                    i += 1;
+
+                    c
+                },
+                _ => return Err(AssemblerError::new(AssemblerErrorKind::InvalidCharacterLiteral, span(i))),
+            };
+
+            if chars.get(i) != Some(&'\'') {
+                return Err(AssemblerError::new(AssemblerErrorKind::InvalidCharacterLiteral, span(i)));
+            }
+
+            i += 1;
+
+            TokenKind::Integer(value as i64)
+        } else if c == '.' {
+            i += 1;
+
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+
+            if i == start + 1 {
+                return Err(AssemblerError::new(AssemblerErrorKind::UnexpectedCharacter('.'), span(i)));
+            }
+
+            TokenKind::Directive(chars[start + 1..i].iter().collect())
+        } else if c == '"' {
+            let mut value = String::new();
+
+            i += 1;
+
+            loop {
+                match chars.get(i) {
+                    None => return Err(AssemblerError::new(AssemblerErrorKind::UnterminatedString, span(i))),
+                    Some('"') => break,
+                    Some('\\') if i + 1 == chars.len() => {
+                        return Err(AssemblerError::new(AssemblerErrorKind::UnterminatedString, span(i + 1)));
+                    },
+                    Some('\\') => {
+                        value.push(escape(chars, i, file, line)?);
+
+                        i += 2;
+                    },
+                    Some(&c) => {
+                        value.push(c);
+
+                        i += 1;
+                    },
+                }
+            }
+
+            // Skip the closing quote
+            i += 1;
+
+            TokenKind::String(value)
+        } else if c == ':' {
+            i += 1;
+
+            TokenKind::Colon
+        } else if c == ',' {
+            i += 1;
+
+            TokenKind::Comma
+        } else if c == '(' || c == ')' {
+            i += 1;
+
+            if c == '(' { TokenKind::OpenParen } else { TokenKind::CloseParen }
+        } else if let Some(operator) = operator(chars, i) {
+            i += operator.to_string().len();
+
+            TokenKind::Operator(operator)
+        } else {
+            return Err(AssemblerError::new(AssemblerErrorKind::UnexpectedCharacter(c), span(i + 1)));
+        };
+
+        tokens.push(Token { kind, span: span(i) });
+    }
+
+    Ok(())
+}
+
+// The character escaped by the backslash at `i`, as in `\n`.
+fn escape(chars: &[char], i: usize, file: usize, line: usize) -> Result<char, AssemblerError> {
+    match chars.get(i + 1) {
+        Some('n') => Ok('\n'),
+        Some('t') => Ok('\t'),
+        Some('r') => Ok('\r'),
+        Some('0') => Ok('\0'),
+        Some('\\') => Ok('\\'),
+        Some('"') => Ok('"'),
+        Some('\'') => Ok('\''),
+        Some(&escape) => Err(AssemblerError::new(AssemblerErrorKind::InvalidEscape(escape), Span { file, line, column: i + 1, len: 2 })),
+        None => Err(AssemblerError::new(AssemblerErrorKind::InvalidCharacterLiteral, Span { file, line, column: i + 1, len: 1 })),
+    }
+}
+
+// The operator starting at `i`, if there is one.
+fn operator(chars: &[char], i: usize) -> Option<Operator> {
+    let operator = match (chars[i], chars.get(i + 1)) {
+        ('<', Some('<')) => Operator::ShiftLeft,
+        ('>', Some('>')) => Operator::ShiftRight,
+        ('+', _) => Operator::Add,
+        ('-', _) => Operator::Subtract,
+        ('*', _) => Operator::Multiply,
+        ('/', _) => Operator::Divide,
+        ('%', _) => Operator::Remainder,
+        ('&', _) => Operator::And,
+        ('|', _) => Operator::Or,
+        ('^', _) => Operator::Xor,
+        ('~', _) => Operator::Not,
+        _ => return None,
+    };
+
+    Some(operator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        tokenize(source, 0).unwrap().into_iter().map(|token| token.kind).collect()
+    }
+
+    #[test]
+    fn tokenize_instructions() {
+        assert_eq!(kinds("loop: JMP $0"), vec![
+            TokenKind::Identifier("loop".to_string()),
+            TokenKind::Colon,
+            TokenKind::Identifier("JMP".to_string()),
+            TokenKind::Register(0),
+            TokenKind::Newline,
+        ]);
+
+        assert_eq!(kinds("ADD $5 $3 $4 ; $5 = $3 + $4\nset $0 -12"), vec![
+            TokenKind::Identifier("ADD".to_string()),
+            TokenKind::Register(5),
+            TokenKind::Register(3),
+            TokenKind::Register(4),
+            TokenKind::Newline,
+            TokenKind::Identifier("set".to_string()),
+            TokenKind::Register(0),
+            TokenKind::Operator(Operator::Subtract),
+            TokenKind::Integer(12),
+            TokenKind::Newline,
+        ]);
+
+        let tokens = tokenize("\n  JMP $16", 0).unwrap();
+
+        assert_eq!(tokens[1].span, Span { file: 0, line: 2, column: 3, len: 3 });
+        assert_eq!(tokens[2].span, Span { file: 0, line: 2, column: 7, len: 3 });
+    }
+
+    #[test]
+    fn tokenize_directives() {
+        assert_eq!(kinds(".data\nmessage: .asciiz \"a; \\\"b\\\"\\n\"\n.f64 1.5, -2"), vec![
+            TokenKind::Directive("data".to_string()),
+            TokenKind::Newline,
+            TokenKind::Identifier("message".to_string()),
+            TokenKind::Colon,
+            TokenKind::Directive("asciiz".to_string()),
+            TokenKind::String("a; \"b\"\n".to_string()),
+            TokenKind::Newline,
+            TokenKind::Directive("f64".to_string()),
+            TokenKind::Float(1.5),
+            TokenKind::Comma,
+            TokenKind::Operator(Operator::Subtract),
+            TokenKind::Integer(2),
+            TokenKind::Newline,
+        ]);
+    }
+
+    #[test]
+    fn tokenize_expressions() {
+        assert_eq!(kinds("0x1F 0b101 0XfF 'a' '\\n' '\\''"), vec![
+            TokenKind::Integer(31),
+            TokenKind::Integer(5),
+            TokenKind::Integer(255),
+            TokenKind::Integer(97),
+            TokenKind::Integer(10),
+            TokenKind::Integer(39),
+            TokenKind::Newline,
+        ]);
+
+        assert_eq!(kinds("0xffffffffffffffff"), vec![TokenKind::Integer(-1), TokenKind::Newline]);
+
+        assert_eq!(kinds("(end-start)<<2 >> ~x|1&2^3*4/5%6+7"), vec![
+            TokenKind::OpenParen,
+            TokenKind::Identifier("end".to_string()),
+            TokenKind::Operator(Operator::Subtract),
+            TokenKind::Identifier("start".to_string()),
+            TokenKind::CloseParen,
+            TokenKind::Operator(Operator::ShiftLeft),
+            TokenKind::Integer(2),
+            TokenKind::Operator(Operator::ShiftRight),
+            TokenKind::Operator(Operator::Not),
+            TokenKind::Identifier("x".to_string()),
+            TokenKind::Operator(Operator::Or),
+            TokenKind::Integer(1),
+            TokenKind::Operator(Operator::And),
+            TokenKind::Integer(2),
+            TokenKind::Operator(Operator::Xor),
+            TokenKind::Integer(3),
+            TokenKind::Operator(Operator::Multiply),
+            TokenKind::Integer(4),
+            TokenKind::Operator(Operator::Divide),
+            TokenKind::Integer(5),
+            TokenKind::Operator(Operator::Remainder),
+            TokenKind::Integer(6),
+            TokenKind::Operator(Operator::Add),
+            TokenKind::Integer(7),
+            TokenKind::Newline,
+        ]);
+    }
+
+    #[test]
+    fn fail_on_invalid_tokens() {
+        let errors = |source| -> Vec<(Span, AssemblerErrorKind)> {
+            tokenize(source, 0).unwrap_err().into_iter().map(|error| (error.span, error.kind)).collect()
+        };
+
+        assert_eq!(errors(".string \"abc"), vec![(Span { file: 0, line: 1, column: 9, len: 4 }, AssemblerErrorKind::UnterminatedString)]);
+        assert_eq!(errors(".string \"a\\q\""), vec![(Span { file: 0, line: 1, column: 11, len: 2 }, AssemblerErrorKind::InvalidEscape('q'))]);
+        assert_eq!(errors("SET $ 1"), vec![(Span { file: 0, line: 1, column: 5, len: 1 }, AssemblerErrorKind::UnexpectedCharacter('$'))]);
+
+        assert_eq!(errors("SET $0 0x"), vec![(Span { file: 0, line: 1, column: 8, len: 2 }, AssemblerErrorKind::InvalidInteger("0x".to_string()))]);
+        assert_eq!(errors("SET $0 0b12"), vec![(Span { file: 0, line: 1, column: 8, len: 4 }, AssemblerErrorKind::InvalidInteger("0b12".to_string()))]);
+        assert_eq!(errors("SET $0 'ab'"), vec![(Span { file: 0, line: 1, column: 8, len: 2 }, AssemblerErrorKind::InvalidCharacterLiteral)]);
+        assert_eq!(errors("SET $0 ''"), vec![(Span { file: 0, line: 1, column: 8, len: 1 }, AssemblerErrorKind::InvalidCharacterLiteral)]);
+
+        // Every line with an error is reported
+        assert_eq!(errors("SET $0 @\nSET $0 1\nSET $0 99999999999999999999"), vec![
+            (Span { file: 0, line: 1, column: 8, len: 1 }, AssemblerErrorKind::UnexpectedCharacter('@')),
+            (Span { file: 0, line: 3, column: 8, len: 20 }, AssemblerErrorKind::InvalidInteger("99999999999999999999".to_string())),
+        ]);
+    }
+}
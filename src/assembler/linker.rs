@@ -0,0 +1,457 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::assembler::debug::{DebugInfo, LineEntry};
+use crate::assembler::program::Program;
+use crate::assembler::section::{align_up, Section, SECTION_ALIGNMENT};
+use crate::vm::call::Export;
+
+/// Who can refer to a symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    /// Only the object that defines it, with no `.global` or `.extern`
+    Local,
+
+    /// Every object, through `.global`
+    Exported,
+
+    /// Defined by another object and declared with `.extern`
+    Imported,
+}
+
+/// A label in an object, which relocations refer to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+
+    pub binding: Binding,
+
+    /// The section the label is in. Unused for imported symbols.
+    pub section: Section,
+
+    /// The byte offset into the bytecode for `.text`, and the heap address for everything else.
+    /// Always 0 for imported symbols.
+    pub address: usize,
+}
+
+/// A place holding the address of a symbol, which has to be patched once the object has been moved
+/// or the symbol has been resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    /// `Text` for a place in the bytecode, any other section for a place in the read only data
+    pub section: Section,
+
+    /// The byte offset into the bytecode or the heap address of the place
+    pub offset: usize,
+
+    /// The number of bytes at `offset` that hold the value
+    pub size: usize,
+
+    /// The index of the symbol in the object's symbol table
+    pub symbol: usize,
+
+    /// Added to the address of the symbol, as in `buffer + 4`
+    pub addend: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LinkErrorKind {
+    /// An imported symbol, named here, is not exported by any object
+    UndefinedSymbol(String),
+
+    /// A symbol is exported by more than one object
+    DuplicateSymbol { name: String, first_object: usize },
+
+    /// The address of a symbol plus its addend does not fit in the place it is written to
+    OutOfRange { name: String, value: i64, size: usize },
+
+    /// None of several objects chooses where execution starts with `.entry` or `main`. Reported
+    /// against the first object.
+    NoEntryPoint,
+
+    /// More than one object chooses where execution starts
+    DuplicateEntryPoint { first_object: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkError {
+    pub kind: LinkErrorKind,
+
+    /// The index of the object the error was found in
+    pub object: usize,
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            LinkErrorKind::UndefinedSymbol(name) => write!(f, "symbol `{}` is imported but never exported", name),
+            LinkErrorKind::DuplicateSymbol { name, .. } => write!(f, "symbol `{}` is exported more than once", name),
+            LinkErrorKind::OutOfRange { name, value, size } => write!(f, "address {} of `{}` does not fit in {} byte(s)", value, name, size),
+            LinkErrorKind::NoEntryPoint => write!(f, "no object has an entry point, add `main` or `.entry`"),
+            LinkErrorKind::DuplicateEntryPoint { .. } => write!(f, "entry point is chosen by more than one object"),
+        }
+    }
+}
+
+// Where an object ends up in the linked program.
+#[derive(Debug, Clone, Copy)]
+struct Placement {
+    // Added to byte offsets into the bytecode
+    text: usize,
+
+    // Added to heap addresses of `.rodata` and `.data`
+    read_only: usize,
+
+    // Added to heap addresses of `.bss`
+    bss: usize,
+}
+
+impl Placement {
+    fn address(&self, section: Section, address: usize) -> usize {
+        match section {
+            Section::Text => address + self.text,
+            Section::ReadOnly | Section::Data => address + self.read_only,
+            Section::Bss => address + self.bss,
+        }
+    }
+}
+
+/// Links objects into a single program. The bytecode and the read only data of every object are
+/// placed one after the other, followed by the `.bss` of every object, each at a multiple of the
+/// object's alignment. Imported symbols are resolved to the object exporting them, and every
+/// relocation is patched with the address the symbol ended up at.
+///
+/// Execution starts at the entry point of the one object choosing it with `.entry` or `main`, which
+/// a lone object does not need. The linked program keeps the exported symbols and the functions
+/// every object exports to the host, and has no relocations left. It has debug info if any object
+/// does.
+pub fn link(objects: &[Program]) -> Result<Program, Vec<LinkError>> {
+    let mut program = Program::default();
+    let mut placements = vec![];
+
+    for object in objects {
+        let alignment = object.alignment.max(SECTION_ALIGNMENT);
+
+        let text = align_up(program.bytecode.len(), alignment);
+        let read_only = align_up(program.read_only.len(), alignment);
+
+        program.bytecode.resize(text, 0);
+        program.bytecode.extend_from_slice(&object.bytecode);
+
+        program.read_only.resize(read_only, 0);
+        program.read_only.extend_from_slice(&object.read_only);
+
+        placements.push(Placement { text, read_only, bss: 0 });
+    }
+
+    // `.bss` starts right after the read only data within an object, and has to stay at the same
+    // position relative to a multiple of its alignment
+    let mut end = program.read_only.len();
+
+    for (object, placement) in objects.iter().zip(&mut placements) {
+        if object.bss_size == 0 {
+            continue;
+        }
+
+        let alignment = object.alignment.max(SECTION_ALIGNMENT) as i64;
+        let start = end + (object.read_only.len() as i64 - end as i64).rem_euclid(alignment) as usize;
+
+        placement.bss = start - object.read_only.len();
+
+        end = start + object.bss_size;
+    }
+
+    program.bss_size = end - program.read_only.len();
+
+    let mut errors = vec![];
+
+    // The object execution starts in
+    let mut entry = None;
+
+    for (i, _) in objects.iter().enumerate().filter(|(_, object)| object.has_entry) {
+        match entry {
+            Some(first_object) => errors.push(LinkError { kind: LinkErrorKind::DuplicateEntryPoint { first_object }, object: i }),
+            None => entry = Some(i),
+        }
+    }
+
+    if entry.is_none() && objects.len() > 1 {
+        errors.push(LinkError { kind: LinkErrorKind::NoEntryPoint, object: 0 });
+    }
+
+    // Where every exported symbol ended up, and the object exporting it
+    let mut exports: HashMap<&str, (usize, usize)> = HashMap::new();
+
+    for (i, (object, placement)) in objects.iter().zip(&placements).enumerate() {
+        for symbol in object.symbols.iter().filter(|symbol| symbol.binding == Binding::Exported) {
+            if let Some(&(_, first_object)) = exports.get(symbol.name.as_str()) {
+                errors.push(LinkError { kind: LinkErrorKind::DuplicateSymbol { name: symbol.name.clone(), first_object }, object: i });
+
+                continue;
+            }
+
+            let address = placement.address(symbol.section, symbol.address);
+
+            exports.insert(&symbol.name, (address, i));
+
+            program.symbols.push(Symbol { address, ..symbol.clone() });
+        }
+    }
+
+    // The object exporting every function to the host
+    let mut functions: HashMap<&str, usize> = HashMap::new();
+
+    for (i, (object, placement)) in objects.iter().zip(&placements).enumerate() {
+        for export in &object.exports {
+            if let Some(&first_object) = functions.get(export.name.as_str()) {
+                errors.push(LinkError { kind: LinkErrorKind::DuplicateSymbol { name: export.name.clone(), first_object }, object: i });
+
+                continue;
+            }
+
+            functions.insert(&export.name, i);
+
+            program.exports.push(Export { offset: export.offset + placement.text, ..export.clone() });
+        }
+    }
+
+    for (i, (object, placement)) in objects.iter().zip(&placements).enumerate() {
+        let mut undefined = vec![];
+
+        for relocation in &object.relocations {
+            let symbol = &object.symbols[relocation.symbol];
+
+            let address = match symbol.binding {
+                Binding::Imported => match exports.get(symbol.name.as_str()) {
+                    Some(&(address, _)) => address,
+                    None => {
+                        // Reported once, however often it is used
+                        if !undefined.contains(&symbol.name) {
+                            undefined.push(symbol.name.clone());
+                        }
+
+                        continue;
+                    },
+                },
+                _ => placement.address(symbol.section, symbol.address),
+            };
+
+            let value = address as i64 + relocation.addend;
+            let bits = relocation.size as u32 * 8;
+
+            if value < 0 || (bits < 64 && value >= 1 << bits) {
+                errors.push(LinkError { kind: LinkErrorKind::OutOfRange { name: symbol.name.clone(), value, size: relocation.size }, object: i });
+
+                continue;
+            }
+
+            let (bytes, offset) = match relocation.section {
+                Section::Text => (&mut program.bytecode, relocation.offset + placement.text),
+                _ => (&mut program.read_only, relocation.offset + placement.read_only),
+            };
+
+            bytes[offset..offset + relocation.size].copy_from_slice(&value.to_le_bytes()[..relocation.size]);
+        }
+
+        errors.extend(undefined.into_iter().map(|name| LinkError { kind: LinkErrorKind::UndefinedSymbol(name), object: i }));
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    if let Some(i) = entry.or(if objects.len() == 1 { Some(0) } else { None }) {
+        program.header.entry_point = objects[i].header.entry_point + placements[i].text;
+        program.has_entry = objects[i].has_entry;
+    }
+
+    program.alignment = objects.iter().map(|object| object.alignment).max().unwrap_or(0);
+
+    if objects.iter().any(|object| object.debug_info.is_some()) {
+        program.debug_info = Some(link_debug_info(objects, &placements));
+    }
+
+    Ok(program)
+}
+
+// Merges the debug info of every object, moving offsets along with the bytecode. Objects are placed
+// in order, so lines and labels stay sorted.
+fn link_debug_info(objects: &[Program], placements: &[Placement]) -> DebugInfo {
+    let mut linked = DebugInfo::default();
+
+    for (object, placement) in objects.iter().zip(placements) {
+        let debug_info = match &object.debug_info {
+            Some(debug_info) => debug_info,
+            None => continue,
+        };
+
+        let files = linked.files.len();
+
+        linked.files.extend(debug_info.files.iter().cloned());
+
+        linked.lines.extend(debug_info.lines.iter().map(|entry| {
+            LineEntry { offset: entry.offset + placement.text, file: entry.file + files, ..*entry }
+        }));
+
+        linked.labels.extend(debug_info.labels.iter().map(|(name, offset)| (name.clone(), offset + placement.text)));
+    }
+
+    linked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::vm::VM;
+    use crate::vm::memory::Memory;
+
+    fn object(source: &str) -> Program {
+        let mut assembler = Assembler::default();
+
+        assembler.compile(source).unwrap();
+
+        assembler.result
+    }
+
+    #[test]
+    fn link_objects() {
+        let main = object("
+            .extern add_one, counter
+            .global result
+
+                HLT
+            main:
+                SET $1 counter
+                LOAD $0 $1
+                CALL add_one
+                STOR result $0
+                HLT
+
+            .data
+            result: .word 0
+        ");
+
+        let library = object("
+            .global add_one, counter
+
+            .rodata
+                .byte 1, 2, 3
+            .data
+            counter: .word 41
+            pointer: .word counter + 4
+            .bss
+                .space 4
+            .text
+                HLT
+            add_one:
+                INC $0
+                RET
+        ");
+
+        assert_eq!(main.relocations.len(), 3);
+        assert_eq!(main.symbols.iter().filter(|symbol| symbol.binding == Binding::Imported).count(), 2);
+
+        let program = link(&[main.clone(), library.clone()]).unwrap();
+
+        // The library follows the main object, aligned
+        assert_eq!(program.bytecode.len(), 16 + library.bytecode.len());
+        assert_eq!(program.read_only.len(), 8 + library.read_only.len());
+        assert_eq!(program.bss_size, 4);
+        assert_eq!(program.header.entry_point, 1);
+
+        let address = |name: &str| program.symbols.iter().find(|symbol| symbol.name == name).unwrap().address;
+
+        assert_eq!(address("add_one"), 17);
+        assert_eq!(address("counter"), 16);
+        assert_eq!(address("result"), 0);
+        assert_eq!(program.read_only[20..24], [20, 0, 0, 0]);
+
+        let mut test_vm = VM::default();
+
+        test_vm.heap = Memory::from(program.read_only.clone());
+        test_vm.heap.grow(program.bss_size);
+        test_vm.set_program(program.bytecode.clone());
+        test_vm.pc = program.header.entry_point;
+
+        test_vm.run().unwrap();
+
+        assert_eq!(test_vm.heap.read_u32(0), Ok(42));
+
+        // Objects can be linked in any order, and execution still starts at `main`
+        let program = link(&[library.clone(), main.clone()]).unwrap();
+
+        assert_eq!(program.header.entry_point, align_up(library.bytecode.len(), SECTION_ALIGNMENT) + 1);
+        assert_eq!(program.read_only[8..12], [41, 0, 0, 0]);
+        assert_eq!(program.debug_info, None);
+    }
+
+    #[test]
+    fn link_debug_info() {
+        let compile = |name: &str, source: &str| {
+            let mut assembler = Assembler::default();
+
+            assembler.emit_debug_info = true;
+            assembler.compile_source(name, source).unwrap();
+
+            assembler.result
+        };
+
+        let main = compile("main.asm", ".extern add_one\nmain:\n    CALL add_one\n    HLT");
+        let library = compile("lib.asm", ".global add_one\n.export add_one \"i32 -> i32\"\nadd_one:\n    INC $0\n    RET");
+
+        let program = link(&[main, library]).unwrap();
+
+        // Functions exported to the host move along with the bytecode
+        assert_eq!(program.exports.iter().map(|export| (export.name.as_str(), export.offset)).collect::<Vec<_>>(), vec![("add_one", 8)]);
+
+        let debug_info = program.debug_info.unwrap();
+
+        assert_eq!(debug_info.files, vec!["main.asm".to_string(), "lib.asm".to_string()]);
+        assert_eq!(debug_info.labels, vec![("main".to_string(), 0), ("add_one".to_string(), 8)]);
+        assert_eq!(debug_info.describe(10), "lib.asm:5:5 in `add_one`");
+    }
+
+    #[test]
+    fn fail_on_bad_symbols() {
+        let user = object(".extern value, missing\nmain: STOR value $0\nSET $0 missing");
+        let first = object(".global value\n.data\nvalue: .word 1");
+        let second = object(".global value\n.data\n.space 300\nvalue: .word 2");
+
+        let kinds = |objects: &[Program]| -> Vec<(usize, LinkErrorKind)> {
+            link(objects).unwrap_err().into_iter().map(|error| (error.object, error.kind)).collect()
+        };
+
+        assert_eq!(kinds(&[user.clone(), first.clone(), second.clone()]), vec![
+            (2, LinkErrorKind::DuplicateSymbol { name: "value".to_string(), first_object: 1 }),
+            (0, LinkErrorKind::UndefinedSymbol("missing".to_string())),
+        ]);
+
+        // Heap addresses in instructions only take a byte
+        assert_eq!(kinds(&[user, second]), vec![
+            (0, LinkErrorKind::OutOfRange { name: "value".to_string(), value: 300, size: 1 }),
+            (0, LinkErrorKind::UndefinedSymbol("missing".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn fail_on_bad_entry_points() {
+        let kinds = |objects: &[Program]| -> Vec<(usize, LinkErrorKind)> {
+            link(objects).unwrap_err().into_iter().map(|error| (error.object, error.kind)).collect()
+        };
+
+        let library = object("INC $0\nRET");
+
+        assert_eq!(kinds(&[library.clone(), library.clone()]), vec![(0, LinkErrorKind::NoEntryPoint)]);
+
+        // A lone object starts at the beginning without one
+        assert_eq!(link(std::slice::from_ref(&library)).unwrap().header.entry_point, 0);
+
+        let main = object("HLT\nmain: HLT");
+        let start = object(".entry start\nstart: HLT");
+
+        assert_eq!(kinds(&[main.clone(), library.clone(), start.clone()]), vec![(2, LinkErrorKind::DuplicateEntryPoint { first_object: 0 })]);
+        assert_eq!(link(&[library, start]).unwrap().header.entry_point, 8);
+    }
+}
@@ -1,17 +1,1238 @@
+pub mod checksum;
+pub mod debug;
+pub mod disassembler;
+pub mod error;
 pub mod header;
+pub mod lexer;
+pub mod linker;
+pub mod parser;
+pub mod preprocessor;
 pub mod program;
+pub mod section;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+use crate::assembler::debug::{DebugInfo, LineEntry};
+use crate::assembler::error::{AssemblerError, AssemblerErrorKind, Span};
+use crate::assembler::lexer::Operator;
+use crate::assembler::linker::{Binding, Relocation, Symbol};
+use crate::assembler::parser::{Argument, ArgumentKind, Statement, StatementKind};
+use crate::assembler::preprocessor::SourceFile;
+use crate::assembler::section::{Section, Sections};
+use crate::vm::call::{Export, Signature};
+use crate::vm::instructions::Opcode;
+use crate::vm::operand::OperandKind;
+
+/// Which pass over the source the assembler is making. The first pass lays out the program and
+/// records the address of every label, which the second pass then fills in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AssemblerPhase {
+    #[default]
+    First,
+    Second,
+}
+
+// How the value of an expression depends on the addresses of labels, which decides what happens to
+// it when the program is linked
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Dependency {
+    // Not at all
+    None,
+
+    // The value is the address of the label plus a number, and is patched through a relocation
+    Label(String),
+
+    // The value is the distance between labels in the same section, which linking does not change
+    Distance,
+
+    // In any other way, which linking cannot patch
+    Other,
+}
+
+// A symbol defined with `.equ`
+#[derive(Debug, Clone)]
+struct Constant {
+    value: i64,
+
+    dependency: Dependency,
+
+    // The last phase that reached the definition. During the second phase, a constant defined in
+    // the first is one whose `.equ` has not been reached yet.
+    phase: AssemblerPhase,
+
+    span: Span,
+}
 
 #[derive(Default)]
 pub struct Assembler {
-    pub result: program::Program
+    pub result: program::Program,
+
+    /// The address of every label defined by the last compiled source. Labels in `.text` resolve
+    /// to byte offsets into the bytecode, all others to heap addresses.
+    pub labels: HashMap<String, usize>,
+
+    /// Whether `result` gets the debug info mapping its bytecode back to the source
+    pub emit_debug_info: bool,
+
+    phase: AssemblerPhase,
+
+    // The section and offset into it of every label, along with where it was defined, collected
+    // during the first phase
+    symbols: HashMap<String, (Section, usize, Span)>,
+
+    constants: HashMap<String, Constant>,
+
+    // The label given to `.entry` and where, if any
+    entry: Option<(String, Span)>,
+
+    // Labels declared with `.extern` and `.global`, collected during the first phase
+    imports: HashMap<String, Span>,
+    exports: Vec<(String, Span)>,
+
+    // Functions declared with `.export` and where, collected during the second phase
+    functions: Vec<(String, Signature, Span)>,
+
+    // The index of every label and import in the symbol table of `result`
+    symbol_indices: HashMap<String, usize>,
+
+    // The relocations made during the second phase, with offsets relative to their section
+    relocations: Vec<Relocation>,
+
+    // The line every instruction was assembled from, recorded during the second phase
+    lines: Vec<LineEntry>,
+
+    // The source being compiled followed by every file it includes, which spans point into
+    files: Vec<SourceFile>,
 }
 
 impl Assembler {
-    pub fn compile(&mut self) {
+    // Assembles `source` into `result`. Code goes into the bytecode, and `.rodata` and `.data` into
+    // the read only data loaded onto the heap. Every error found is returned, ordered by position.
+    pub fn compile(&mut self, source: &str) -> Result<(), Vec<AssemblerError>> {
+        self.compile_source("<source>", source)
+    }
+
+    // Like `compile`, naming the file `source` was read from in errors.
+    pub fn compile_source(&mut self, name: &str, source: &str) -> Result<(), Vec<AssemblerError>> {
+        self.files = vec![SourceFile { name: name.to_string(), path: None, source: source.to_string() }];
+
+        self.assemble_files()
+    }
+
+    // Compiles the file at `path`, which `.include` paths inside it are relative to.
+    pub fn compile_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Vec<AssemblerError>> {
+        let path = path.as_ref();
+
+        let source = fs::read_to_string(path).map_err(|error| {
+            let kind = AssemblerErrorKind::IncludeFailed { path: path.display().to_string(), reason: error.kind() };
+
+            let mut error = AssemblerError::new(kind, Span::default());
+
+            error.file = path.display().to_string();
+
+            vec![error]
+        })?;
+
+        self.files = vec![SourceFile { name: path.display().to_string(), path: Some(path.to_path_buf()), source }];
+
+        self.assemble_files()
+    }
+
+    fn assemble_files(&mut self) -> Result<(), Vec<AssemblerError>> {
+        self.assemble_source().map_err(|mut errors| {
+            for error in &mut errors {
+                let file = &self.files[error.span.file];
+
+                error.file = file.name.clone();
+                error.source_line = file.source.lines().nth(error.span.line.wrapping_sub(1)).unwrap_or_default().to_string();
+            }
+
+            errors.sort_by_key(|error| (error.span.file, error.span.line, error.span.column));
+
+            // A macro used more than once repeats the errors in its body
+            errors.dedup();
+
+            errors
+        })
+    }
+
+    fn assemble_source(&mut self) -> Result<(), Vec<AssemblerError>> {
+        // Syntax errors are reported on their own, since whatever follows from them would be noise
+        let tokens = preprocessor::preprocess(&mut self.files)?;
+        let statements = parser::parse(&tokens)?;
+
+        self.labels.clear();
+        self.symbols.clear();
+        self.constants.clear();
+        self.entry = None;
+        self.imports.clear();
+        self.exports.clear();
+        self.functions.clear();
+        self.symbol_indices.clear();
+        self.relocations.clear();
+        self.lines.clear();
+
+        let mut errors = vec![];
+
+        self.phase = AssemblerPhase::First;
+
+        let sections = self.assemble(&statements, &mut errors);
+
+        // Only now that every section's size is known can labels be given an address
+        for (name, &(section, offset, _)) in &self.symbols {
+            self.labels.insert(name.clone(), sections.base(section) + offset);
+        }
+
+        let symbols = self.symbol_table();
+
+        self.phase = AssemblerPhase::Second;
+
+        let sections = self.assemble(&statements, &mut errors);
+
+        for (name, span) in &self.exports {
+            if !self.symbols.contains_key(name) {
+                errors.push(AssemblerError::new(AssemblerErrorKind::UndefinedLabel(name.clone()), *span));
+            }
+        }
+
+        for (name, span) in &self.imports {
+            if let Some(&(_, _, first)) = self.symbols.get(name) {
+                errors.push(AssemblerError::new(AssemblerErrorKind::DuplicateLabel { name: name.clone(), first_line: first.line }, *span));
+            }
+        }
+
+        // Execution starts at the label given to `.entry`, else at `main`, else at the beginning
+        let main = match self.symbols.get("main") {
+            Some(&(Section::Text, offset, _)) => Some(offset),
+            _ => None,
+        };
+
+        let entry_point = match &self.entry {
+            Some((name, span)) => match self.symbols.get(name) {
+                Some(&(Section::Text, offset, _)) => offset,
+                Some(_) => {
+                    errors.push(AssemblerError::new(AssemblerErrorKind::EntryOutsideText(name.clone()), *span));
+
+                    0
+                },
+                None => {
+                    errors.push(AssemblerError::new(AssemblerErrorKind::UndefinedLabel(name.clone()), *span));
+
+                    0
+                },
+            },
+            None => main.unwrap_or(0),
+        };
+
+        let mut functions = vec![];
+
+        for (name, signature, span) in &self.functions {
+            match self.symbols.get(name) {
+                Some(&(Section::Text, offset, _)) => functions.push(Export { name: name.clone(), offset, signature: signature.clone() }),
+                Some(_) => errors.push(AssemblerError::new(AssemblerErrorKind::ExportOutsideText(name.clone()), *span)),
+                None => errors.push(AssemblerError::new(AssemblerErrorKind::UndefinedLabel(name.clone()), *span)),
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        self.result.header.entry_point = entry_point;
+        self.result.has_entry = self.entry.is_some() || main.is_some();
+
+        self.result.bytecode = sections.text.clone();
+        self.result.read_only = sections.heap();
+        self.result.bss_size = sections.bss_size();
+        self.result.alignment = sections.alignment();
+        self.result.symbols = symbols;
+
+        self.result.relocations = self.relocations.drain(..).map(|relocation| {
+            Relocation { offset: sections.base(relocation.section) + relocation.offset, ..relocation }
+        }).collect();
+
+        self.result.exports = functions;
+
+        self.result.debug_info = if self.emit_debug_info { Some(self.debug_info()) } else { None };
+
+        Ok(())
+    }
+
+    // Collects the lines recorded during the second phase and every label in `.text`. Labels made
+    // up for macro expansions are left out, since they never appear in the source.
+    fn debug_info(&mut self) -> DebugInfo {
+        let mut labels: Vec<(String, usize)> = self.symbols.iter()
+            .filter(|(name, &(section, _, _))| section == Section::Text && !name.contains('@'))
+            .map(|(name, &(_, offset, _))| (name.clone(), offset))
+            .collect();
+
+        labels.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+
+        DebugInfo {
+            files: self.files.iter().map(|file| file.name.clone()).collect(),
+            lines: self.lines.drain(..).collect(),
+            labels,
+        }
+    }
+
+    // Builds the symbol table of the program out of every label and import found during the first
+    // phase, sorted by name so the output does not change from one run to the next.
+    fn symbol_table(&mut self) -> Vec<Symbol> {
+        let mut symbols: Vec<Symbol> = self.symbols.iter().map(|(name, &(section, _, _))| {
+            let binding = if self.exports.iter().any(|(export, _)| export == name) { Binding::Exported } else { Binding::Local };
+
+            Symbol { name: name.clone(), binding, section, address: self.labels[name] }
+        }).collect();
+
+        for name in self.imports.keys().filter(|name| !self.symbols.contains_key(*name)) {
+            symbols.push(Symbol { name: name.clone(), binding: Binding::Imported, section: Section::Text, address: 0 });
+        }
+
+        symbols.sort_by(|a, b| a.name.cmp(&b.name));
+
+        self.symbol_indices = symbols.iter().enumerate().map(|(i, symbol)| (symbol.name.clone(), i)).collect();
+
+        symbols
+    }
+
+    // Makes a pass over every statement. The first phase only reports duplicate labels, leaving
+    // everything else to the second phase so nothing is reported twice.
+    fn assemble(&mut self, statements: &[Statement], errors: &mut Vec<AssemblerError>) -> Sections {
+        let mut sections = Sections::default();
+        let mut section = Section::Text;
+
+        for statement in statements {
+            let result = match &statement.kind {
+                StatementKind::Label(name) if self.phase == AssemblerPhase::First => match self.symbols.get(name) {
+                    Some(&(_, _, first)) => {
+                        let kind = AssemblerErrorKind::DuplicateLabel { name: name.clone(), first_line: first.line };
+
+                        Err(AssemblerError::new(kind, statement.span))
+                    },
+                    None => {
+                        self.symbols.insert(name.clone(), (section, sections.len(section), statement.span));
+
+                        Ok(())
+                    },
+                },
+                StatementKind::Label(_) => Ok(()),
+                StatementKind::Instruction { .. } if section != Section::Text => {
+                    Err(AssemblerError::new(AssemblerErrorKind::InstructionOutsideText, statement.span))
+                },
+                StatementKind::Instruction { opcode, arguments } => {
+                    if self.phase == AssemblerPhase::Second && self.emit_debug_info {
+                        let span = statement.span;
+
+                        self.lines.push(LineEntry { offset: sections.text.len(), file: span.file, line: span.line, column: span.column });
+                    }
+
+                    self.encode(*opcode, arguments, statement.span, &mut sections.text)
+                },
+                StatementKind::Directive { name, arguments } => {
+                    self.directive(name, arguments, statement.span, &mut section, &mut sections)
+                },
+            };
+
+            let is_label = matches!(statement.kind, StatementKind::Label(_));
+
+            if let Err(error) = result {
+                if self.phase == AssemblerPhase::Second || is_label {
+                    errors.push(error);
+                }
+            }
+        }
+
+        sections
+    }
+
+    // Switches to another section, or assembles data into the current one.
+    fn directive(&mut self, name: &str, arguments: &[Argument], span: Span, section: &mut Section, sections: &mut Sections) -> Result<(), AssemblerError> {
+        let operand_count = |expected| {
+            let kind = AssemblerErrorKind::WrongOperandCount { name: format!(".{}", name), expected, found: arguments.len() };
+
+            AssemblerError::new(kind, span)
+        };
+
+        let switch_to = match name {
+            "text" => Some(Section::Text),
+            "rodata" => Some(Section::ReadOnly),
+            "data" => Some(Section::Data),
+            "bss" => Some(Section::Bss),
+            _ => None,
+        };
+
+        if let Some(switch_to) = switch_to {
+            if !arguments.is_empty() {
+                return Err(operand_count(0));
+            }
+
+            *section = switch_to;
+
+            return Ok(());
+        }
+
+        if name == "global" || name == "extern" {
+            if arguments.is_empty() {
+                return Err(operand_count(1));
+            }
+
+            for argument in arguments {
+                let label = match &argument.kind {
+                    ArgumentKind::Label(label) => label,
+                    _ => return Err(AssemblerError::new(AssemblerErrorKind::ExpectedArgument("a label"), argument.span)),
+                };
+
+                // Imports have to be known before the second phase resolves labels
+                if self.phase == AssemblerPhase::First {
+                    if name == "global" {
+                        self.exports.push((label.clone(), argument.span));
+                    } else {
+                        self.imports.insert(label.clone(), argument.span);
+                    }
+                }
+            }
+
+            return Ok(());
+        }
+
+        if name == "equ" {
+            let (constant, argument) = match arguments {
+                [Argument { kind: ArgumentKind::Label(constant), .. }, argument] => (constant, argument),
+                [first, _] => return Err(AssemblerError::new(AssemblerErrorKind::ExpectedArgument("a name"), first.span)),
+                _ => return Err(operand_count(2)),
+            };
+
+            let (value, dependency) = self.evaluate(argument)?;
+
+            // Clashes are caught in the second phase, once every label is known
+            if self.phase == AssemblerPhase::Second {
+                let first_line = match (self.symbols.get(constant), self.constants.get(constant)) {
+                    (Some(&(_, _, first)), _) => Some(first.line),
+                    (_, Some(first)) if first.phase == AssemblerPhase::Second => Some(first.span.line),
+                    _ => None,
+                };
+
+                if let Some(first_line) = first_line {
+                    return Err(AssemblerError::new(AssemblerErrorKind::DuplicateLabel { name: constant.clone(), first_line }, arguments[0].span));
+                }
+            }
+
+            self.constants.insert(constant.clone(), Constant { value, dependency, phase: self.phase, span: arguments[0].span });
+
+            return Ok(());
+        }
+
+        if name == "entry" {
+            let label = match arguments {
+                [Argument { kind: ArgumentKind::Label(label), .. }] => label,
+                [argument] => return Err(AssemblerError::new(AssemblerErrorKind::ExpectedArgument("a label"), argument.span)),
+                _ => return Err(operand_count(1)),
+            };
+
+            // Resolved once both phases are done, since the label may come later
+            if self.phase == AssemblerPhase::Second {
+                if let Some((_, first)) = &self.entry {
+                    return Err(AssemblerError::new(AssemblerErrorKind::DuplicateEntry { first_line: first.line }, span));
+                }
+
+                self.entry = Some((label.clone(), arguments[0].span));
+            }
+
+            return Ok(());
+        }
+
+        if name == "export" {
+            let (label, signature) = match arguments {
+                [Argument { kind: ArgumentKind::Label(label), .. }, Argument { kind: ArgumentKind::String(signature), span }] => {
+                    let signature = Signature::parse(signature).ok_or_else(|| {
+                        AssemblerError::new(AssemblerErrorKind::InvalidSignature(signature.clone()), *span)
+                    })?;
+
+                    (label, signature)
+                },
+                [Argument { kind: ArgumentKind::Label(_), .. }, argument] => return Err(AssemblerError::new(AssemblerErrorKind::ExpectedArgument("a signature string"), argument.span)),
+                [argument, _] => return Err(AssemblerError::new(AssemblerErrorKind::ExpectedArgument("a label"), argument.span)),
+                _ => return Err(operand_count(2)),
+            };
+
+            // Resolved once both phases are done, since the label may come later
+            if self.phase == AssemblerPhase::Second {
+                if let Some((_, _, first)) = self.functions.iter().find(|(name, _, _)| name == label) {
+                    return Err(AssemblerError::new(AssemblerErrorKind::DuplicateExport { name: label.clone(), first_line: first.line }, span));
+                }
+
+                self.functions.push((label.clone(), signature, arguments[0].span));
+            }
+
+            return Ok(());
+        }
+
+        if name == "align" || name == "space" {
+            let argument = match arguments {
+                [argument] => argument,
+                _ => return Err(operand_count(1)),
+            };
+
+            let value = match self.evaluate(argument)? {
+                (value, Dependency::None) => value,
+                _ => return Err(AssemblerError::new(AssemblerErrorKind::NotConstant, argument.span)),
+            };
+
+            if name == "align" {
+                if value <= 0 || (value as u64).count_ones() != 1 {
+                    return Err(AssemblerError::new(AssemblerErrorKind::InvalidAlignment(value), argument.span));
+                }
+
+                sections.align(*section, value as usize);
+            } else {
+                if value < 0 {
+                    return Err(AssemblerError::new(AssemblerErrorKind::InvalidSize(value), argument.span));
+                }
+
+                sections.space(*section, value as usize);
+            }
+
+            return Ok(());
+        }
+
+        if !matches!(name, "byte" | "word" | "dword" | "f64" | "string" | "asciiz") {
+            return Err(AssemblerError::new(AssemblerErrorKind::UnknownDirective(name.to_string()), span));
+        }
+
+        let mut data = vec![];
+
+        for argument in arguments {
+            let expected = |expected| AssemblerError::new(AssemblerErrorKind::ExpectedArgument(expected), argument.span);
+
+            match name {
+                "byte" | "word" | "dword" => {
+                    // A word is 32 bits, the width `LOAD` and `STOR` access
+                    let size = match name {
+                        "byte" => 1,
+                        "word" => 4,
+                        _ => 8,
+                    };
+
+                    let (value, dependency) = self.sized_value(argument, size)?;
+
+                    self.relocate(dependency, value, *section, sections.len(*section) + data.len(), size, argument.span)?;
+
+                    data.extend_from_slice(&value.to_le_bytes()[..size]);
+                },
+                "f64" => {
+                    let value = match argument.kind {
+                        ArgumentKind::Float(value) => value,
+                        ArgumentKind::Register(_) | ArgumentKind::String(_) => return Err(expected("a number")),
+                        _ => match self.evaluate(argument)? {
+                            // There is no patching an address once it has been turned into a float
+                            (_, Dependency::Label(_)) if self.phase == AssemblerPhase::Second => {
+                                return Err(AssemblerError::new(AssemblerErrorKind::NotRelocatable, argument.span));
+                            },
+                            (_, Dependency::Other) if self.phase == AssemblerPhase::Second => {
+                                return Err(AssemblerError::new(AssemblerErrorKind::NotRelocatable, argument.span));
+                            },
+                            (value, _) => value as f64,
+                        },
+                    };
+
+                    data.extend_from_slice(&value.to_le_bytes());
+                },
+                "string" | "asciiz" => {
+                    match &argument.kind {
+                        ArgumentKind::String(value) => data.extend_from_slice(value.as_bytes()),
+                        _ => return Err(expected("a string")),
+                    }
+
+                    if name == "asciiz" {
+                        data.push(0);
+                    }
+                },
+                _ => unreachable!(),
+            }
+        }
+
+        match sections.bytes_mut(*section) {
+            Some(bytes) => bytes.extend_from_slice(&data),
+            None => return Err(AssemblerError::new(AssemblerErrorKind::DataInBss(name.to_string()), span)),
+        }
+
+        Ok(())
+    }
+
+    // Appends the encoded instruction to `bytecode`, checking each argument against the operand the
+    // instruction expects there.
+    //
+    // Jumps can also be written with a target after the register, as in `JMP $6 loop`. They expand
+    // to a `SET` of the register followed by the jump, where `JMPF` and `JMPB` set the distance
+    // from the end of the jump to the target rather than the target itself.
+    fn encode(&mut self, opcode: Opcode, arguments: &[Argument], span: Span, bytecode: &mut Vec<u8>) -> Result<(), AssemblerError> {
+        let is_jump = matches!(opcode, Opcode::Jump | Opcode::JumpIfEqual | Opcode::CallRegister | Opcode::JumpForward | Opcode::JumpBackward);
+
+        if let (true, [register, target]) = (is_jump, arguments) {
+            let end = bytecode.len() + Opcode::Set.encoded_len() + opcode.encoded_len();
+
+            let (address, dependency) = self.resolve(target, OperandKind::Imm16)?;
+            let error = |kind| AssemblerError::new(kind, target.span);
+
+            let distance = |distance| Argument { kind: ArgumentKind::Integer(distance), span: target.span };
+
+            let value = match opcode {
+                // Absolute targets are set like any other operand, relocation included
+                Opcode::Jump | Opcode::JumpIfEqual | Opcode::CallRegister => target.clone(),
+
+                // Until labels are known there is no telling how far the jump goes
+                _ if self.phase == AssemblerPhase::First => distance(0),
+
+                // The distance to a label elsewhere is not known until the program is linked
+                _ if !self.is_local(&dependency) => return Err(error(AssemblerErrorKind::NotRelocatable)),
+
+                Opcode::JumpForward if address < end as i64 => return Err(error(AssemblerErrorKind::WrongJumpDirection(opcode))),
+                Opcode::JumpForward => distance(address - end as i64),
+
+                Opcode::JumpBackward if address > end as i64 => return Err(error(AssemblerErrorKind::WrongJumpDirection(opcode))),
+                _ => distance(end as i64 - address),
+            };
+
+            self.encode(Opcode::Set, &[register.clone(), value], span, bytecode)?;
+
+            return self.encode(opcode, std::slice::from_ref(register), span, bytecode);
+        }
 
+        let kinds = opcode.operands();
+
+        if kinds.len() != arguments.len() {
+            let kind = AssemblerErrorKind::WrongOperandCount { name: opcode.instruction().to_string(), expected: kinds.len(), found: arguments.len() };
+
+            return Err(AssemblerError::new(kind, span));
+        }
+
+        bytecode.push(opcode.byte());
+
+        for (&kind, argument) in kinds.iter().zip(arguments) {
+            let error = |error_kind| AssemblerError::new(error_kind, argument.span);
+
+            let value = match (kind.is_register(), &argument.kind) {
+                (true, &ArgumentKind::Register(register)) => {
+                    if register > u32::from(kind.max()) {
+                        return Err(error(AssemblerErrorKind::BadRegister { register, kind }));
+                    }
+
+                    i64::from(register)
+                },
+                (true, _) => return Err(error(AssemblerErrorKind::ExpectedOperand(kind))),
+                (false, _) => {
+                    let (value, dependency) = self.resolve(argument, kind)?;
+
+                    if value < 0 || value > i64::from(kind.max()) {
+                        return Err(error(AssemblerErrorKind::OutOfRange { value, kind }));
+                    }
+
+                    self.relocate(dependency, value, Section::Text, bytecode.len(), kind.size(), argument.span)?;
+
+                    value
+                },
+            };
+
+            bytecode.extend_from_slice(&(value as u16).to_le_bytes()[..kind.size()]);
+        }
+
+        Ok(())
+    }
+
+    // The value of an argument given for an operand of `kind`.
+    fn resolve(&self, argument: &Argument, kind: OperandKind) -> Result<(i64, Dependency), AssemblerError> {
+        match argument.kind {
+            ArgumentKind::Register(_) => Err(AssemblerError::new(AssemblerErrorKind::ExpectedOperand(kind), argument.span)),
+            _ => self.evaluate(argument),
+        }
+    }
+
+    // Records a relocation for `value`, written at `offset` into `section` in `size` bytes, when it
+    // depends on the address of a label. Values that depend on labels in ways a relocation cannot
+    // express are rejected.
+    fn relocate(&mut self, dependency: Dependency, value: i64, section: Section, offset: usize, size: usize, span: Span) -> Result<(), AssemblerError> {
+        if self.phase == AssemblerPhase::First {
+            return Ok(());
+        }
+
+        match dependency {
+            Dependency::Label(name) => {
+                // Imports have no address until they are linked
+                let addend = value - self.labels.get(&name).map_or(0, |&address| address as i64);
+
+                self.relocations.push(Relocation { section, offset, size, symbol: self.symbol_indices[&name], addend });
+
+                Ok(())
+            },
+            Dependency::Other => Err(AssemblerError::new(AssemblerErrorKind::NotRelocatable, span)),
+            Dependency::None | Dependency::Distance => Ok(()),
+        }
+    }
+
+    // Whether the distance to a target with `dependency` is known before linking.
+    fn is_local(&self, dependency: &Dependency) -> bool {
+        match dependency {
+            Dependency::Label(name) => self.symbols.contains_key(name),
+            Dependency::Other => false,
+            Dependency::None | Dependency::Distance => true,
+        }
+    }
+
+    // The value of an integer expression, along with how it depends on the addresses of labels.
+    // Labels that are not known yet count as 0 during the first phase, which is good enough to lay
+    // out the program as long as sizes do not depend on them. Imports count as 0 until linked.
+    fn evaluate(&self, argument: &Argument) -> Result<(i64, Dependency), AssemblerError> {
+        let error = |kind| Err(AssemblerError::new(kind, argument.span));
+
+        match &argument.kind {
+            ArgumentKind::Integer(value) => Ok((*value, Dependency::None)),
+            ArgumentKind::Label(name) => {
+                if let Some(constant) = self.constants.get(name) {
+                    if constant.phase == self.phase {
+                        return Ok((constant.value, constant.dependency.clone()));
+                    }
+
+                    return error(AssemblerErrorKind::ConstantUsedBeforeDefinition(name.clone()));
+                }
+
+                match self.labels.get(name) {
+                    Some(&address) => Ok((address as i64, Dependency::Label(name.clone()))),
+                    None if self.phase == AssemblerPhase::First || self.imports.contains_key(name) => Ok((0, Dependency::Label(name.clone()))),
+                    None => error(AssemblerErrorKind::UndefinedLabel(name.clone())),
+                }
+            },
+            ArgumentKind::Unary(operator, operand) => {
+                let (value, dependency) = self.evaluate(operand)?;
+
+                let dependency = match dependency {
+                    Dependency::Label(_) => Dependency::Other,
+                    dependency => dependency,
+                };
+
+                match operator {
+                    Operator::Not => Ok((!value, dependency)),
+                    _ => match value.checked_neg() {
+                        Some(value) => Ok((value, dependency)),
+                        None => error(AssemblerErrorKind::ArithmeticOverflow),
+                    },
+                }
+            },
+            ArgumentKind::Binary(operator, left, right) => {
+                let (left, left_dependency) = self.evaluate(left)?;
+                let (right, right_dependency) = self.evaluate(right)?;
+
+                let dependency = match (operator, left_dependency, right_dependency) {
+                    (_, Dependency::None, Dependency::None) => Dependency::None,
+
+                    // Moving a label by a number keeps the result relocatable
+                    (Operator::Add, Dependency::Label(name), Dependency::None | Dependency::Distance) => Dependency::Label(name),
+                    (Operator::Add, Dependency::None | Dependency::Distance, Dependency::Label(name)) => Dependency::Label(name),
+                    (Operator::Subtract, Dependency::Label(name), Dependency::None | Dependency::Distance) => Dependency::Label(name),
+
+                    // Labels in the same section move together
+                    (Operator::Subtract, Dependency::Label(a), Dependency::Label(b)) if self.same_section(&a, &b) => Dependency::Distance,
+
+                    (_, Dependency::Label(_), _) | (_, _, Dependency::Label(_)) => Dependency::Other,
+                    (_, Dependency::Other, _) | (_, _, Dependency::Other) => Dependency::Other,
+                    _ => Dependency::Distance,
+                };
+
+                if right == 0 && matches!(operator, Operator::Divide | Operator::Remainder) {
+                    return error(AssemblerErrorKind::DivisionByZero);
+                }
+
+                let shift = u32::try_from(right).unwrap_or(u32::MAX);
+
+                let value = match operator {
+                    Operator::Add => left.checked_add(right),
+                    Operator::Subtract => left.checked_sub(right),
+                    Operator::Multiply => left.checked_mul(right),
+                    Operator::Divide => left.checked_div(right),
+                    Operator::Remainder => left.checked_rem(right),
+                    Operator::ShiftLeft => left.checked_shl(shift),
+                    Operator::ShiftRight => left.checked_shr(shift),
+                    Operator::And => Some(left & right),
+                    Operator::Or => Some(left | right),
+                    Operator::Xor => Some(left ^ right),
+                    Operator::Not => unreachable!(),
+                };
+
+                match value {
+                    Some(value) => Ok((value, dependency)),
+                    None => error(AssemblerErrorKind::ArithmeticOverflow),
+                }
+            },
+            _ => error(AssemblerErrorKind::ExpectedArgument("an integer")),
+        }
+    }
+
+    // Whether both labels are defined in the same section.
+    fn same_section(&self, a: &str, b: &str) -> bool {
+        match (self.symbols.get(a), self.symbols.get(b)) {
+            (Some(&(a, _, _)), Some(&(b, _, _))) => a == b,
+            _ => false,
+        }
+    }
+
+    // The value of an argument that must fit in `size` bytes, either signed or unsigned.
+    fn sized_value(&self, argument: &Argument, size: usize) -> Result<(i64, Dependency), AssemblerError> {
+        let (value, dependency) = self.evaluate(argument)?;
+        let bits = size as u32 * 8;
+
+        if bits < 64 && (value < -(1 << (bits - 1)) || value >= 1 << bits) {
+            return Err(AssemblerError::new(AssemblerErrorKind::DataOutOfRange { value, size }, argument.span));
+        }
+
+        Ok((value, dependency))
     }
 }
 
-pub struct AssemblerPhase {
-    
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::linker::{Binding, Relocation, Symbol};
+    use crate::assembler::program::Program;
+    use crate::vm::VM;
+    use crate::vm::memory::Memory;
+    use crate::vm::trap::ExitReason;
+
+    fn assemble(source: &str) -> Result<Vec<u8>, Vec<AssemblerError>> {
+        let mut assembler = Assembler::default();
+
+        assembler.compile(source)?;
+
+        Ok(assembler.result.bytecode)
+    }
+
+    // The line and kind of every error in `source`
+    fn errors(source: &str) -> Vec<(usize, AssemblerErrorKind)> {
+        assemble(source).unwrap_err().into_iter().map(|error| (error.span.line, error.kind)).collect()
+    }
+
+    #[test]
+    fn compile_instructions() {
+        assert_eq!(assemble("SET $0 500\nADD $5 $3 $4\nSETF $31 2\nSTOR 8 $1\nSHL $2 3\nJMP $6\nHLT").unwrap(), vec![
+            Opcode::Set.byte(), 0, 244, 1,
+            Opcode::Add.byte(), 5, 3, 4,
+            Opcode::SetF64.byte(), 31, 2, 0,
+            Opcode::Store.byte(), 8, 1,
+            Opcode::ShiftLeft.byte(), 2, 3,
+            Opcode::Jump.byte(), 6,
+            Opcode::Halt.byte(),
+        ]);
+    }
+
+    #[test]
+    fn run_compiled_program() {
+        let mut test_vm = VM::default();
+
+        test_vm.set_program(assemble("
+            SET $1 10
+            SET $2 0
+            SET $3 16   ; start of the loop
+            SET $4 27   ; end of the program
+
+            EQ $1 $2
+            JEQ $4
+            DEC $1
+            INC $0
+            JMP $3
+        ").unwrap());
+
+        test_vm.run().unwrap();
+
+        assert_eq!(test_vm.registers[0], 10);
+    }
+
+    #[test]
+    fn resolve_labels() {
+        let mut test_vm = VM::default();
+
+        test_vm.set_program(assemble("
+                SET $1 10
+                SET $2 0
+                SET $3 loop
+                SET $4 end
+
+            loop:
+                EQ $1 $2
+                JEQ $4
+                DEC $1
+                INC $0
+                JMP $3
+            end:
+        ").unwrap());
+
+        test_vm.run().unwrap();
+
+        assert_eq!(test_vm.registers[0], 10);
+
+        assert_eq!(assemble("CALL sub\nHLT\nsub: RET").unwrap(), vec![
+            Opcode::Call.byte(), 4, 0,
+            Opcode::Halt.byte(),
+            Opcode::Return.byte(),
+        ]);
+    }
+
+    #[test]
+    fn expand_jump_pseudo_instructions() {
+        assert_eq!(assemble("start: JMPF $0 end\nJEQ $1 start\nJMPB $2 start\nend:").unwrap(), vec![
+            Opcode::Set.byte(), 0, 12, 0,
+            Opcode::JumpForward.byte(), 0,
+            Opcode::Set.byte(), 1, 0, 0,
+            Opcode::JumpIfEqual.byte(), 1,
+            Opcode::Set.byte(), 2, 18, 0,
+            Opcode::JumpBackward.byte(), 2,
+        ]);
+
+        let mut test_vm = VM::default();
+
+        // Counts $0 down to zero with both kinds of relative jumps
+        test_vm.set_program(assemble("
+                SET $0 5
+                SET $1 0
+            loop:
+                EQ $0 $1
+                JEQ $2 end
+                DEC $0
+                JMPF $3 skip
+                HLT
+            skip:
+                JMPB $3 loop
+            end:
+        ").unwrap());
+
+        assert_eq!(test_vm.run(), Ok(ExitReason::EndOfProgram));
+        assert_eq!(test_vm.registers[0], 0);
+    }
+
+    #[test]
+    fn fail_on_bad_labels() {
+        assert_eq!(errors("a: HLT\na:"), vec![(2, AssemblerErrorKind::DuplicateLabel { name: "a".to_string(), first_line: 1 })]);
+        assert_eq!(errors("HLT\nCALL nowhere"), vec![(2, AssemblerErrorKind::UndefinedLabel("nowhere".to_string()))]);
+        assert_eq!(errors("a: JMPF $0 a"), vec![(1, AssemblerErrorKind::WrongJumpDirection(Opcode::JumpForward))]);
+        assert_eq!(errors("JMPB $0 a\nHLT\na:"), vec![(1, AssemblerErrorKind::WrongJumpDirection(Opcode::JumpBackward))]);
+    }
+
+    #[test]
+    fn assemble_data_sections() {
+        let mut assembler = Assembler::default();
+
+        assembler.compile("
+            .rodata
+            greeting: .asciiz \"hi\"
+            .align 4
+            table: .word 1, -1
+                   .byte 255, -128
+
+            .data
+            counter: .dword 7
+            ratio: .f64 1.5
+
+            .bss
+            buffer: .space 3
+                    .align 16
+            scratch: .space 16
+
+            .text
+            main:
+                SET $1 counter
+                LOAD $0 $1
+                STOR buffer $0
+        ").unwrap();
+
+        let program = &assembler.result;
+
+        assert_eq!(assembler.labels["greeting"], 0);
+        assert_eq!(assembler.labels["table"], 4);
+        assert_eq!(assembler.labels["counter"], 16);
+        assert_eq!(assembler.labels["ratio"], 24);
+        assert_eq!(assembler.labels["buffer"], 32);
+        assert_eq!(assembler.labels["scratch"], 48);
+        assert_eq!(assembler.labels["main"], 0);
+
+        assert_eq!(&program.read_only[..14], &[b'h', b'i', 0, 0, 1, 0, 0, 0, 255, 255, 255, 255, 255, 128]);
+        assert_eq!(&program.read_only[16..24], &7u64.to_le_bytes());
+        assert_eq!(&program.read_only[24..], &1.5f64.to_le_bytes());
+        assert_eq!(program.bss_size, 32);
+
+        // Data labels are heap addresses the program can load from
+        let mut test_vm = VM::default();
+
+        test_vm.heap = Memory::from(program.read_only.clone());
+        test_vm.heap.grow(program.bss_size);
+        test_vm.set_program(program.bytecode.clone());
+
+        test_vm.run().unwrap();
+
+        assert_eq!(test_vm.registers[0], 7);
+        assert_eq!(test_vm.heap.read_u32(32), Ok(7));
+    }
+
+    #[test]
+    fn fail_on_bad_directives() {
+        assert_eq!(errors(".data\nHLT"), vec![(2, AssemblerErrorKind::InstructionOutsideText)]);
+        assert_eq!(errors(".bss\n.byte 1"), vec![(2, AssemblerErrorKind::DataInBss("byte".to_string()))]);
+        assert_eq!(errors(".rodata 1"), vec![(1, AssemblerErrorKind::WrongOperandCount { name: ".rodata".to_string(), expected: 0, found: 1 })]);
+        assert_eq!(errors(".quad 1"), vec![(1, AssemblerErrorKind::UnknownDirective("quad".to_string()))]);
+        assert_eq!(errors(".byte 256"), vec![(1, AssemblerErrorKind::DataOutOfRange { value: 256, size: 1 })]);
+        assert_eq!(errors(".word 4294967296"), vec![(1, AssemblerErrorKind::DataOutOfRange { value: 4294967296, size: 4 })]);
+        assert_eq!(errors(".align 3"), vec![(1, AssemblerErrorKind::InvalidAlignment(3))]);
+        assert_eq!(errors(".space -1"), vec![(1, AssemblerErrorKind::InvalidSize(-1))]);
+        assert_eq!(errors(".string 1"), vec![(1, AssemblerErrorKind::ExpectedArgument("a string"))]);
+        assert_eq!(errors(".byte \"a\""), vec![(1, AssemblerErrorKind::ExpectedArgument("an integer"))]);
+    }
+
+    #[test]
+    fn set_entry_point() {
+        let mut assembler = Assembler::default();
+
+        assembler.compile("HLT\nmain: INC $0\nstart: INC $1").unwrap();
+        assert_eq!(assembler.result.header.entry_point, 1);
+        assert!(assembler.result.has_entry);
+
+        assembler.compile(".entry start\nHLT\nmain: INC $0\nstart: INC $1").unwrap();
+        assert_eq!(assembler.result.header.entry_point, 3);
+
+        // A `main` outside of code is just data
+        assembler.compile(".data\nmain: .byte 1\n.text\nINC $0").unwrap();
+        assert_eq!(assembler.result.header.entry_point, 0);
+        assert!(!assembler.result.has_entry);
+
+        // The file loads back with everything in place
+        assembler.compile(".rodata\nvalue: .word 9\n.bss\n.space 4\n.text\nHLT\nmain: HLT").unwrap();
+
+        let program = Program::decode(&assembler.result.bytes().unwrap()).unwrap();
+
+        assert_eq!(program.header.entry_point, 1);
+        assert_eq!(program.read_only, assembler.result.read_only);
+        assert_eq!(program.read_only[..4], [9, 0, 0, 0]);
+        assert_eq!(program.bss_size, 4);
+        assert_eq!(program.bytecode, vec![Opcode::Halt.byte(), Opcode::Halt.byte()]);
+    }
+
+    #[test]
+    fn fail_on_bad_entry() {
+        assert_eq!(errors(".entry a\n.entry a\na: HLT"), vec![(2, AssemblerErrorKind::DuplicateEntry { first_line: 1 })]);
+        assert_eq!(errors(".entry nowhere"), vec![(1, AssemblerErrorKind::UndefinedLabel("nowhere".to_string()))]);
+        assert_eq!(errors(".entry value\n.data\nvalue: .byte 1"), vec![(1, AssemblerErrorKind::EntryOutsideText("value".to_string()))]);
+        assert_eq!(errors(".entry 4"), vec![(1, AssemblerErrorKind::ExpectedArgument("a label"))]);
+    }
+
+    #[test]
+    fn export_functions() {
+        let mut assembler = Assembler::default();
+
+        assembler.compile(".export add \"i32, i32 -> i32\"\nHLT\nadd: ADD $0 $0 $1\nRET").unwrap();
+
+        assert_eq!(assembler.result.exports, vec![
+            Export { name: "add".to_string(), offset: 1, signature: Signature::parse("i32, i32 -> i32").unwrap() },
+        ]);
+    }
+
+    #[test]
+    fn fail_on_bad_exports() {
+        assert_eq!(errors(".export a \"\"\n.export a \"i32\"\na: RET"), vec![(2, AssemblerErrorKind::DuplicateExport { name: "a".to_string(), first_line: 1 })]);
+        assert_eq!(errors(".export nowhere \"\""), vec![(1, AssemblerErrorKind::UndefinedLabel("nowhere".to_string()))]);
+        assert_eq!(errors(".export value \"\"\n.data\nvalue: .byte 1"), vec![(1, AssemblerErrorKind::ExportOutsideText("value".to_string()))]);
+        assert_eq!(errors(".export a \"i32 -> u8\"\na: RET"), vec![(1, AssemblerErrorKind::InvalidSignature("i32 -> u8".to_string()))]);
+        assert_eq!(errors(".export a 4\na: RET"), vec![(1, AssemblerErrorKind::ExpectedArgument("a signature string"))]);
+        assert_eq!(errors(".export a"), vec![(1, AssemblerErrorKind::WrongOperandCount { name: ".export".to_string(), expected: 2, found: 1 })]);
+    }
+
+    #[test]
+    fn record_relocations() {
+        let mut assembler = Assembler::default();
+
+        assembler.compile("
+            .extern far
+            .global start
+
+            start:
+                SET $0 far + 2
+                JMP $1 start
+                JMPB $2 start
+
+            .data
+                .word 0
+            value:
+                .word start, value - 4, end - start
+
+            .text
+            end:
+        ").unwrap();
+
+        let program = &assembler.result;
+
+        assert_eq!(program.symbols, vec![
+            Symbol { name: "end".to_string(), binding: Binding::Local, section: Section::Text, address: 16 },
+            Symbol { name: "far".to_string(), binding: Binding::Imported, section: Section::Text, address: 0 },
+            Symbol { name: "start".to_string(), binding: Binding::Exported, section: Section::Text, address: 0 },
+            Symbol { name: "value".to_string(), binding: Binding::Local, section: Section::Data, address: 4 },
+        ]);
+
+        // Relative jumps and distances between labels need none
+        assert_eq!(program.relocations, vec![
+            Relocation { section: Section::Text, offset: 2, size: 2, symbol: 1, addend: 2 },
+            Relocation { section: Section::Text, offset: 6, size: 2, symbol: 2, addend: 0 },
+            Relocation { section: Section::Data, offset: 4, size: 4, symbol: 2, addend: 0 },
+            Relocation { section: Section::Data, offset: 8, size: 4, symbol: 3, addend: -4 },
+        ]);
+    }
+
+    #[test]
+    fn fail_on_bad_symbols() {
+        assert_eq!(errors("a: HLT\nSET $0 a * 2"), vec![(2, AssemblerErrorKind::NotRelocatable)]);
+        assert_eq!(errors(".data\na: .word 0\n.text\nb: SET $0 b - a"), vec![(4, AssemblerErrorKind::NotRelocatable)]);
+        assert_eq!(errors(".extern a\nJMPF $0 a"), vec![(2, AssemblerErrorKind::NotRelocatable)]);
+        assert_eq!(errors(".extern a\n.f64 a"), vec![(2, AssemblerErrorKind::NotRelocatable)]);
+        assert_eq!(errors(".global nowhere"), vec![(1, AssemblerErrorKind::UndefinedLabel("nowhere".to_string()))]);
+        assert_eq!(errors(".extern a\na: HLT"), vec![(1, AssemblerErrorKind::DuplicateLabel { name: "a".to_string(), first_line: 2 })]);
+        assert_eq!(errors(".global 1"), vec![(1, AssemblerErrorKind::ExpectedArgument("a label"))]);
+    }
+
+    #[test]
+    fn collect_every_error() {
+        let mut assembler = Assembler::default();
+
+        let errors = assembler.compile_source("fib.asm", "start:\n    ADD $17 $3 $4\n    SETF $0\nstart: JMP $0 end").unwrap_err();
+
+        assert_eq!(errors.len(), 4);
+
+        assert_eq!(errors[0].to_string(), "\
+error: register $17 out of range
+ --> fib.asm:2:9
+  |
+2 |     ADD $17 $3 $4
+  |         ^^^
+  = help: integer registers are $0-$15");
+
+        assert_eq!(errors[1].to_string(), "\
+error: SETF expects 2 operands, found 1
+ --> fib.asm:3:5
+  |
+3 |     SETF $0
+  |     ^^^^
+  = help: SETF takes a float register and a 16-bit immediate");
+
+        assert_eq!(errors[2].kind, AssemblerErrorKind::DuplicateLabel { name: "start".to_string(), first_line: 1 });
+        assert_eq!(errors[3].kind, AssemblerErrorKind::UndefinedLabel("end".to_string()));
+        assert_eq!((errors[3].span.column, errors[3].span.len), (15, 3));
+    }
+
+    #[test]
+    fn assemble_macros() {
+        let mut test_vm = VM::default();
+
+        test_vm.set_program(assemble("
+            .macro add_to target, count
+                SET $1 count
+                SET $2 0
+            loop:
+                EQ $1 $2
+                JEQ $4 done
+                DEC $1
+                INC target
+                JMP $3 loop
+            done:
+            .endm
+
+                add_to $0, 3
+                add_to $0, 4
+                HLT
+        ").unwrap());
+
+        test_vm.run().unwrap();
+
+        assert_eq!(test_vm.registers[0], 7);
+
+        let mut assembler = Assembler::default();
+
+        let errors = assembler.compile_source("macro.asm", ".macro put value\n    SET $16 value\n.endm\nput 1\nput 2").unwrap_err();
+
+        // Both expansions fail at the same place in the body, which is reported once
+        assert_eq!(errors.len(), 1);
+
+        assert_eq!(errors[0].to_string(), "\
+error: register $16 out of range
+ --> macro.asm:2:9
+  |
+2 |     SET $16 value
+  |         ^^^
+  = help: integer registers are $0-$15");
+
+        let errors = assembler.compile_file("missing.asm").unwrap_err();
+
+        assert!(errors[0].to_string().starts_with("error: could not read `missing.asm`: "));
+        assert!(errors[0].to_string().ends_with("\n --> missing.asm"));
+    }
+
+    #[test]
+    fn evaluate_constant_expressions() {
+        let mut assembler = Assembler::default();
+
+        assembler.compile("
+            .equ MAX 44
+            .equ SIZE, end - start
+            .equ LAST MAX -1
+
+                SET $0 MAX - 1
+                SET $1 'a' + 0b11 * 0x10
+                SET $2 SIZE
+                SET $3 buffer + 4
+                SET $4 (1 << 15) | ~-1 & 7
+                SET $5 LAST
+            start:
+                HLT
+            end:
+
+            .data
+                .space MAX / 22
+            buffer:
+                .word -MAX, 100 / 7 % 4
+        ").unwrap();
+
+        assert_eq!(assembler.result.bytecode[..24], [
+            Opcode::Set.byte(), 0, 43, 0,
+            Opcode::Set.byte(), 1, 145, 0,
+            Opcode::Set.byte(), 2, 1, 0,
+            Opcode::Set.byte(), 3, 6, 0,
+            Opcode::Set.byte(), 4, 0, 128,
+            Opcode::Set.byte(), 5, 43, 0,
+        ]);
+
+        assert_eq!(assembler.result.read_only, [0, 0, 212, 255, 255, 255, 2, 0, 0, 0]);
+
+        // Overflow of the 16-bit immediate is caught however the value is written
+        assert_eq!(errors(".equ MAX 0xffff\nSET $0 MAX + 1"), vec![(2, AssemblerErrorKind::OutOfRange { value: 65536, kind: OperandKind::Imm16 })]);
+        assert_eq!(errors("SET $0 -1"), vec![(1, AssemblerErrorKind::OutOfRange { value: -1, kind: OperandKind::Imm16 })]);
+    }
+
+    #[test]
+    fn fail_on_bad_constants() {
+        assert_eq!(errors("SET $0 1 / (2 - 2)"), vec![(1, AssemblerErrorKind::DivisionByZero)]);
+        assert_eq!(errors(".dword 0x7fffffffffffffff + 1"), vec![(1, AssemblerErrorKind::ArithmeticOverflow)]);
+        assert_eq!(errors(".dword 1 << 64"), vec![(1, AssemblerErrorKind::ArithmeticOverflow)]);
+        assert_eq!(errors("a: HLT\n.space a + 1"), vec![(2, AssemblerErrorKind::NotConstant)]);
+        assert_eq!(errors("SET $0 X\n.equ X 1"), vec![(1, AssemblerErrorKind::ConstantUsedBeforeDefinition("X".to_string()))]);
+        assert_eq!(errors(".equ X 1\n.equ X 2"), vec![(2, AssemblerErrorKind::DuplicateLabel { name: "X".to_string(), first_line: 1 })]);
+        assert_eq!(errors("X: HLT\n.equ X 2"), vec![(2, AssemblerErrorKind::DuplicateLabel { name: "X".to_string(), first_line: 1 })]);
+        assert_eq!(errors(".equ 1 2"), vec![(1, AssemblerErrorKind::ExpectedArgument("a name"))]);
+        assert_eq!(errors(".equ X"), vec![(1, AssemblerErrorKind::WrongOperandCount { name: ".equ".to_string(), expected: 2, found: 1 })]);
+    }
+
+    #[test]
+    fn fail_with_line_numbers() {
+        assert_eq!(errors("HLT\nADD $0 $1"), vec![(2, AssemblerErrorKind::WrongOperandCount { name: "ADD".to_string(), expected: 3, found: 2 })]);
+        assert_eq!(errors("SET 1 $0"), vec![(1, AssemblerErrorKind::ExpectedOperand(OperandKind::Register))]);
+        assert_eq!(errors("\n\nMOV $16 $0"), vec![(3, AssemblerErrorKind::BadRegister { register: 16, kind: OperandKind::Register })]);
+        assert_eq!(errors("SET $0 65536"), vec![(1, AssemblerErrorKind::OutOfRange { value: 65536, kind: OperandKind::Imm16 })]);
+        assert_eq!(errors("SHL $0 -1"), vec![(1, AssemblerErrorKind::OutOfRange { value: -1, kind: OperandKind::Imm8 })]);
+
+        assert_eq!(assemble("SETF $31 1"), Ok(vec![Opcode::SetF64.byte(), 31, 1, 0]));
+        assert_eq!(errors("SETF $32 1"), vec![(1, AssemblerErrorKind::BadRegister { register: 32, kind: OperandKind::FloatRegister })]);
+    }
+}
@@ -0,0 +1,362 @@
+use crate::assembler::error::{AssemblerError, AssemblerErrorKind, Span};
+use crate::assembler::lexer::{Operator, Token, TokenKind};
+use crate::vm::instructions::Opcode;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgumentKind {
+    Register(u32),
+    Integer(i64),
+    Float(f64),
+    String(String),
+
+    /// Stands for the address of the label, or the value of the constant, with this name
+    Label(String),
+
+    /// An operator applied to the expression on its right, as in `-start`
+    Unary(Operator, Box<Argument>),
+
+    Binary(Operator, Box<Argument>, Box<Argument>),
+}
+
+/// An operand as written in source, before it is checked against what the instruction expects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Argument {
+    pub kind: ArgumentKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatementKind {
+    /// Names the address of whatever follows it
+    Label(String),
+
+    Instruction { opcode: Opcode, arguments: Vec<Argument> },
+
+    /// A directive such as `.byte 1, 2`, without the leading dot
+    Directive { name: String, arguments: Vec<Argument> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub kind: StatementKind,
+
+    /// Where the label, mnemonic or directive was written
+    pub span: Span,
+}
+
+/// Groups tokens into statements. Each line holds an optional label followed by an optional
+/// instruction or directive. Arguments may be separated by commas, and numbers may be written as
+/// expressions such as `(end - start) / 4`, with the operator precedence of C.
+pub fn parse(tokens: &[Token]) -> Result<Vec<Statement>, Vec<AssemblerError>> {
+    let mut statements = vec![];
+    let mut errors = vec![];
+
+    for line in tokens.split(|token| token.kind == TokenKind::Newline) {
+        if let Err(error) = parse_line(line, &mut statements) {
+            errors.push(error);
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(statements)
+}
+
+fn parse_line(mut line: &[Token], statements: &mut Vec<Statement>) -> Result<(), AssemblerError> {
+    if let [Token { kind: TokenKind::Identifier(name), span }, Token { kind: TokenKind::Colon, .. }, rest @ ..] = line {
+        statements.push(Statement { kind: StatementKind::Label(name.clone()), span: *span });
+
+        line = rest;
+    }
+
+    let (first, rest) = match line.split_first() {
+        Some(split) => split,
+        None => return Ok(()),
+    };
+
+    let mut arguments = vec![];
+    let mut i = 0;
+
+    while i < rest.len() {
+        // Commas may only sit between two arguments
+        if !arguments.is_empty() && rest[i].kind == TokenKind::Comma {
+            i += 1;
+
+            match rest.get(i) {
+                Some(token) if token.kind == TokenKind::Comma => return Err(unexpected(token)),
+                None => return Err(unexpected(&rest[i - 1])),
+                _ => {},
+            }
+        }
+
+        arguments.push(argument(rest, &mut i)?);
+    }
+
+    let kind = match &first.kind {
+        TokenKind::Identifier(name) => match Opcode::from_instruction(name) {
+            Some(opcode) => StatementKind::Instruction { opcode, arguments },
+            None => {
+                let kind = AssemblerErrorKind::UnknownMnemonic { name: name.clone(), suggestion: suggest(name) };
+
+                return Err(AssemblerError::new(kind, first.span));
+            },
+        },
+        TokenKind::Directive(name) => StatementKind::Directive { name: name.clone(), arguments },
+        _ => return Err(AssemblerError::new(AssemblerErrorKind::ExpectedMnemonic, first.span)),
+    };
+
+    statements.push(Statement { kind, span: first.span });
+
+    Ok(())
+}
+
+fn unexpected(token: &Token) -> AssemblerError {
+    AssemblerError::new(AssemblerErrorKind::UnexpectedToken(token.kind.to_string()), token.span)
+}
+
+// Parses the argument starting at `tokens[*i]`, advancing `i` past it.
+fn argument(tokens: &[Token], i: &mut usize) -> Result<Argument, AssemblerError> {
+    let token = &tokens[*i];
+
+    let kind = match &token.kind {
+        TokenKind::Register(register) => ArgumentKind::Register(*register),
+        TokenKind::String(value) => ArgumentKind::String(value.clone()),
+        _ => return expression(tokens, i, 0),
+    };
+
+    *i += 1;
+
+    Ok(Argument { kind, span: token.span })
+}
+
+// How tightly a binary operator binds, following C.
+fn precedence(operator: Operator) -> Option<u8> {
+    match operator {
+        Operator::Or => Some(1),
+        Operator::Xor => Some(2),
+        Operator::And => Some(3),
+        Operator::ShiftLeft | Operator::ShiftRight => Some(4),
+        Operator::Add | Operator::Subtract => Some(5),
+        Operator::Multiply | Operator::Divide | Operator::Remainder => Some(6),
+        Operator::Not => None,
+    }
+}
+
+// Parses operands joined by operators that bind at least as tightly as `min_precedence`.
+fn expression(tokens: &[Token], i: &mut usize, min_precedence: u8) -> Result<Argument, AssemblerError> {
+    let mut left = operand(tokens, i)?;
+
+    while let Some(Token { kind: TokenKind::Operator(operator), .. }) = tokens.get(*i) {
+        let precedence = match precedence(*operator) {
+            Some(precedence) if precedence >= min_precedence => precedence,
+            _ => break,
+        };
+
+        *i += 1;
+
+        let right = expression(tokens, i, precedence + 1)?;
+        let span = join(left.span, right.span);
+
+        left = Argument { kind: ArgumentKind::Binary(*operator, Box::new(left), Box::new(right)), span };
+    }
+
+    Ok(left)
+}
+
+// Parses a number, a name, an expression in parentheses or an operand behind a unary operator.
+fn operand(tokens: &[Token], i: &mut usize) -> Result<Argument, AssemblerError> {
+    let token = match tokens.get(*i) {
+        Some(token) => token,
+
+        // Point at whatever was left waiting for an operand
+        None => return Err(AssemblerError::new(AssemblerErrorKind::UnexpectedToken(TokenKind::Newline.to_string()), tokens[*i - 1].span)),
+    };
+
+    *i += 1;
+
+    let kind = match &token.kind {
+        TokenKind::Integer(value) => ArgumentKind::Integer(*value),
+        TokenKind::Float(value) => ArgumentKind::Float(*value),
+        TokenKind::Identifier(name) => ArgumentKind::Label(name.clone()),
+        TokenKind::Operator(operator @ Operator::Subtract) | TokenKind::Operator(operator @ Operator::Not) => {
+            let operand = operand(tokens, i)?;
+            let span = join(token.span, operand.span);
+
+            // Negative numbers are kept as numbers
+            let kind = match (operator, operand.kind) {
+                (Operator::Subtract, ArgumentKind::Integer(value)) => ArgumentKind::Integer(value.wrapping_neg()),
+                (Operator::Subtract, ArgumentKind::Float(value)) => ArgumentKind::Float(-value),
+                (_, kind) => ArgumentKind::Unary(*operator, Box::new(Argument { kind, span: operand.span })),
+            };
+
+            return Ok(Argument { kind, span });
+        },
+        TokenKind::OpenParen => {
+            let inner = expression(tokens, i, 0)?;
+
+            match tokens.get(*i) {
+                Some(close) if close.kind == TokenKind::CloseParen => {
+                    *i += 1;
+
+                    return Ok(Argument { kind: inner.kind, span: join(token.span, close.span) });
+                },
+                _ => return Err(AssemblerError::new(AssemblerErrorKind::UnclosedParenthesis, token.span)),
+            }
+        },
+        _ => return Err(unexpected(token)),
+    };
+
+    Ok(Argument { kind, span: token.span })
+}
+
+// The span from the start of `first` to the end of `last`, which lie on the same line.
+fn join(first: Span, last: Span) -> Span {
+    Span { len: last.column + last.len - first.column, ..first }
+}
+
+// The mnemonic closest to `name`, if it is near enough to likely be a typo.
+fn suggest(name: &str) -> Option<&'static str> {
+    let name = name.to_uppercase();
+
+    Opcode::all().into_iter()
+        .map(|opcode| (edit_distance(&name, opcode.instruction()), opcode.instruction()))
+        .filter(|&(distance, _)| distance <= (name.len() / 3).max(1))
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, instruction)| instruction)
+}
+
+// The number of single character insertions, removals and substitutions that turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, &b) in b.iter().enumerate() {
+            let substitution = previous[j] + if a == b { 0 } else { 1 };
+
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::lexer::tokenize;
+
+    fn kinds(source: &str) -> Vec<StatementKind> {
+        parse(&tokenize(source, 0).unwrap()).unwrap().into_iter().map(|statement| statement.kind).collect()
+    }
+
+    fn argument(kind: ArgumentKind, column: usize, len: usize) -> Argument {
+        Argument { kind, span: Span { file: 0, line: 1, column, len } }
+    }
+
+    #[test]
+    fn parse_statements() {
+        assert_eq!(kinds("\nset $0 1\nstart:\n  HLT ; done\nend: HLT\n.byte 1, 2 3"), vec![
+            StatementKind::Instruction { opcode: Opcode::Set, arguments: vec![
+                Argument { kind: ArgumentKind::Register(0), span: Span { file: 0, line: 2, column: 5, len: 2 } },
+                Argument { kind: ArgumentKind::Integer(1), span: Span { file: 0, line: 2, column: 8, len: 1 } },
+            ] },
+            StatementKind::Label("start".to_string()),
+            StatementKind::Instruction { opcode: Opcode::Halt, arguments: vec![] },
+            StatementKind::Label("end".to_string()),
+            StatementKind::Instruction { opcode: Opcode::Halt, arguments: vec![] },
+            StatementKind::Directive { name: "byte".to_string(), arguments: vec![
+                Argument { kind: ArgumentKind::Integer(1), span: Span { file: 0, line: 6, column: 7, len: 1 } },
+                Argument { kind: ArgumentKind::Integer(2), span: Span { file: 0, line: 6, column: 10, len: 1 } },
+                Argument { kind: ArgumentKind::Integer(3), span: Span { file: 0, line: 6, column: 12, len: 1 } },
+            ] },
+        ]);
+
+        assert_eq!(kinds("JMP $0 start"), vec![
+            StatementKind::Instruction { opcode: Opcode::Jump, arguments: vec![
+                argument(ArgumentKind::Register(0), 5, 2),
+                argument(ArgumentKind::Label("start".to_string()), 8, 5),
+            ] },
+        ]);
+    }
+
+    #[test]
+    fn parse_expressions() {
+        let integer = |value, column, len| Box::new(argument(ArgumentKind::Integer(value), column, len));
+        let label = |name: &str, column, len| Box::new(argument(ArgumentKind::Label(name.to_string()), column, len));
+
+        // Multiplication binds tighter than addition, and subtraction is left associative
+        assert_eq!(kinds(".word 1 + 2 * 3, 4 - 5 - x"), vec![
+            StatementKind::Directive { name: "word".to_string(), arguments: vec![
+                argument(ArgumentKind::Binary(Operator::Add, integer(1, 7, 1), Box::new(
+                    argument(ArgumentKind::Binary(Operator::Multiply, integer(2, 11, 1), integer(3, 15, 1)), 11, 5),
+                )), 7, 9),
+                argument(ArgumentKind::Binary(Operator::Subtract, Box::new(
+                    argument(ArgumentKind::Binary(Operator::Subtract, integer(4, 18, 1), integer(5, 22, 1)), 18, 5),
+                ), label("x", 26, 1)), 18, 9),
+            ] },
+        ]);
+
+        assert_eq!(kinds(".word -(end - start), -1, ~0, -1.5"), vec![
+            StatementKind::Directive { name: "word".to_string(), arguments: vec![
+                argument(ArgumentKind::Unary(Operator::Subtract, Box::new(
+                    argument(ArgumentKind::Binary(Operator::Subtract, label("end", 9, 3), label("start", 15, 5)), 8, 13),
+                )), 7, 14),
+                argument(ArgumentKind::Integer(-1), 23, 2),
+                argument(ArgumentKind::Unary(Operator::Not, integer(0, 28, 1)), 27, 2),
+                argument(ArgumentKind::Float(-1.5), 31, 4),
+            ] },
+        ]);
+
+        // Without commas, an argument ends where no operator joins it to the next
+        assert_eq!(kinds("SET $0 MAX - 1"), vec![
+            StatementKind::Instruction { opcode: Opcode::Set, arguments: vec![
+                argument(ArgumentKind::Register(0), 5, 2),
+                argument(ArgumentKind::Binary(Operator::Subtract, label("MAX", 8, 3), integer(1, 14, 1)), 8, 7),
+            ] },
+        ]);
+
+        // Whitespace does not matter, so a `-` right before a number still subtracts
+        assert_eq!(kinds("SET $0 MAX -1"), vec![
+            StatementKind::Instruction { opcode: Opcode::Set, arguments: vec![
+                argument(ArgumentKind::Register(0), 5, 2),
+                argument(ArgumentKind::Binary(Operator::Subtract, label("MAX", 8, 3), integer(1, 13, 1)), 8, 6),
+            ] },
+        ]);
+
+        // A negative argument after an expression needs a comma
+        assert_eq!(kinds(".byte MAX, -1"), vec![
+            StatementKind::Directive { name: "byte".to_string(), arguments: vec![
+                argument(ArgumentKind::Label("MAX".to_string()), 7, 3),
+                argument(ArgumentKind::Integer(-1), 12, 2),
+            ] },
+        ]);
+    }
+
+    #[test]
+    fn fail_on_invalid_statements() {
+        let errors = |source| -> Vec<(Span, AssemblerErrorKind)> {
+            parse(&tokenize(source, 0).unwrap()).unwrap_err().into_iter().map(|error| (error.span, error.kind)).collect()
+        };
+
+        assert_eq!(errors("HLT\nFOO $0\nADDD $0 $1 $2"), vec![
+            (Span { file: 0, line: 2, column: 1, len: 3 }, AssemblerErrorKind::UnknownMnemonic { name: "FOO".to_string(), suggestion: None }),
+            (Span { file: 0, line: 3, column: 1, len: 4 }, AssemblerErrorKind::UnknownMnemonic { name: "ADDD".to_string(), suggestion: Some("ADD") }),
+        ]);
+
+        assert_eq!(errors("$0 1"), vec![(Span { file: 0, line: 1, column: 1, len: 2 }, AssemblerErrorKind::ExpectedMnemonic)]);
+        assert_eq!(errors("SET $0 (1 + 2"), vec![(Span { file: 0, line: 1, column: 8, len: 1 }, AssemblerErrorKind::UnclosedParenthesis)]);
+        assert_eq!(errors("SET $0 1 +"), vec![(Span { file: 0, line: 1, column: 10, len: 1 }, AssemblerErrorKind::UnexpectedToken("end of line".to_string()))]);
+        assert_eq!(errors("SET $0 1 + $1"), vec![(Span { file: 0, line: 1, column: 12, len: 2 }, AssemblerErrorKind::UnexpectedToken("`$1`".to_string()))]);
+        assert_eq!(errors("JMP $0:"), vec![(Span { file: 0, line: 1, column: 7, len: 1 }, AssemblerErrorKind::UnexpectedToken("`:`".to_string()))]);
+        assert_eq!(errors(".byte 1,, 2"), vec![(Span { file: 0, line: 1, column: 9, len: 1 }, AssemblerErrorKind::UnexpectedToken("`,`".to_string()))]);
+        assert_eq!(errors(".byte 1,"), vec![(Span { file: 0, line: 1, column: 8, len: 1 }, AssemblerErrorKind::UnexpectedToken("`,`".to_string()))]);
+    }
+}
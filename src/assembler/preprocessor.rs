@@ -0,0 +1,514 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::assembler::error::{AssemblerError, AssemblerErrorKind, Span};
+use crate::assembler::lexer::{self, Token, TokenKind};
+use crate::vm::instructions::Opcode;
+
+/// How deep macros may expand inside one another before the expansion is taken to be endless.
+pub const MAX_MACRO_DEPTH: usize = 64;
+
+/// A file of source read by the assembler. Spans point into one of these by index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    /// The name used for the file in errors
+    pub name: String,
+
+    /// Where the file was read from, which `.include` paths are relative to. Source that was not
+    /// read from disk has none, making its includes relative to the working directory.
+    pub path: Option<PathBuf>,
+
+    pub source: String,
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    parameters: Vec<String>,
+
+    // Every line between `.macro` and `.endm`, without their newlines
+    body: Vec<Vec<Token>>,
+
+    // Where the name of the macro was written
+    span: Span,
+}
+
+/// Tokenizes the first of `files`, replacing `.include "file.asm"` with the tokens of that file and
+/// expanding macros. Included files are appended to `files`.
+///
+/// Macros are defined with
+///
+/// ```text
+/// .macro name first, second
+///     ...
+/// .endm
+/// ```
+///
+/// and used like instructions, as in `name $0, loop`. Every identifier in the body naming a
+/// parameter is replaced with the tokens passed for it. Arguments are separated by commas, or by
+/// whitespace when each is a single token. Labels defined in the body are renamed on every
+/// expansion so a macro can be used more than once.
+///
+/// Tokens keep the span they were written at, so errors in an expansion point into the body of the
+/// macro, or at the argument passed in.
+pub fn preprocess(files: &mut Vec<SourceFile>) -> Result<Vec<Token>, Vec<AssemblerError>> {
+    let mut preprocessor = Preprocessor {
+        files,
+        macros: HashMap::new(),
+        includes: vec![],
+        expansions: 0,
+        definition: None,
+        runaway: false,
+        recursive: HashSet::new(),
+        tokens: vec![],
+        errors: vec![],
+    };
+
+    if let Some(path) = &preprocessor.files[0].path {
+        if let Ok(path) = fs::canonicalize(path) {
+            preprocessor.includes.push(path);
+        }
+    }
+
+    preprocessor.file(0, 0);
+
+    if !preprocessor.errors.is_empty() {
+        return Err(preprocessor.errors);
+    }
+
+    Ok(preprocessor.tokens)
+}
+
+struct Preprocessor<'a> {
+    files: &'a mut Vec<SourceFile>,
+    macros: HashMap<String, Macro>,
+
+    // The canonical path of every file being preprocessed, innermost last
+    includes: Vec<PathBuf>,
+
+    // The number of macros expanded so far, which keeps the labels of each expansion apart
+    expansions: usize,
+
+    // The name and contents of the macro between `.macro` and `.endm`
+    definition: Option<(String, Macro)>,
+
+    // Set once a macro expands too deep, which abandons every expansion up to the outermost one.
+    // Otherwise a macro using itself twice would expand exponentially often before giving up.
+    runaway: bool,
+
+    // Every macro found expanding too deep, which is reported the first time and not expanded again
+    recursive: HashSet<String>,
+
+    tokens: Vec<Token>,
+    errors: Vec<AssemblerError>,
+}
+
+impl Preprocessor<'_> {
+    fn file(&mut self, file: usize, depth: usize) {
+        let tokens = match lexer::tokenize(&self.files[file].source, file) {
+            Ok(tokens) => tokens,
+            Err(errors) => return self.errors.extend(errors),
+        };
+
+        for line in tokens.split(|token| token.kind == TokenKind::Newline) {
+            self.line(line, depth);
+        }
+
+        // A macro cannot be closed by the file that included it
+        if let Some((name, definition)) = self.definition.take() {
+            self.error(AssemblerErrorKind::UnterminatedMacro(name), definition.span);
+        }
+    }
+
+    fn line(&mut self, line: &[Token], depth: usize) {
+        if let Some((_, definition)) = &mut self.definition {
+            match line.first().map(|token| &token.kind) {
+                Some(TokenKind::Directive(name)) if name == "endm" => self.end_definition(line),
+                Some(TokenKind::Directive(name)) if name == "macro" => self.error(AssemblerErrorKind::NestedMacro, line[0].span),
+                _ => definition.body.push(line.to_vec()),
+            }
+
+            return;
+        }
+
+        // Anything after a label is handled as if it were on a line of its own
+        let (label, rest) = match line {
+            [Token { kind: TokenKind::Identifier(_), .. }, Token { kind: TokenKind::Colon, .. }, rest @ ..] => line.split_at(line.len() - rest.len()),
+            _ => line.split_at(0),
+        };
+
+        let first = match rest.first() {
+            Some(first) => first,
+            None => return self.emit(line),
+        };
+
+        match &first.kind {
+            TokenKind::Directive(name) if name == "macro" => {
+                self.emit(label);
+                self.start_definition(rest);
+            },
+            TokenKind::Directive(name) if name == "endm" => self.error(AssemblerErrorKind::UnmatchedEndm, first.span),
+            TokenKind::Directive(name) if name == "include" => {
+                self.emit(label);
+                self.include(rest, depth);
+            },
+            TokenKind::Identifier(name) if self.macros.contains_key(name) => {
+                self.emit(label);
+                self.expand(rest, depth);
+            },
+            _ => self.emit(line),
+        }
+    }
+
+    // Appends a line of tokens to the output.
+    fn emit(&mut self, line: &[Token]) {
+        let last = match line.last() {
+            Some(last) => last,
+            None => return,
+        };
+
+        let span = Span { column: last.span.column + last.span.len, len: 1, ..last.span };
+
+        self.tokens.extend_from_slice(line);
+        self.tokens.push(Token { kind: TokenKind::Newline, span });
+    }
+
+    fn error(&mut self, kind: AssemblerErrorKind, span: Span) {
+        self.errors.push(AssemblerError::new(kind, span));
+    }
+
+    // Begins collecting the body of the macro named on a `.macro` line.
+    fn start_definition(&mut self, line: &[Token]) {
+        // Without a name the body is still collected, so its lines are not mistaken for code
+        let (name, span) = match line.get(1) {
+            Some(Token { kind: TokenKind::Identifier(name), span }) => (name.clone(), *span),
+            token => {
+                let span = token.unwrap_or(&line[0]).span;
+
+                self.error(AssemblerErrorKind::ExpectedArgument("a macro name"), span);
+
+                (String::new(), span)
+            },
+        };
+
+        let mut parameters = vec![];
+
+        for (i, token) in line[2..].iter().enumerate() {
+            match &token.kind {
+                TokenKind::Identifier(parameter) => parameters.push(parameter.clone()),
+                TokenKind::Comma if i > 0 && i + 3 < line.len() && line[i + 1].kind != TokenKind::Comma => {},
+                kind => self.error(AssemblerErrorKind::UnexpectedToken(kind.to_string()), token.span),
+            }
+        }
+
+        if Opcode::from_instruction(&name).is_some() {
+            self.error(AssemblerErrorKind::MacroShadowsMnemonic(name.clone()), span);
+        }
+
+        self.definition = Some((name, Macro { parameters, body: vec![], span }));
+    }
+
+    fn end_definition(&mut self, line: &[Token]) {
+        let (name, definition) = match self.definition.take() {
+            Some(definition) => definition,
+            None => return,
+        };
+
+        if let Some(token) = line.get(1) {
+            self.error(AssemblerErrorKind::UnexpectedToken(token.kind.to_string()), token.span);
+        }
+
+        // Already reported when the definition started
+        if name.is_empty() || Opcode::from_instruction(&name).is_some() {
+            return;
+        }
+
+        match self.macros.get(&name) {
+            Some(first) => {
+                let kind = AssemblerErrorKind::DuplicateMacro { name, first_line: first.span.line };
+
+                self.error(kind, definition.span);
+            },
+            None => {
+                self.macros.insert(name, definition);
+            },
+        }
+    }
+
+    // Replaces a `.include` line with the lines of the file it names.
+    fn include(&mut self, line: &[Token], depth: usize) {
+        let (name, span) = match line {
+            [_, Token { kind: TokenKind::String(name), span }] => (name, *span),
+            [_, Token { kind: TokenKind::String(_), .. }, token, ..] => {
+                return self.error(AssemblerErrorKind::UnexpectedToken(token.kind.to_string()), token.span);
+            },
+            [_, token, ..] => return self.error(AssemblerErrorKind::ExpectedArgument("a file name in quotes"), token.span),
+            _ => return self.error(AssemblerErrorKind::ExpectedArgument("a file name in quotes"), line[0].span),
+        };
+
+        let path = match self.files[span.file].path.as_ref().and_then(|path| path.parent()) {
+            Some(directory) => directory.join(name),
+            None => PathBuf::from(name),
+        };
+
+        let read = fs::read_to_string(&path).and_then(|source| Ok((source, fs::canonicalize(&path)?)));
+
+        let (source, canonical) = match read {
+            Ok(read) => read,
+            Err(error) => {
+                let kind = AssemblerErrorKind::IncludeFailed { path: path.display().to_string(), reason: error.kind() };
+
+                return self.error(kind, span);
+            },
+        };
+
+        if self.includes.contains(&canonical) {
+            return self.error(AssemblerErrorKind::IncludeCycle(path.display().to_string()), span);
+        }
+
+        self.files.push(SourceFile { name: path.display().to_string(), path: Some(path), source });
+        self.includes.push(canonical);
+
+        self.file(self.files.len() - 1, depth);
+
+        self.includes.pop();
+    }
+
+    // Replaces a use of a macro with its body, substituting the arguments passed in.
+    fn expand(&mut self, line: &[Token], depth: usize) {
+        let (name, span) = match &line[0] {
+            Token { kind: TokenKind::Identifier(name), span } => (name.clone(), *span),
+            _ => return,
+        };
+
+        if self.runaway || self.recursive.contains(&name) {
+            return;
+        }
+
+        if depth >= MAX_MACRO_DEPTH {
+            self.runaway = true;
+            self.recursive.insert(name.clone());
+
+            return self.error(AssemblerErrorKind::MacroRecursion(name), span);
+        }
+
+        let definition = self.macros[&name].clone();
+
+        let arguments = match split_arguments(&line[1..]) {
+            Ok(arguments) => arguments,
+            Err(comma) => return self.error(AssemblerErrorKind::UnexpectedToken(comma.kind.to_string()), comma.span),
+        };
+
+        if arguments.len() != definition.parameters.len() {
+            let kind = AssemblerErrorKind::WrongOperandCount { name, expected: definition.parameters.len(), found: arguments.len() };
+
+            return self.error(kind, span);
+        }
+
+        self.expansions += 1;
+
+        let expansion = self.expansions;
+
+        let labels: HashSet<&String> = definition.body.iter()
+            .filter_map(|line| match line.as_slice() {
+                [Token { kind: TokenKind::Identifier(label), .. }, Token { kind: TokenKind::Colon, .. }, ..] => Some(label),
+                _ => None,
+            })
+            .collect();
+
+        for line in &definition.body {
+            let mut expanded = vec![];
+
+            for token in line {
+                match &token.kind {
+                    TokenKind::Identifier(name) => match definition.parameters.iter().position(|parameter| parameter == name) {
+                        Some(i) => expanded.extend_from_slice(arguments[i]),
+
+                        // `@` cannot appear in source, so the new name cannot clash with another
+                        None if labels.contains(name) => {
+                            expanded.push(Token { kind: TokenKind::Identifier(format!("{}@{}", name, expansion)), span: token.span });
+                        },
+                        None => expanded.push(token.clone()),
+                    },
+                    _ => expanded.push(token.clone()),
+                }
+            }
+
+            self.line(&expanded, depth + 1);
+
+            if self.runaway {
+                break;
+            }
+        }
+
+        // Lines following the outermost use are preprocessed as usual
+        if depth == 0 {
+            self.runaway = false;
+        }
+    }
+}
+
+// Splits the arguments to a macro at commas, or makes each token an argument if there are none.
+// Fails with a comma that does not sit between two arguments.
+fn split_arguments(tokens: &[Token]) -> Result<Vec<&[Token]>, &Token> {
+    if !tokens.iter().any(|token| token.kind == TokenKind::Comma) {
+        return Ok(tokens.chunks(1).collect());
+    }
+
+    let mut arguments = vec![];
+    let mut start = 0;
+
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind == TokenKind::Comma {
+            if i == start {
+                return Err(token);
+            }
+
+            arguments.push(&tokens[start..i]);
+
+            start = i + 1;
+        }
+    }
+
+    if start == tokens.len() {
+        return Err(&tokens[start - 1]);
+    }
+
+    arguments.push(&tokens[start..]);
+
+    Ok(arguments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    fn files(source: &str) -> Vec<SourceFile> {
+        vec![SourceFile { name: "<source>".to_string(), path: None, source: source.to_string() }]
+    }
+
+    // The expanded source, one line per string
+    fn expand(source: &str) -> Vec<String> {
+        let tokens = preprocess(&mut files(source)).unwrap();
+
+        tokens.split(|token| token.kind == TokenKind::Newline)
+            .filter(|line| !line.is_empty())
+            .map(|line| line.iter().map(|token| token.kind.to_string().replace('`', "")).collect::<Vec<_>>().join(" "))
+            .collect()
+    }
+
+    fn errors(source: &str) -> Vec<(Span, AssemblerErrorKind)> {
+        preprocess(&mut files(source)).unwrap_err().into_iter().map(|error| (error.span, error.kind)).collect()
+    }
+
+    #[test]
+    fn expand_macros() {
+        let source = "
+            .macro countdown counter, target
+            again:
+                DEC counter
+                EQ counter $15
+                JEQ $14 target
+                JMP $14 again
+            .endm
+
+            .macro stop
+                HLT
+            .endm
+
+            countdown $0, done
+            start: countdown $1 done
+            done: stop
+        ";
+
+        assert_eq!(expand(source), vec![
+            "again@1 :", "DEC $0", "EQ $0 $15", "JEQ $14 done", "JMP $14 again@1",
+            "start :",
+            "again@2 :", "DEC $1", "EQ $1 $15", "JEQ $14 done", "JMP $14 again@2",
+            "done :", "HLT",
+        ]);
+
+        // Macros may use other macros
+        assert_eq!(expand(".macro inner r\nINC r\n.endm\n.macro outer a, b\ninner a\ninner b\n.endm\nouter $0, $1"), vec!["INC $0", "INC $1"]);
+
+        // Arguments separated by commas may span more than one token
+        assert_eq!(expand(".macro twice a, b\n.byte a\n.byte b\n.endm\ntwice 1 2, 3"), vec![".byte 1 2", ".byte 3"]);
+    }
+
+    #[test]
+    fn point_into_macro_body() {
+        let tokens = preprocess(&mut files(".macro put value\n    SET $0 value\n.endm\n\nput 12")).unwrap();
+
+        // The mnemonic is where the body wrote it, and the value where it was passed in
+        assert_eq!(tokens[0].span, Span { file: 0, line: 2, column: 5, len: 3 });
+        assert_eq!(tokens[2].span, Span { file: 0, line: 5, column: 5, len: 2 });
+    }
+
+    #[test]
+    fn fail_on_bad_macros() {
+        let span = |line, column, len| Span { file: 0, line, column, len };
+
+        assert_eq!(errors(".macro m\nHLT"), vec![(span(1, 8, 1), AssemblerErrorKind::UnterminatedMacro("m".to_string()))]);
+        assert_eq!(errors("HLT\n.endm"), vec![(span(2, 1, 5), AssemblerErrorKind::UnmatchedEndm)]);
+        assert_eq!(errors(".macro m\n.macro n\n.endm"), vec![(span(2, 1, 6), AssemblerErrorKind::NestedMacro)]);
+        assert_eq!(errors(".macro 1\n.endm"), vec![(span(1, 8, 1), AssemblerErrorKind::ExpectedArgument("a macro name"))]);
+        assert_eq!(errors(".macro add\n.endm"), vec![(span(1, 8, 3), AssemblerErrorKind::MacroShadowsMnemonic("add".to_string()))]);
+        assert_eq!(errors(".macro m\n.endm\n.macro m\n.endm"), vec![(span(3, 8, 1), AssemblerErrorKind::DuplicateMacro { name: "m".to_string(), first_line: 1 })]);
+
+        assert_eq!(errors(".macro m a\n.endm\nm 1 2"), vec![
+            (span(3, 1, 1), AssemblerErrorKind::WrongOperandCount { name: "m".to_string(), expected: 1, found: 2 }),
+        ]);
+
+        assert_eq!(errors(".macro m a\n.endm\nm 1,"), vec![(span(3, 4, 1), AssemblerErrorKind::UnexpectedToken("`,`".to_string()))]);
+        assert_eq!(errors(".macro m\nm\n.endm\nm"), vec![(span(2, 1, 1), AssemblerErrorKind::MacroRecursion("m".to_string()))]);
+
+        // A macro using itself more than once gives up at once, with the same error
+        assert_eq!(errors(".macro m\nm\nm\n.endm\nm"), vec![(span(2, 1, 1), AssemblerErrorKind::MacroRecursion("m".to_string()))]);
+
+        // The recursion is reported once however often the macro is used, and what follows is
+        // still checked
+        assert_eq!(errors(".macro m\nm\nm\n.endm\nm\nm\n.endm"), vec![
+            (span(2, 1, 1), AssemblerErrorKind::MacroRecursion("m".to_string())),
+            (span(7, 1, 5), AssemblerErrorKind::UnmatchedEndm),
+        ]);
+
+        let errors = Assembler::default().compile_source("macro.asm", ".macro m\nm\nm\n.endm\nm\nm").unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].span, &errors[0].kind), (span(2, 1, 1), &AssemblerErrorKind::MacroRecursion("m".to_string())));
+    }
+
+    #[test]
+    fn include_files() {
+        let directory = std::env::temp_dir().join(format!("lux-include-{}", std::process::id()));
+
+        fs::create_dir_all(directory.join("lib")).unwrap();
+        fs::write(directory.join("main.asm"), ".include \"lib/halt.asm\"\nstop").unwrap();
+        fs::write(directory.join("lib/halt.asm"), ".macro stop\n    HLT\n.endm").unwrap();
+        fs::write(directory.join("lib/loop.asm"), ".include \"../cycle.asm\"").unwrap();
+        fs::write(directory.join("cycle.asm"), "HLT\n.include \"lib/loop.asm\"").unwrap();
+
+        let mut main = vec![SourceFile { name: "main.asm".to_string(), path: Some(directory.join("main.asm")), source: fs::read_to_string(directory.join("main.asm")).unwrap() }];
+
+        let tokens = preprocess(&mut main).unwrap();
+
+        assert_eq!(main.len(), 2);
+        assert_eq!(tokens[0].kind, TokenKind::Identifier("HLT".to_string()));
+        assert_eq!(tokens[0].span, Span { file: 1, line: 2, column: 5, len: 3 });
+
+        let mut cycle = vec![SourceFile { name: "cycle.asm".to_string(), path: Some(directory.join("cycle.asm")), source: fs::read_to_string(directory.join("cycle.asm")).unwrap() }];
+
+        let errors = preprocess(&mut cycle).unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0].kind, AssemblerErrorKind::IncludeCycle(path) if path.ends_with("cycle.asm")));
+        assert_eq!(errors[0].span.file, 1);
+
+        let errors = preprocess(&mut files(".include \"missing.asm\"")).unwrap_err();
+
+        assert!(matches!(&errors[0].kind, AssemblerErrorKind::IncludeFailed { path, .. } if path == "missing.asm"));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,25 +1,667 @@
-use crate::assembler::header::Header;
+use std::io;
+use std::io::{Read, Write};
 
-#[derive(Default)]
+use byteorder::ByteOrder;
+use byteorder::LittleEndian;
+
+use crate::assembler::checksum::crc32;
+use crate::assembler::debug::{DebugInfo, LineEntry};
+use crate::assembler::header::{narrow, DecodeHeaderError, EncodeError, Header, HeaderErrorKind, SectionHeader, SectionKind, SECTION_LOADED, SECTION_ZEROED};
+use crate::assembler::linker::{Binding, Relocation, Symbol};
+use crate::assembler::section::Section;
+use crate::vm::call::{Export, Signature, ValueType};
+use crate::vm::memory::DEFAULT_MAX_SIZE;
+
+/// A program as stored in a `.lux` file: the header and its section table, followed by the contents
+/// of every section.
+///
+/// Programs straight out of the assembler are also relocatable objects, which `linker::link` can
+/// combine with others through their symbols and relocations.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct Program {
     pub header: Header,
 
     pub read_only: Vec<u8>,
     pub bytecode: Vec<u8>,
+
+    /// The number of zeroed bytes that follow `read_only` on the heap
+    pub bss_size: usize,
+
+    /// Every label relocations refer to, and every exported and imported label
+    pub symbols: Vec<Symbol>,
+
+    /// Every place in `bytecode` and `read_only` holding the address of a symbol
+    pub relocations: Vec<Relocation>,
+
+    /// The largest alignment any section needs
+    pub alignment: usize,
+
+    /// Whether `.entry` or a `main` label chose the entry point, rather than it defaulting to the
+    /// start of the bytecode
+    pub has_entry: bool,
+
+    /// Where the bytecode was assembled from, if the assembler was asked for it
+    pub debug_info: Option<DebugInfo>,
+
+    /// Functions the host can call by name, declared with `.export`
+    pub exports: Vec<Export>,
 }
 
 impl Program {
-    pub fn load() {
-        // TODO: Load from stream
+    /// Reads a `.lux` file from `reader` until it ends, and decodes it.
+    pub fn load<R: Read>(mut reader: R) -> Result<Program, DecodeHeaderError> {
+        let mut bytes = vec![];
+
+        reader.read_to_end(&mut bytes)?;
+
+        Program::decode(&bytes)
+    }
+
+    /// Decodes a program from the contents of a `.lux` file.
+    pub fn decode(bytes: &[u8]) -> Result<Program, DecodeHeaderError> {
+        let header = Header::decode(bytes.to_vec())?;
+
+        let error = |kind| Err(DecodeHeaderError::new(kind));
+
+        let mut program = Program::default();
+
+        for section in &header.sections {
+            if section.flags & SECTION_ZEROED != 0 {
+                match section.kind {
+                    SectionKind::Bss => program.bss_size = section.length,
+                    kind => return error(HeaderErrorKind::InvalidSection(kind)),
+                }
+
+                continue;
+            }
+
+            // Decoding the header made sure the contents are there and intact
+            let contents = &bytes[section.offset..section.offset + section.length];
+
+            match section.kind {
+                SectionKind::ReadOnly => program.read_only = contents.to_vec(),
+                SectionKind::Bytecode => program.bytecode = contents.to_vec(),
+                SectionKind::Symbols => {
+                    if decode_symbols(&mut program, contents).is_none() {
+                        return error(HeaderErrorKind::InvalidSection(SectionKind::Symbols));
+                    }
+                },
+                SectionKind::Debug => match decode_debug_info(contents) {
+                    Some(debug_info) => program.debug_info = Some(debug_info),
+                    None => return error(HeaderErrorKind::InvalidSection(SectionKind::Debug)),
+                },
+                SectionKind::Exports => match decode_exports(contents) {
+                    Some(exports) => program.exports = exports,
+                    None => return error(HeaderErrorKind::InvalidSection(SectionKind::Exports)),
+                },
+
+                SectionKind::Bss => return error(HeaderErrorKind::InvalidSection(SectionKind::Bss)),
+            }
+        }
+
+        if header.entry_point > program.bytecode.len() {
+            return error(HeaderErrorKind::InvalidEntryPoint);
+        }
+
+        if program.exports.iter().any(|export| export.offset >= program.bytecode.len()) {
+            return error(HeaderErrorKind::InvalidSection(SectionKind::Exports));
+        }
+
+        // Checked once every section is read, as the symbols may come before the places they patch
+        let outside = |relocation: &Relocation| {
+            let len = match relocation.section {
+                Section::Text => program.bytecode.len(),
+                _ => program.read_only.len(),
+            };
+
+            relocation.offset + relocation.size > len
+        };
+
+        if program.relocations.iter().any(outside) {
+            return error(HeaderErrorKind::InvalidSection(SectionKind::Symbols));
+        }
+
+        // Checked here rather than when booting, so a corrupted size cannot exhaust memory
+        if program.read_only.len() + program.bss_size > DEFAULT_MAX_SIZE {
+            return error(HeaderErrorKind::TooLarge);
+        }
+
+        program.header = header;
+
+        Ok(program)
+    }
+
+    /// Encodes the program as the contents of a `.lux` file. The section table in the header is
+    /// filled in from the program itself. Symbols, debug info and exports are only written for
+    /// programs that have any. Fails if a value is too large for the field holding it.
+    pub fn bytes(&self) -> Result<Vec<u8>, EncodeError> {
+        let mut contents = vec![
+            (SectionKind::ReadOnly, SECTION_LOADED, self.read_only.clone()),
+            (SectionKind::Bytecode, SECTION_LOADED, self.bytecode.clone()),
+        ];
+
+        if !self.symbols.is_empty() || !self.relocations.is_empty() {
+            contents.push((SectionKind::Symbols, 0, encode_symbols(self)?));
+        }
+
+        if let Some(debug_info) = &self.debug_info {
+            contents.push((SectionKind::Debug, 0, encode_debug_info(debug_info)?));
+        }
+
+        if !self.exports.is_empty() {
+            contents.push((SectionKind::Exports, 0, encode_exports(&self.exports)?));
+        }
+
+        let mut header = self.header.clone();
+
+        header.sections = contents.iter().map(|(kind, flags, bytes)| {
+            SectionHeader { kind: *kind, flags: *flags, offset: 0, length: bytes.len(), checksum: crc32(bytes) }
+        }).collect();
+
+        if self.bss_size > 0 {
+            header.sections.push(SectionHeader { kind: SectionKind::Bss, flags: SECTION_LOADED | SECTION_ZEROED, offset: 0, length: self.bss_size, checksum: 0 });
+        }
+
+        // Contents follow the section table in order
+        let mut offset = header.encoded_len();
+
+        for (section, (_, _, bytes)) in header.sections.iter_mut().zip(&contents) {
+            section.offset = offset;
+
+            offset += bytes.len();
+        }
+
+        let mut bytes = header.bytes()?;
+
+        for (_, _, contents) in contents {
+            bytes.extend_from_slice(&contents);
+        }
+
+        Ok(bytes)
+    }
+
+    /// Writes the program to `writer` as a `.lux` file. Programs that cannot be encoded fail with
+    /// `InvalidData`, before anything is written.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.bytes()?)
     }
 }
 
+// Reads little endian values from the contents of a section, returning `None` once they run out.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.bytes.len() {
+            return None;
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+
+        self.bytes = rest;
+
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(LittleEndian::read_u16(self.take(2)?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(LittleEndian::read_u32(self.take(4)?))
+    }
+
+    fn i64(&mut self) -> Option<i64> {
+        Some(LittleEndian::read_i64(self.take(8)?))
+    }
+}
+
+const SECTIONS: [Section; 4] = [Section::Text, Section::ReadOnly, Section::Data, Section::Bss];
+const BINDINGS: [Binding; 3] = [Binding::Local, Binding::Exported, Binding::Imported];
+const VALUE_TYPES: [ValueType; 2] = [ValueType::I32, ValueType::F64];
+
+// The largest alignment an object may ask for, which is far more than any section needs
+const MAX_ALIGNMENT: usize = 4096;
+
+// The symbols section holds the alignment and a byte that is 1 if the object chose its entry
+// point, then the symbols and then the relocations, each preceded by their count:
+//
+//   symbol:     binding u8, section u8, address u32, name length u16, name
+//   relocation: section u8, size u8, offset u32, symbol u32, addend i64
+//
+// Sections and bindings are stored as their index in `SECTIONS` and `BINDINGS`.
+fn encode_symbols(program: &Program) -> Result<Vec<u8>, EncodeError> {
+    let section = Some(SectionKind::Symbols);
+    let index = |section| SECTIONS.iter().position(|&other| other == section).unwrap() as u8;
+
+    let mut bytes = vec![];
+
+    bytes.extend_from_slice(&narrow::<u32>(program.alignment, section)?.to_le_bytes());
+    bytes.push(program.has_entry as u8);
+    bytes.extend_from_slice(&narrow::<u32>(program.symbols.len(), section)?.to_le_bytes());
+
+    for symbol in &program.symbols {
+        bytes.push(BINDINGS.iter().position(|&binding| binding == symbol.binding).unwrap() as u8);
+        bytes.push(index(symbol.section));
+        bytes.extend_from_slice(&narrow::<u32>(symbol.address, section)?.to_le_bytes());
+        bytes.extend_from_slice(&narrow::<u16>(symbol.name.len(), section)?.to_le_bytes());
+        bytes.extend_from_slice(symbol.name.as_bytes());
+    }
+
+    bytes.extend_from_slice(&narrow::<u32>(program.relocations.len(), section)?.to_le_bytes());
+
+    for relocation in &program.relocations {
+        bytes.push(index(relocation.section));
+        bytes.push(narrow(relocation.size, section)?);
+        bytes.extend_from_slice(&narrow::<u32>(relocation.offset, section)?.to_le_bytes());
+        bytes.extend_from_slice(&narrow::<u32>(relocation.symbol, section)?.to_le_bytes());
+        bytes.extend_from_slice(&relocation.addend.to_le_bytes());
+    }
+
+    Ok(bytes)
+}
+
+// Decodes the contents of the symbols section into `program`, returning `None` if they are invalid.
+fn decode_symbols(program: &mut Program, bytes: &[u8]) -> Option<()> {
+    let mut reader = Reader { bytes };
+
+    program.alignment = reader.u32()? as usize;
+
+    // Zero when nothing asked for an alignment
+    if program.alignment > MAX_ALIGNMENT || (program.alignment != 0 && !program.alignment.is_power_of_two()) {
+        return None;
+    }
+
+    program.has_entry = match reader.u8()? {
+        0 => false,
+        1 => true,
+        _ => return None,
+    };
+
+    for _ in 0..reader.u32()? {
+        let binding = *BINDINGS.get(reader.u8()? as usize)?;
+        let section = *SECTIONS.get(reader.u8()? as usize)?;
+        let address = reader.u32()? as usize;
+
+        let length = reader.u16()? as usize;
+        let name = String::from_utf8(reader.take(length)?.to_vec()).ok()?;
+
+        program.symbols.push(Symbol { name, binding, section, address });
+    }
+
+    for _ in 0..reader.u32()? {
+        let section = *SECTIONS.get(reader.u8()? as usize)?;
+        let size = reader.u8()? as usize;
+        let offset = reader.u32()? as usize;
+        let symbol = reader.u32()? as usize;
+        let addend = reader.i64()?;
+
+        if symbol >= program.symbols.len() || ![1, 2, 4, 8].contains(&size) {
+            return None;
+        }
+
+        program.relocations.push(Relocation { section, offset, size, symbol, addend });
+    }
+
+    if !reader.bytes.is_empty() {
+        return None;
+    }
+
+    Some(())
+}
+
+// The debug section holds the file names, then the line table and then the labels, each preceded
+// by their count:
+//
+//   file:  name length u16, name
+//   line:  offset u32, file u16, line u32, column u32
+//   label: offset u32, name length u16, name
+pub(crate) fn encode_debug_info(debug_info: &DebugInfo) -> Result<Vec<u8>, EncodeError> {
+    let section = Some(SectionKind::Debug);
+
+    let mut bytes = vec![];
+
+    let name = |bytes: &mut Vec<u8>, name: &str| {
+        bytes.extend_from_slice(&narrow::<u16>(name.len(), section)?.to_le_bytes());
+        bytes.extend_from_slice(name.as_bytes());
+
+        Ok(())
+    };
+
+    bytes.extend_from_slice(&narrow::<u32>(debug_info.files.len(), section)?.to_le_bytes());
+
+    for file in &debug_info.files {
+        name(&mut bytes, file)?;
+    }
+
+    bytes.extend_from_slice(&narrow::<u32>(debug_info.lines.len(), section)?.to_le_bytes());
+
+    for entry in &debug_info.lines {
+        bytes.extend_from_slice(&narrow::<u32>(entry.offset, section)?.to_le_bytes());
+        bytes.extend_from_slice(&narrow::<u16>(entry.file, section)?.to_le_bytes());
+        bytes.extend_from_slice(&narrow::<u32>(entry.line, section)?.to_le_bytes());
+        bytes.extend_from_slice(&narrow::<u32>(entry.column, section)?.to_le_bytes());
+    }
+
+    bytes.extend_from_slice(&narrow::<u32>(debug_info.labels.len(), section)?.to_le_bytes());
+
+    for (label, offset) in &debug_info.labels {
+        bytes.extend_from_slice(&narrow::<u32>(*offset, section)?.to_le_bytes());
+        name(&mut bytes, label)?;
+    }
+
+    Ok(bytes)
+}
+
+// Decodes the contents of the debug section, returning `None` if they are invalid.
+pub(crate) fn decode_debug_info(bytes: &[u8]) -> Option<DebugInfo> {
+    let mut reader = Reader { bytes };
+    let mut debug_info = DebugInfo::default();
+
+    let name = |reader: &mut Reader| {
+        let length = reader.u16()? as usize;
+
+        String::from_utf8(reader.take(length)?.to_vec()).ok()
+    };
+
+    for _ in 0..reader.u32()? {
+        debug_info.files.push(name(&mut reader)?);
+    }
+
+    for _ in 0..reader.u32()? {
+        let offset = reader.u32()? as usize;
+        let file = reader.u16()? as usize;
+        let line = reader.u32()? as usize;
+        let column = reader.u32()? as usize;
+
+        if file >= debug_info.files.len() {
+            return None;
+        }
+
+        debug_info.lines.push(LineEntry { offset, file, line, column });
+    }
+
+    for _ in 0..reader.u32()? {
+        let offset = reader.u32()? as usize;
+
+        debug_info.labels.push((name(&mut reader)?, offset));
+    }
+
+    // Lookups rely on both being sorted
+    let is_sorted = |offsets: Vec<usize>| offsets.windows(2).all(|pair| pair[0] <= pair[1]);
+
+    if !reader.bytes.is_empty() || !is_sorted(debug_info.lines.iter().map(|entry| entry.offset).collect()) || !is_sorted(debug_info.labels.iter().map(|(_, offset)| *offset).collect()) {
+        return None;
+    }
+
+    Some(debug_info)
+}
+
+// The exports section holds the number of functions, followed by every function:
+//
+//   function: offset u32, name length u16, name, parameter count u8, parameters,
+//             result count u8, results
+//
+// Parameter and result types are stored as a byte holding their index in `VALUE_TYPES`.
+pub(crate) fn encode_exports(exports: &[Export]) -> Result<Vec<u8>, EncodeError> {
+    let section = Some(SectionKind::Exports);
+
+    let mut bytes = vec![];
+
+    let types = |bytes: &mut Vec<u8>, types: &[ValueType]| {
+        bytes.push(narrow(types.len(), section)?);
+        bytes.extend(types.iter().map(|&value_type| VALUE_TYPES.iter().position(|&other| other == value_type).unwrap() as u8));
+
+        Ok(())
+    };
+
+    bytes.extend_from_slice(&narrow::<u32>(exports.len(), section)?.to_le_bytes());
+
+    for export in exports {
+        bytes.extend_from_slice(&narrow::<u32>(export.offset, section)?.to_le_bytes());
+        bytes.extend_from_slice(&narrow::<u16>(export.name.len(), section)?.to_le_bytes());
+        bytes.extend_from_slice(export.name.as_bytes());
+
+        types(&mut bytes, &export.signature.params)?;
+        types(&mut bytes, &export.signature.results)?;
+    }
+
+    Ok(bytes)
+}
+
+// Decodes the contents of the exports section, returning `None` if they are invalid.
+pub(crate) fn decode_exports(bytes: &[u8]) -> Option<Vec<Export>> {
+    let mut reader = Reader { bytes };
+    let mut exports = vec![];
+
+    let types = |reader: &mut Reader| -> Option<Vec<ValueType>> {
+        (0..reader.u8()?).map(|_| VALUE_TYPES.get(reader.u8()? as usize).copied()).collect()
+    };
+
+    for _ in 0..reader.u32()? {
+        let offset = reader.u32()? as usize;
+
+        let length = reader.u16()? as usize;
+        let name = String::from_utf8(reader.take(length)?.to_vec()).ok()?;
+
+        let signature = Signature { params: types(&mut reader)?, results: types(&mut reader)? };
+
+        if !signature.fits_registers() || exports.iter().any(|export: &Export| export.name == name) {
+            return None;
+        }
+
+        exports.push(Export { name, offset, signature });
+    }
+
+    if !reader.bytes.is_empty() {
+        return None;
+    }
+
+    Some(exports)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::assembler::header::{HEADER_LENGTH, SECTION_ENTRY_LENGTH};
 
     #[test]
     fn create_program() {
         Program::default();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn encode_then_decode_program() {
+        let mut program = Program::default();
+
+        program.header.entry_point = 4;
+        program.read_only = vec![1, 2, 3];
+        program.bytecode = vec![4, 5, 6, 7, 8];
+        program.bss_size = 16;
+
+        let mut file = vec![];
+
+        program.write(&mut file).unwrap();
+
+        // Read only data, bytecode and bss
+        let header_len = HEADER_LENGTH + 3 * SECTION_ENTRY_LENGTH;
+
+        assert_eq!(file.len(), header_len + 8);
+        assert_eq!(file[header_len..], [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let decoded = Program::load(&file[..]).unwrap();
+
+        assert_eq!(decoded.header.entry_point, 4);
+        assert_eq!(decoded.header.sections, vec![
+            SectionHeader { kind: SectionKind::ReadOnly, flags: SECTION_LOADED, offset: header_len, length: 3, checksum: crc32(&[1, 2, 3]) },
+            SectionHeader { kind: SectionKind::Bytecode, flags: SECTION_LOADED, offset: header_len + 3, length: 5, checksum: crc32(&[4, 5, 6, 7, 8]) },
+            SectionHeader { kind: SectionKind::Bss, flags: SECTION_LOADED | SECTION_ZEROED, offset: 0, length: 16, checksum: 0 },
+        ]);
+        assert_eq!(decoded.read_only, program.read_only);
+        assert_eq!(decoded.bytecode, program.bytecode);
+        assert_eq!(decoded.bss_size, 16);
+    }
+
+    #[test]
+    fn encode_then_decode_symbols() {
+        let mut program = Program::default();
+
+        program.bytecode = vec![0; 4];
+        program.read_only = vec![0; 16];
+        program.alignment = 16;
+        program.has_entry = true;
+        program.symbols = vec![
+            Symbol { name: "main".to_string(), binding: Binding::Exported, section: Section::Text, address: 2 },
+            Symbol { name: "buffer".to_string(), binding: Binding::Imported, section: Section::Text, address: 0 },
+            Symbol { name: "table".to_string(), binding: Binding::Local, section: Section::Bss, address: 8 },
+        ];
+        program.relocations = vec![
+            Relocation { section: Section::Text, offset: 1, size: 2, symbol: 1, addend: -4 },
+            Relocation { section: Section::Data, offset: 8, size: 8, symbol: 2, addend: 0 },
+        ];
+
+        let file = program.bytes().unwrap();
+        let decoded = Program::decode(&file).unwrap();
+
+        assert_eq!(decoded.alignment, 16);
+        assert!(decoded.has_entry);
+        assert_eq!(decoded.symbols, program.symbols);
+        assert_eq!(decoded.relocations, program.relocations);
+
+        let invalid = |program: &Program| Program::decode(&program.bytes().unwrap()).unwrap_err().kind() == &HeaderErrorKind::InvalidSection(SectionKind::Symbols);
+
+        // A relocation pointing past the symbol table
+        program.relocations[1].symbol = 3;
+
+        assert!(invalid(&program));
+
+        // Relocations patching bytes past the end of their section
+        program.relocations[1].symbol = 2;
+        program.relocations[0].offset = 3;
+
+        assert!(invalid(&program));
+
+        program.relocations[0].offset = 1;
+        program.relocations[1].offset = 9;
+
+        assert!(invalid(&program));
+
+        // Alignments that are not powers of two, or absurdly large
+        program.relocations[1].offset = 8;
+        program.alignment = 12;
+
+        assert!(invalid(&program));
+
+        program.alignment = 1 << 20;
+
+        assert!(invalid(&program));
+
+        program.alignment = 16;
+
+        assert!(Program::decode(&program.bytes().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn fail_to_encode_values_too_large() {
+        let mut program = Program::default();
+
+        program.symbols = vec![
+            Symbol { name: "a".repeat(usize::from(u16::MAX) + 1), binding: Binding::Exported, section: Section::Text, address: 0 },
+        ];
+
+        assert_eq!(program.bytes().unwrap_err().section(), Some(SectionKind::Symbols));
+
+        // Nothing is written
+        let mut file = vec![];
+
+        assert_eq!(program.write(&mut file).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(file.is_empty());
+
+        program.symbols.clear();
+        program.header.entry_point = 1 << 32;
+
+        assert_eq!(program.bytes().unwrap_err().section(), None);
+    }
+
+    #[test]
+    fn encode_then_decode_debug_info() {
+        let mut program = Program::default();
+
+        program.bytecode = vec![0; 8];
+        program.debug_info = Some(DebugInfo {
+            files: vec!["main.asm".to_string(), "lib.asm".to_string()],
+            lines: vec![
+                LineEntry { offset: 0, file: 0, line: 3, column: 5 },
+                LineEntry { offset: 4, file: 1, line: 1, column: 1 },
+            ],
+            labels: vec![("main".to_string(), 0), ("add_one".to_string(), 4)],
+        });
+
+        let decoded = Program::decode(&program.bytes().unwrap()).unwrap();
+
+        assert_eq!(decoded.debug_info, program.debug_info);
+
+        // A line pointing past the file names
+        program.debug_info.as_mut().unwrap().lines[1].file = 2;
+
+        assert_eq!(Program::decode(&program.bytes().unwrap()).unwrap_err().kind(), &HeaderErrorKind::InvalidSection(SectionKind::Debug));
+    }
+
+    #[test]
+    fn encode_then_decode_exports() {
+        let mut program = Program::default();
+
+        program.bytecode = vec![0; 8];
+        program.exports = vec![
+            Export { name: "checksum".to_string(), offset: 2, signature: Signature::parse("i32, f64 -> i32").unwrap() },
+            Export { name: "reset".to_string(), offset: 7, signature: Signature::default() },
+        ];
+
+        let decoded = Program::decode(&program.bytes().unwrap()).unwrap();
+
+        assert_eq!(decoded.exports, program.exports);
+
+        // A function starting past the end of the bytecode
+        program.exports[1].offset = 8;
+
+        assert_eq!(Program::decode(&program.bytes().unwrap()).unwrap_err().kind(), &HeaderErrorKind::InvalidSection(SectionKind::Exports));
+    }
+
+    #[test]
+    fn fail_on_malformed_program() {
+        let mut program = Program::default();
+
+        program.read_only = vec![1, 2, 3];
+        program.bytecode = vec![4, 5];
+
+        let kind = |bytes: &[u8]| Program::load(bytes).unwrap_err().kind().clone();
+
+        let file = program.bytes().unwrap();
+
+        assert_eq!(kind(&file[..HEADER_LENGTH - 1]), HeaderErrorKind::Truncated);
+        assert_eq!(kind(&file[..HEADER_LENGTH + 2]), HeaderErrorKind::Truncated);
+        assert_eq!(kind(&file[..file.len() - 1]), HeaderErrorKind::Truncated);
+        assert_eq!(kind(b"lux"), HeaderErrorKind::Truncated);
+        assert_eq!(kind(b"#!/bin/sh"), HeaderErrorKind::MagicNumber);
+
+        // A single flipped bit is caught, and names the section it is in
+        let mut corrupted = file.clone();
+        let last = corrupted.len() - 1;
+
+        corrupted[last] ^= 1;
+        assert_eq!(kind(&corrupted), HeaderErrorKind::Corrupted(SectionKind::Bytecode));
+
+        program.header.entry_point = 3;
+        assert_eq!(kind(&program.bytes().unwrap()), HeaderErrorKind::InvalidEntryPoint);
+
+        program.header.entry_point = 0;
+        program.bss_size = u32::MAX as usize;
+        assert_eq!(kind(&program.bytes().unwrap()), HeaderErrorKind::TooLarge);
+    }
+}
@@ -0,0 +1,155 @@
+/// Where assembled output goes. Code lives in `Text`, which becomes the program's bytecode. The
+/// other sections are laid out on the heap one after the other, starting at address 0 with
+/// `ReadOnly`, so their labels resolve to heap addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Section {
+    Text,
+    ReadOnly,
+    Data,
+    Bss,
+}
+
+/// Heap sections start at a multiple of this, or of the largest `.align` used in them if larger.
+pub const SECTION_ALIGNMENT: usize = 8;
+
+pub fn align_up(value: usize, alignment: usize) -> usize {
+    value.div_ceil(alignment) * alignment
+}
+
+/// The output of every section during a single pass over the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sections {
+    pub text: Vec<u8>,
+    pub read_only: Vec<u8>,
+    pub data: Vec<u8>,
+
+    /// `.bss` is never written, so only its size is tracked
+    pub bss: usize,
+
+    // The largest alignment requested in `.data` and `.bss`
+    data_alignment: usize,
+    bss_alignment: usize,
+
+    // The largest alignment requested in any section
+    alignment: usize,
+}
+
+impl Default for Sections {
+    fn default() -> Self {
+        Sections {
+            text: vec![],
+            read_only: vec![],
+            data: vec![],
+            bss: 0,
+            data_alignment: SECTION_ALIGNMENT,
+            bss_alignment: SECTION_ALIGNMENT,
+            alignment: SECTION_ALIGNMENT,
+        }
+    }
+}
+
+impl Sections {
+    /// The number of bytes assembled into `section` so far.
+    pub fn len(&self, section: Section) -> usize {
+        match section {
+            Section::Text => self.text.len(),
+            Section::ReadOnly => self.read_only.len(),
+            Section::Data => self.data.len(),
+            Section::Bss => self.bss,
+        }
+    }
+
+    /// The contents of `section`, or `None` for `.bss`, which holds no contents.
+    pub fn bytes_mut(&mut self, section: Section) -> Option<&mut Vec<u8>> {
+        match section {
+            Section::Text => Some(&mut self.text),
+            Section::ReadOnly => Some(&mut self.read_only),
+            Section::Data => Some(&mut self.data),
+            Section::Bss => None,
+        }
+    }
+
+    /// Pads `section` with zeros up to a multiple of `alignment`, which must be a power of two.
+    pub fn align(&mut self, section: Section, alignment: usize) {
+        let padding = align_up(self.len(section), alignment) - self.len(section);
+
+        self.space(section, padding);
+
+        match section {
+            Section::Data => self.data_alignment = self.data_alignment.max(alignment),
+            Section::Bss => self.bss_alignment = self.bss_alignment.max(alignment),
+            _ => { },
+        }
+
+        self.alignment = self.alignment.max(alignment);
+    }
+
+    /// The largest alignment any section needs. Moving the bytecode or the heap by a multiple of it
+    /// keeps everything aligned.
+    pub fn alignment(&self) -> usize {
+        self.alignment
+    }
+
+    /// Appends `size` zeroed bytes to `section`.
+    pub fn space(&mut self, section: Section, size: usize) {
+        match self.bytes_mut(section) {
+            Some(bytes) => bytes.resize(bytes.len() + size, 0),
+            None => self.bss += size,
+        }
+    }
+
+    /// The address `section` starts at: a byte offset into the bytecode for `.text`, and a heap
+    /// address for everything else.
+    pub fn base(&self, section: Section) -> usize {
+        match section {
+            Section::Text | Section::ReadOnly => 0,
+            Section::Data => align_up(self.read_only.len(), self.data_alignment),
+            Section::Bss => align_up(self.base(Section::Data) + self.data.len(), self.bss_alignment),
+        }
+    }
+
+    /// The initial contents of the heap: `.rodata` followed by `.data`.
+    pub fn heap(&self) -> Vec<u8> {
+        let mut heap = self.read_only.clone();
+
+        heap.resize(self.base(Section::Data), 0);
+        heap.extend_from_slice(&self.data);
+
+        heap
+    }
+
+    /// The number of zeroed bytes following `heap` that hold `.bss`, including any padding.
+    pub fn bss_size(&self) -> usize {
+        let bss_size = self.base(Section::Bss) + self.bss - self.base(Section::Data) - self.data.len();
+
+        if self.bss == 0 { 0 } else { bss_size }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lay_out_sections() {
+        let mut sections = Sections::default();
+
+        sections.read_only.extend_from_slice(b"abc");
+        sections.data.push(1);
+        sections.align(Section::Data, 16);
+        sections.data.push(2);
+        sections.space(Section::Bss, 4);
+
+        assert_eq!(sections.base(Section::Data), 16);
+        assert_eq!(sections.base(Section::Bss), 40);
+
+        let heap = sections.heap();
+
+        assert_eq!(heap.len(), 33);
+        assert_eq!(&heap[..4], b"abc\0");
+        assert_eq!((heap[16], heap[32]), (1, 2));
+
+        assert_eq!(sections.bss_size(), 11);
+        assert_eq!(sections.alignment(), 16);
+    }
+}
@@ -1,9 +1,25 @@
+// Programs and VMs are usually built from a default and then filled in field by field.
+#![allow(clippy::field_reassign_with_default)]
+
+// Assembler errors carry the source line they point at. They are only built on the way out of a
+// failed compile, so their size does not matter.
+#![allow(clippy::result_large_err)]
+
 pub mod vm;
 pub mod assembler;
 
+use std::env;
+use std::fs::File;
 use std::io;
 use std::io::prelude::*;
-
+use std::path::Path;
+use std::process;
+
+use crate::assembler::Assembler;
+use crate::assembler::debug::Tracer;
+use crate::assembler::disassembler;
+use crate::assembler::linker;
+use crate::assembler::program::Program;
 use crate::vm::VM;
 use crate::vm::instructions::Opcode;
 
@@ -13,12 +29,41 @@ pub static BANNER: &str = "Rust Register-based Virtual Machine v1. Type .help fo
 pub static PROMPT: &str = ">>> ";
 
 fn main() {
+    let (flags, args): (Vec<String>, Vec<String>) = env::args().skip(1).partition(|arg| arg.starts_with('-'));
+
+    // `-g` keeps debug info when building, and `--trace` prints every instruction when running
+    let mut debug_info = false;
+    let mut trace = false;
+
+    for flag in &flags {
+        match flag.as_str() {
+            "-g" => debug_info = true,
+            "--trace" => trace = true,
+            _ => {
+                eprintln!("error: unknown flag `{}`", flag);
+
+                process::exit(1);
+            },
+        }
+    }
+
+    // `language program.lux` runs a program, and `language main.asm lib.asm [program.lux]` assembles
+    // and links files, instead of starting the REPL
+    match args.as_slice() {
+        [] => { },
+        [program] if program.ends_with(".lux") => process::exit(run(program, trace)),
+        _ => process::exit(build(&args, debug_info)),
+    }
+
     println!("{}", BANNER);
 
     let mut vm = VM::default();
 
     let mut show_registers = true;
 
+    // Every `.equ` entered so far, assembled in front of each line so its constants can be used
+    let mut definitions = String::new();
+
     'main: loop {
         if show_registers {
             println!("------------------------------------------------------------------------------------------");
@@ -63,60 +108,160 @@ fn main() {
                 break 'main;
             } else if command == "registers" {
                 show_registers = true;
+            } else if command == "program" {
+                print!("{}", disassembler::render(&disassembler::disassemble(vm.program())));
+            } else if command == "equ" {
+                match Assembler::default().compile_source("<repl>", &format!("{}{}", definitions, input)) {
+                    Ok(()) => definitions.push_str(input),
+                    Err(errors) => errors.iter().for_each(|error| println!("{}", error)),
+                }
             } else if command == "help" {
-                if args.len() > 0 {
-                    let op = Opcode::from(args[0].to_uppercase());
-                    
-                    println!("{}: {}", op.name(), op.info());
+                if !args.is_empty() {
+                    match Opcode::from_instruction(args[0]) {
+                        Some(op) => println!("{}: {}", op.usage(), op.info()),
+                        None => println!("Unknown instruction: {}", args[0]),
+                    }
                 } else {
                     println!("List of all Opcodes:");
                     for op in Opcode::all() {
-                        println!("  {}: {}", op.instruction(), op.info());
+                        println!("  {}: {}", op.usage(), op.info());
                     }
                 }
             } else {
                 println!("Unknown command: {}", command);
             }
         } else {
-            let tokens: Vec<&str> = input.split_whitespace().collect();
-    
-            let op = Opcode::from(tokens[0].to_uppercase());
-    
-            print!("{} ", op.instruction());
-            
-            let mut bytes: Vec<u8> = vec![ op.byte() ];
-    
-            for arg in &tokens[1..] {
-                let result = u8::from_str_radix(&arg, 16);
-    
-                match result {
-                    Err(e) => {
-                        println!("<{}>", e);
-                        continue 'main;
-                    },
-                    Ok(byte) => {
-                        print!("<{:#04x}> ", byte);
-    
-                        bytes.append(&mut vec![ byte ]);
-                    }
+            let mut assembler = Assembler::default();
+
+            if let Err(errors) = assembler.compile_source("<repl>", &format!("{}{}", definitions, input)) {
+                for error in errors {
+                    println!("{}", error);
                 }
+
+                continue 'main;
+            }
+
+            let mut bytes = assembler.result.bytecode;
+
+            for byte in &bytes {
+                print!("<{:#04x}> ", byte);
             }
 
-            vm.program.append(&mut bytes);
+            vm.program_mut().append(&mut bytes);
     
             println!();
     
-            vm.run();
+            if let Err(trap) = vm.run() {
+                println!("{}", trap);
+
+                // Drop the faulting instruction so the next one can run
+                vm.program_mut().truncate(trap.pc);
+            }
+        }
+    }
+}
+
+// Loads the program at `path` and runs it, printing the registers once it stops. With `trace`, every
+// instruction is printed to stderr as it executes. Returns the exit code.
+fn run(path: &str, trace: bool) -> i32 {
+    let program = match File::open(path).map_err(Into::into).and_then(Program::load) {
+        Ok(program) => program,
+        Err(error) => {
+            eprintln!("error: {}\n --> {}", error, path);
+
+            if let Some(hint) = error.kind().hint() {
+                eprintln!("  = help: {}", hint);
+            }
+
+            return 1;
+        },
+    };
+
+    let mut vm = VM::from_program(program);
+
+    if trace {
+        vm.set_observer(Tracer::new(vm.debug_info.clone().unwrap_or_default(), io::stderr()));
+    }
+
+    let result = vm.run();
+
+    println!("Registers: {:?}", vm.registers);
+
+    match result {
+        Ok(_) => 0,
+        Err(trap) => {
+            eprintln!("{}", vm.describe_trap(&trap));
+
+            1
+        },
+    }
+}
+
+// Assembles every source file in `args` and links them into a `.lux` file, named by an argument
+// ending in `.lux` or else after the first source. Execution starts at `.entry` or `main` in
+// whichever source has one. With `debug_info`, the program keeps the debug info of every source.
+// Returns the exit code.
+fn build(args: &[String], debug_info: bool) -> i32 {
+    let (outputs, inputs): (Vec<&String>, Vec<&String>) = args.iter().partition(|arg| arg.ends_with(".lux"));
+
+    let output = match (outputs.first(), inputs.first()) {
+        (Some(output), _) => Path::new(output).to_path_buf(),
+        (None, Some(input)) => Path::new(input).with_extension("lux"),
+        (None, None) => {
+            eprintln!("error: no source files given");
+
+            return 1;
+        },
+    };
+
+    let mut objects = vec![];
+    let mut failed = false;
+
+    for input in &inputs {
+        let mut assembler = Assembler::default();
+
+        assembler.emit_debug_info = debug_info;
+
+        match assembler.compile_file(input) {
+            Ok(()) => objects.push(assembler.result),
+            Err(errors) => {
+                errors.iter().for_each(|error| eprintln!("{}", error));
+
+                failed = true;
+            },
         }
     }
+
+    if failed {
+        return 1;
+    }
+
+    let program = match linker::link(&objects) {
+        Ok(program) => program,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("error: {}\n --> {}", error, inputs[error.object]);
+            }
+
+            return 1;
+        },
+    };
+
+    if let Err(error) = File::create(&output).and_then(|file| program.write(file)) {
+        eprintln!("error: could not write {}: {}", output.display(), error);
+
+        return 1;
+    }
+
+    0
 }
 
 #[cfg(test)]
 mod tests {
     use std::time;
     
+    use crate::assembler::Assembler;
     use crate::vm::VM;
-    use crate::vm::instructions::Opcode;
 
     #[test]
     pub fn fib() {
@@ -147,50 +292,61 @@ mod tests {
         
         println!("Rust took: {:.2?}ns", (totals.iter().sum::<u128>() / totals.len() as u128));
 
-        let mut totals: Vec<u128> = vec![];
-        
-        for _i in 0..TIMES {
-            let mut test_vm = VM::default();
+        let mut assembler = Assembler::default();
 
-            test_vm.program = vec![
-                Opcode::Set.byte(), 0, 1, 0,    // Set $0 to 1
-                Opcode::JumpForward.byte(), 0,  // Jump forward $0 bytes
-                Opcode::Halt.byte(),
+        assembler.compile(&format!("
+                SET $0 done         ; Jump targets are loaded once, outside of the loop
+                SET $6 loop
 
-                Opcode::Set.byte(), 0, 6, 0,   // Load $0 with 6: byte of the halt instruction
+                SET $1 0            ; Current iteration
+                SET $2 {}           ; Last iteration
 
-                Opcode::Set.byte(), 1, 0, 0,   // Reset $1 to 0
-                Opcode::Set.byte(), 2, MAX_ITERATIONS - 1, 0,
+                SET $3 0
+                SET $4 1
 
-                Opcode::Set.byte(), 3, 0, 0,   // Sets $3 to 0
-                Opcode::Set.byte(), 4, 1, 0,   // Sets $4 to 1
-                
-                Opcode::Set.byte(), 6, 31, 0,  // Load $6 with 29: the start of the iteration
+            loop:
+                EQ $1 $2
+                JEQ $0              ; Stop once $1 reaches $2
 
-                // Start of fib iterations
-                Opcode::Equal.byte(), 1, 2,     // Check if $1 (current) and $2 (max) are equal
-                Opcode::JumpIfEqual.byte(), 0,  // Jump to byte $0 if equal flag is set
+                INC $1
+                ADD $5 $3 $4        ; $5 = $3 + $4
+                MOV $3 $4           ; $3 = $4
+                MOV $4 $5           ; $4 = $5
 
-                Opcode::Increment.byte(), 1,    // Increment $1
-                Opcode::Add.byte(), 5, 3, 4,    // $5 = $3 + $4
-                Opcode::Move.byte(), 3, 4,      // $3 = $4
-                Opcode::Move.byte(), 4, 5,      // $4 = $5
-                
-                Opcode::Jump.byte(), 6,         // Jump to byte $6
-            ];
+                JMP $6
 
-            let before = time::Instant::now();
+            done:
+                HLT
+        ", MAX_ITERATIONS - 1)).unwrap();
+
+        for &predecode in &[false, true] {
+            let mut totals: Vec<u128> = vec![];
+        
+            for _i in 0..TIMES {
+                let mut test_vm = VM::default();
+
+                test_vm.set_program(assembler.result.bytecode.clone());
+
+                // The program is only decoded once, so leave it out of the timing
+                if predecode {
+                    test_vm.predecode();
+                }
+
+                let before = time::Instant::now();
             
-            // Run until halt
-            test_vm.run();
+                // Run until halt
+                test_vm.run().unwrap();
 
-            totals.push(before.elapsed().as_nanos());
+                totals.push(before.elapsed().as_nanos());
 
-            // Verify that our register has reached MAX_ITERATIONS
-            assert_eq!(test_vm.registers[1], test_vm.registers[2]);
-            assert_eq!(test_vm.registers[4], target);
-        }
+                // Verify that our register has reached MAX_ITERATIONS
+                assert_eq!(test_vm.registers[1], test_vm.registers[2]);
+                assert_eq!(test_vm.registers[4], target);
+            }
 
-        println!("Bytecode took: {:.2?}ns", (totals.iter().sum::<u128>() / totals.len() as u128));
+            let name = if predecode { "Pre-decoded bytecode" } else { "Bytecode" };
+
+            println!("{} took: {:.2?}ns", name, (totals.iter().sum::<u128>() / totals.len() as u128));
+        }
     }
 }
\ No newline at end of file
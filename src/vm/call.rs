@@ -0,0 +1,387 @@
+//! Calling functions in bytecode from the host, by the names given to them with `.export`.
+//!
+//! Calls follow the same register convention as `CALL` and host functions. Integer arguments go in
+//! `$0`-`$7` and float arguments in the float registers `$0`-`$15`, each in the order they appear
+//! in the signature, so `i32, f64, i32` passes its arguments in `$0`, float `$0` and `$1`. Results
+//! are read back the same way once the function returns. Registers `$8`-`$15` and the float
+//! registers `$16`-`$31` are restored on return, and every other register may be clobbered.
+
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+use crate::vm::VM;
+use crate::vm::trap::{ExitReason, Trap};
+
+/// Integer registers that carry arguments and results.
+pub const ARGUMENT_REGISTERS: Range<usize> = 0..8;
+
+/// Floating point registers that carry arguments and results.
+pub const FLOAT_ARGUMENT_REGISTERS: Range<usize> = 0..16;
+
+/// The type of an argument or result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    I32,
+    F64,
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValueType::I32 => write!(f, "i32"),
+            ValueType::F64 => write!(f, "f64"),
+        }
+    }
+}
+
+/// An argument passed to, or a result returned from, a function in bytecode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    I32(i32),
+    F64(f64),
+}
+
+impl Value {
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::I32(_) => ValueType::I32,
+            Value::F64(_) => ValueType::F64,
+        }
+    }
+}
+
+/// The types a function takes and returns, written as `i32, f64 -> i32`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Signature {
+    pub params: Vec<ValueType>,
+    pub results: Vec<ValueType>,
+}
+
+impl Signature {
+    /// Parses a signature such as `i32, f64 -> i32`. Either side may be empty, and the arrow may be
+    /// left out for functions that return nothing. Returns `None` for unknown types and for
+    /// signatures needing more registers than the calling convention has.
+    pub fn parse(text: &str) -> Option<Signature> {
+        let types = |text: &str| -> Option<Vec<ValueType>> {
+            if text.trim().is_empty() {
+                return Some(vec![]);
+            }
+
+            text.split(',').map(|name| match name.trim() {
+                "i32" => Some(ValueType::I32),
+                "f64" => Some(ValueType::F64),
+                _ => None,
+            }).collect()
+        };
+
+        let (params, results) = match text.find("->") {
+            Some(arrow) => (&text[..arrow], &text[arrow + 2..]),
+            None => (text, ""),
+        };
+
+        let signature = Signature { params: types(params)?, results: types(results)? };
+
+        if signature.fits_registers() { Some(signature) } else { None }
+    }
+
+    /// Whether the arguments and the results each fit in the registers that carry them.
+    pub fn fits_registers(&self) -> bool {
+        let fits = |types: &[ValueType]| {
+            let count = |value_type| types.iter().filter(|&&other| other == value_type).count();
+
+            count(ValueType::I32) <= ARGUMENT_REGISTERS.len() && count(ValueType::F64) <= FLOAT_ARGUMENT_REGISTERS.len()
+        };
+
+        fits(&self.params) && fits(&self.results)
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |types: &[ValueType]| types.iter().map(ValueType::to_string).collect::<Vec<_>>().join(", ");
+
+        write!(f, "{} -> {}", join(&self.params), join(&self.results))
+    }
+}
+
+/// A function the host can call by name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export {
+    pub name: String,
+
+    /// The byte offset into the bytecode the function starts at
+    pub offset: usize,
+
+    pub signature: Signature,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum CallErrorKind {
+    /// No function with the name was exported
+    UnknownFunction,
+
+    /// The arguments do not have the types the function takes
+    ArgumentMismatch { expected: Vec<ValueType>, found: Vec<ValueType> },
+
+    /// The function trapped. The call is unwound, leaving the registers it clobbered as they were
+    /// when it trapped.
+    Trap(Trap),
+
+    /// Execution stopped, for the given reason, before the function returned. The call is unwound
+    /// as for a trap, so it cannot be resumed.
+    DidNotReturn(ExitReason),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallError {
+    /// The name of the function that was called
+    pub function: String,
+
+    pub kind: CallErrorKind,
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |types: &[ValueType]| types.iter().map(ValueType::to_string).collect::<Vec<_>>().join(", ");
+
+        match &self.kind {
+            CallErrorKind::UnknownFunction => write!(f, "function `{}` is not exported", self.function),
+            CallErrorKind::ArgumentMismatch { expected, found } => write!(f, "`{}` takes ({}), found ({})", self.function, join(expected), join(found)),
+            CallErrorKind::Trap(trap) => write!(f, "`{}` failed: {}", self.function, trap),
+            CallErrorKind::DidNotReturn(reason) => write!(f, "`{}` stopped before returning: {:?}", self.function, reason),
+        }
+    }
+}
+
+impl Error for CallError { }
+
+impl VM {
+    // Calls the function exported as `name` with `args`, and returns its results once it returns.
+    // Whether or not the call succeeds, the program counter and the call stack are restored
+    // afterwards, so a paused program can be resumed. See the module documentation for how
+    // arguments and results are passed.
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Vec<Value>, CallError> {
+        self.call_with(name, args, VM::run)
+    }
+
+    // Like `call`, but adds `fuel` to the VM's budget and gives up with `ExitReason::OutOfFuel`
+    // once it is spent, so a function that never returns cannot hang the host.
+    pub fn call_with_fuel(&mut self, name: &str, args: &[Value], fuel: u64) -> Result<Vec<Value>, CallError> {
+        self.call_with(name, args, |vm| vm.run_with_fuel(fuel))
+    }
+
+    // Sets up a call to the function exported as `name`, and executes it with `run`.
+    fn call_with<F>(&mut self, name: &str, args: &[Value], run: F) -> Result<Vec<Value>, CallError>
+        where F: FnOnce(&mut VM) -> Result<ExitReason, Trap>
+    {
+        let error = |kind| CallError { function: name.to_string(), kind };
+
+        let export = match self.exports.iter().find(|export| export.name == name) {
+            Some(export) => export.clone(),
+            None => return Err(error(CallErrorKind::UnknownFunction)),
+        };
+
+        let found: Vec<ValueType> = args.iter().map(Value::value_type).collect();
+
+        if found != export.signature.params {
+            return Err(error(CallErrorKind::ArgumentMismatch { expected: export.signature.params, found }));
+        }
+
+        let (mut register, mut float_register) = (ARGUMENT_REGISTERS.start, FLOAT_ARGUMENT_REGISTERS.start);
+
+        for arg in args {
+            match *arg {
+                Value::I32(value) => {
+                    self.registers[register] = value;
+                    register += 1;
+                },
+                Value::F64(value) => {
+                    self.float_registers[float_register] = value;
+                    float_register += 1;
+                },
+            }
+        }
+
+        let pc = self.pc;
+        let depth = self.call_stack.len();
+
+        // Returning to the end of the program stops `run` as soon as the function returns
+        self.pc = self.program.len();
+
+        if let Err(kind) = self.enter_frame(export.offset) {
+            self.pc = pc;
+
+            let opcode = self.program.get(export.offset).copied().unwrap_or_default();
+
+            return Err(error(CallErrorKind::Trap(Trap { pc: export.offset, ic: self.ic, opcode, kind })));
+        }
+
+        // Having returned is checked first, as a breakpoint or fuel running out right at the end
+        // of the program still stops `run` there
+        let returned = |vm: &VM| vm.pc == vm.program.len() && vm.call_stack.len() == depth;
+
+        let result = match run(self) {
+            Ok(_) if returned(self) => Ok(()),
+            Ok(reason) => Err(error(CallErrorKind::DidNotReturn(reason))),
+            Err(trap) => Err(error(CallErrorKind::Trap(trap))),
+        };
+
+        // A call that did not return leaves its frames behind. Unwinding them down to the frame
+        // made above also restores the registers the caller saved.
+        if self.call_stack.len() > depth {
+            self.call_stack.truncate(depth + 1);
+
+            // Cannot fail, since the call stack holds at least the frame made above
+            let _ = self.leave_frame();
+        }
+
+        self.pc = pc;
+
+        result?;
+
+        let (mut register, mut float_register) = (ARGUMENT_REGISTERS.start, FLOAT_ARGUMENT_REGISTERS.start);
+
+        let results = export.signature.results.iter().map(|value_type| match value_type {
+            ValueType::I32 => {
+                register += 1;
+
+                Value::I32(self.registers[register - 1])
+            },
+            ValueType::F64 => {
+                float_register += 1;
+
+                Value::F64(self.float_registers[float_register - 1])
+            },
+        }).collect();
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::vm::trap::TrapKind;
+
+    fn get_test_vm(source: &str) -> VM {
+        let mut assembler = Assembler::default();
+
+        assembler.compile(source).unwrap();
+
+        VM::from_program(assembler.result)
+    }
+
+    #[test]
+    fn parse_signatures() {
+        assert_eq!(Signature::parse("i32, f64 -> i32"), Some(Signature { params: vec![ValueType::I32, ValueType::F64], results: vec![ValueType::I32] }));
+        assert_eq!(Signature::parse(" -> f64"), Some(Signature { params: vec![], results: vec![ValueType::F64] }));
+        assert_eq!(Signature::parse("i32"), Some(Signature { params: vec![ValueType::I32], results: vec![] }));
+        assert_eq!(Signature::parse(""), Some(Signature::default()));
+
+        assert_eq!(Signature::parse("i32, f64 -> i32").unwrap().to_string(), "i32, f64 -> i32");
+
+        assert_eq!(Signature::parse("i64"), None);
+        assert_eq!(Signature::parse("i32,"), None);
+        assert_eq!(Signature::parse(&["i32"; 9].join(", ")), None);
+    }
+
+    #[test]
+    fn call_exported_functions() {
+        let mut test_vm = get_test_vm("
+            .export checksum \"i32, f64 -> i32, f64\"
+            .export nothing \"\"
+
+                SET $8 1
+                HLT
+            checksum:
+                SET $8 100
+                MULF $0 $0 $0
+                ADD $0 $0 $8
+            nothing:
+                RET
+        ");
+
+        // The program has not started, and carries on from where it was once the call is done
+        test_vm.registers[8] = 7;
+
+        assert_eq!(test_vm.call("checksum", &[Value::I32(3), Value::F64(1.5)]), Ok(vec![Value::I32(103), Value::F64(2.25)]));
+        assert_eq!(test_vm.call("nothing", &[]), Ok(vec![]));
+
+        assert_eq!((test_vm.pc, test_vm.registers[8], test_vm.call_stack.len()), (0, 7, 0));
+
+        test_vm.run().unwrap();
+
+        assert_eq!(test_vm.registers[8], 1);
+    }
+
+    #[test]
+    fn fail_on_bad_calls() {
+        let mut test_vm = get_test_vm("
+            .export divide \"i32, i32 -> i32\"
+            .export stuck \"\"
+            .export spin \"\"
+
+                SET $8 5
+                HLT
+            divide:
+                SET $8 1
+                DIV $0 $0 $1
+                RET
+            stuck:
+                HLT
+            spin:
+                JMP $1 spin
+        ");
+
+        let kind = |result: Result<Vec<Value>, CallError>| result.unwrap_err().kind;
+
+        // A program paused on its `HLT`, which failed calls must leave as it was
+        test_vm.pc = 4;
+        test_vm.registers[8] = 7;
+
+        let state = |test_vm: &VM| (test_vm.pc, test_vm.registers[8], test_vm.call_stack.len());
+
+        assert_eq!(kind(test_vm.call("missing", &[])), CallErrorKind::UnknownFunction);
+
+        assert_eq!(kind(test_vm.call("divide", &[Value::F64(1.0)])), CallErrorKind::ArgumentMismatch {
+            expected: vec![ValueType::I32, ValueType::I32],
+            found: vec![ValueType::F64],
+        });
+
+        match kind(test_vm.call("divide", &[Value::I32(1), Value::I32(0)])) {
+            CallErrorKind::Trap(trap) => assert_eq!((trap.pc, trap.kind), (9, TrapKind::DivideByZero)),
+            kind => panic!("{:?}", kind),
+        }
+
+        assert_eq!(state(&test_vm), (4, 7, 0));
+
+        assert_eq!(kind(test_vm.call("stuck", &[])), CallErrorKind::DidNotReturn(ExitReason::Halted));
+        assert_eq!(state(&test_vm), (4, 7, 0));
+
+        // Functions that never return can be stopped with fuel
+        assert_eq!(kind(test_vm.call_with_fuel("spin", &[], 100)), CallErrorKind::DidNotReturn(ExitReason::OutOfFuel));
+        assert_eq!(state(&test_vm), (4, 7, 0));
+
+        test_vm.fuel = 0;
+
+        assert_eq!(test_vm.call_with_fuel("divide", &[Value::I32(6), Value::I32(3)], 100), Ok(vec![Value::I32(2)]));
+        assert_eq!(test_vm.call("divide", &[Value::I32(9), Value::I32(3)]), Ok(vec![Value::I32(3)]));
+        assert_eq!(state(&test_vm), (4, 7, 0));
+
+        // A breakpoint where the function returns to does not hide that it returned
+        test_vm.breakpoints.insert(test_vm.program.len());
+
+        assert_eq!(test_vm.call("divide", &[Value::I32(8), Value::I32(2)]), Ok(vec![Value::I32(4)]));
+        assert_eq!(state(&test_vm), (4, 7, 0));
+
+        test_vm.breakpoints.clear();
+
+        // The paused program carries on where it was
+        test_vm.run().unwrap();
+
+        assert_eq!(test_vm.pc, 5);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::vm::instructions::Instruction;
+
+const NO_INSTRUCTION: u32 = u32::MAX;
+
+/// An instruction found while pre-decoding, linked to the instruction that follows it.
+#[derive(Debug, Clone, Copy)]
+pub struct DecodedInstruction {
+    pub instruction: Instruction,
+
+    /// Byte offset following the instruction
+    pub next: usize,
+
+    // Index of the instruction starting at `next`
+    next_index: u32,
+}
+
+impl DecodedInstruction {
+    /// The index of the instruction that follows this one without a jump.
+    #[inline(always)]
+    pub fn next_index(&self) -> Option<usize> {
+        match self.next_index {
+            NO_INSTRUCTION => None,
+            next_index => Some(next_index as usize),
+        }
+    }
+}
+
+/// A program decoded ahead of time, so running it skips reading and validating operand bytes.
+///
+/// Instructions are found by decoding from the start of the program to its end. Jumps still target
+/// byte offsets, which are mapped back to instruction indices as they are taken. Offsets that no
+/// decoded instruction starts at, such as bytes the sweep could not decode, fall back to decoding
+/// from the raw bytes, so execution behaves exactly as it would without pre-decoding.
+pub struct DecodedProgram {
+    pub instructions: Vec<DecodedInstruction>,
+
+    // The index of the instruction starting at each byte offset of the program
+    indices: Vec<u32>,
+}
+
+impl DecodedProgram {
+    pub fn new(program: &[u8]) -> DecodedProgram {
+        let mut decoded = DecodedProgram {
+            instructions: vec![],
+            indices: vec![NO_INSTRUCTION; program.len()],
+        };
+
+        let mut pos = 0;
+
+        while pos < program.len() {
+            match Instruction::decode(program, pos) {
+                Ok((instruction, next)) => {
+                    decoded.indices[pos] = decoded.instructions.len() as u32;
+                    decoded.instructions.push(DecodedInstruction {
+                        instruction,
+                        next,
+                        next_index: NO_INSTRUCTION,
+                    });
+
+                    pos = next;
+                },
+
+                // Resynchronize on the following byte
+                Err(_) => pos += 1,
+            }
+        }
+
+        for i in 0..decoded.instructions.len() {
+            let next = decoded.instructions[i].next;
+
+            decoded.instructions[i].next_index = decoded.indices.get(next).copied().unwrap_or(NO_INSTRUCTION);
+        }
+
+        decoded
+    }
+
+    /// The length in bytes of the program that was decoded.
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// Maps a byte offset to the index of the instruction starting there.
+    #[inline(always)]
+    pub fn index_of(&self, offset: usize) -> Option<usize> {
+        match self.indices.get(offset) {
+            Some(&index) if index != NO_INSTRUCTION => Some(index as usize),
+            _ => None,
+        }
+    }
+
+    /// The instruction starting at `offset` along with the offset of the byte following it.
+    #[inline(always)]
+    pub fn get(&self, offset: usize) -> Option<(Instruction, usize)> {
+        let decoded = self.instructions[self.index_of(offset)?];
+
+        Some((decoded.instruction, decoded.next))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::instructions::Opcode;
+    use crate::vm::operand::Register;
+
+    #[test]
+    fn decode_program() {
+        let decoded = DecodedProgram::new(&[
+            Opcode::Increment.byte(), 0,
+            Opcode::Add.byte(), 2, 0, 1,
+            Opcode::Halt.byte(),
+        ]);
+
+        assert_eq!(decoded.len(), 7);
+        assert_eq!(decoded.instructions.len(), 3);
+
+        assert_eq!(decoded.index_of(0), Some(0));
+        assert_eq!(decoded.index_of(1), None);
+        assert_eq!(decoded.index_of(2), Some(1));
+        assert_eq!(decoded.index_of(6), Some(2));
+
+        assert_eq!(decoded.get(2), Some((Instruction::Add { target: Register(2), value1: Register(0), value2: Register(1) }, 6)));
+        assert_eq!(decoded.instructions[0].next_index(), Some(1));
+        assert_eq!(decoded.instructions[2].next_index(), None);
+    }
+
+    #[test]
+    fn resynchronize_after_invalid_bytes() {
+        let decoded = DecodedProgram::new(&[
+            0xFF,
+            Opcode::Increment.byte(), 0,
+            Opcode::Increment.byte(), 16,
+            Opcode::Halt.byte(),
+        ]);
+
+        assert_eq!(decoded.index_of(0), None);
+        assert_eq!(decoded.index_of(1), Some(0));
+
+        // The bad register is left to trap when it is executed
+        assert_eq!(decoded.index_of(3), None);
+        assert_eq!(decoded.instructions[0].next_index(), None);
+    }
+}
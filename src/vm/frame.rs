@@ -0,0 +1,25 @@
+use std::ops::Range;
+
+/// The deepest the call stack may grow unless configured otherwise.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+
+/// Integer registers that are preserved across a call. `CALL` saves them in the new frame and `RET`
+/// restores them, so the callee is free to clobber them. The remaining registers are used for
+/// arguments, return values and scratch space.
+pub const SAVED_REGISTERS: Range<usize> = 8..16;
+
+/// Floating point registers that are preserved across a call, see `SAVED_REGISTERS`.
+pub const SAVED_FLOAT_REGISTERS: Range<usize> = 16..32;
+
+/// A single entry on the call stack.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// The byte to continue from once the call returns
+    pub return_address: usize,
+
+    /// Values of `SAVED_REGISTERS` at the time of the call
+    pub registers: [i32; SAVED_REGISTERS.end - SAVED_REGISTERS.start],
+
+    /// Values of `SAVED_FLOAT_REGISTERS` at the time of the call
+    pub float_registers: [f64; SAVED_FLOAT_REGISTERS.end - SAVED_FLOAT_REGISTERS.start],
+}
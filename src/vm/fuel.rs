@@ -0,0 +1,24 @@
+use crate::vm::instructions::Opcode;
+
+/// How much fuel each opcode consumes when executed by `VM::run_with_fuel`, indexed by opcode
+/// byte. Every opcode costs 1 unless configured otherwise.
+#[derive(Clone)]
+pub struct FuelCosts {
+    costs: [u64; 256],
+}
+
+impl Default for FuelCosts {
+    fn default() -> Self {
+        FuelCosts { costs: [1; 256] }
+    }
+}
+
+impl FuelCosts {
+    pub fn set(&mut self, opcode: Opcode, cost: u64) {
+        self.costs[opcode.byte() as usize] = cost;
+    }
+
+    pub fn cost(&self, byte: u8) -> u64 {
+        self.costs[byte as usize]
+    }
+}
@@ -0,0 +1,12 @@
+//! Functions provided by the embedding program, called from bytecode with `SYSCALL <id>`.
+//!
+//! Host functions follow the same register convention as subroutines. Arguments are passed in
+//! `$0`-`$7` and the float registers `$0`-`$15`, and results are returned in `$0` and float `$0`
+//! onwards. Registers `$8`-`$15` and the float registers `$16`-`$31` belong to the caller and should
+//! be left untouched. The same convention is used to call into bytecode from the host, see
+//! `vm::call`.
+
+use crate::vm::VM;
+
+/// A host function. Returning an error stops the VM with a `TrapKind::HostError` trap.
+pub type HostFn = Box<dyn FnMut(&mut VM) -> Result<(), String>>;
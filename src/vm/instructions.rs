@@ -1,16 +1,20 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
 
 use crate::vm::VM;
+use crate::vm::observer::Access;
+use crate::vm::operand::{Address, FloatRegister, Operand, OperandKind, Register};
+use crate::vm::trap::TrapKind;
 
 macro_rules! opcodes {
     (enum $name:ident {
         $($variant:ident = $instruction:ident {
             byte: $byte:expr,
             info: $info:expr,
-            $func:expr
+            |$vm:ident $(, $operand:ident: $kind:ty)*| $body:expr
         }),*,
     }) => {
-        #[derive(Debug, PartialEq)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
         pub enum $name {
             $($variant = $byte,)*
         }
@@ -39,11 +43,37 @@ macro_rules! opcodes {
                     $($name::$variant => stringify!($instruction),)*
                 }
             }
+
+            /// The number of bytes an instruction with this opcode takes up, including the opcode.
+            pub fn encoded_len(&self) -> usize {
+                match self {
+                    $($name::$variant => 1 $(+ <$kind as Operand>::KIND.size())*,)*
+                }
+            }
             
+            /// The kinds of the operands following the opcode, in encoding order.
+            pub fn operands(&self) -> &'static [OperandKind] {
+                match self {
+                    $($name::$variant => &[$(<$kind as Operand>::KIND),*],)*
+                }
+            }
+
+            /// The names of the operands, as `info` refers to them, in encoding order.
+            pub fn operand_names(&self) -> &'static [&'static str] {
+                match self {
+                    $($name::$variant => &[$(stringify!($operand)),*],)*
+                }
+            }
+
+            /// Looks up an opcode by its mnemonic, ignoring case.
+            pub fn from_instruction(instruction: &str) -> Option<$name> {
+                $(if instruction.eq_ignore_ascii_case(stringify!($instruction)) { return Some($name::$variant); })*
+
+                None
+            }
+
             pub fn all() -> Vec<$name> {
-                let mut list = vec![];
-                $(list.push($name::$variant);)*
-                list
+                vec![$($name::$variant,)*]
             }
             
             pub fn map() -> HashMap<&'static str, $name> {
@@ -52,103 +82,129 @@ macro_rules! opcodes {
                 $(map.insert(stringify!($variant), $name::$variant);)*
                 map
             }
+        }
 
-            pub fn call(vm: &mut VM, v: u8) -> bool {
-                return match v {
-                    $($byte => $func(vm),)*
+        impl TryFrom<u8> for $name {
+            type Error = TrapKind;
 
-                    _ => panic!("Unknown operator!"),
+            fn try_from(v: u8) -> Result<Self, Self::Error> {
+                match v {
+                    $($byte => Ok($name::$variant),)*
+
+                    _ => Err(TrapKind::InvalidOpcode),
                 }
             }
         }
 
-        impl From<u8> for $name {
-            fn from(v: u8) -> Self {
-                return match v {
-                    $($byte => $name::$variant,)*
+        /// A single decoded instruction along with its operands.
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub enum Instruction {
+            $($variant { $($operand: $kind),* },)*
+        }
 
-                    _ => panic!("Unknown operator!"),
+        impl Instruction {
+            pub fn opcode(&self) -> $name {
+                match self {
+                    $(Instruction::$variant { .. } => $name::$variant,)*
                 }
             }
-        }
 
-        impl From<String> for $name {
-            fn from(v: String) -> Self {
-                let v_upper = v.to_uppercase();
+            // Decodes the instruction starting at `offset`, returning it along with the offset of the
+            // byte following it.
+            #[inline(always)]
+            pub fn decode(bytes: &[u8], offset: usize) -> Result<(Instruction, usize), TrapKind> {
+                let mut pos = offset + 1;
+
+                let instruction = match *bytes.get(offset).ok_or(TrapKind::TruncatedInstruction)? {
+                    $($byte => Instruction::$variant {
+                        $($operand: <$kind as Operand>::decode(bytes, &mut pos)?,)*
+                    },)*
+
+                    _ => return Err(TrapKind::InvalidOpcode),
+                };
+
+                Ok((instruction, pos))
+            }
+
+            // Executes the instruction. Returns false if execution should stop.
+            #[inline(always)]
+            pub fn execute(self, vm: &mut VM) -> Result<bool, TrapKind> {
+                match self {
+                    $(Instruction::$variant { $($operand),* } => {
+                        let $vm: &mut VM = vm;
 
-                $(if v_upper == stringify!($instruction).to_uppercase() { return $name::$variant; })*
-                
-                $(if v_upper == stringify!($variant).to_uppercase() { return $name::$variant; })*
-                
-                panic!("Unknown operator!")
+                        $body
+                    },)*
+                }
             }
         }
     };
 }
 
 macro_rules! math_op {
-    ($op:tt) => {
-        &|vm: &mut VM| {
-            let target = vm.read_u8() as usize;
+    ($vm:ident, $target:ident, $value1:ident, $value2:ident, $func:ident) => {{
+        $vm.registers[$target.index()] = $vm.registers[$value1.index()].$func($vm.registers[$value2.index()]);
 
-            vm.registers[target]
-                = vm.registers[vm.read_u8() as usize] $op vm.registers[vm.read_u8() as usize];
+        Ok(true)
+    }};
+    ($vm:ident, $target:ident, $value1:ident, $value2:ident, $op:tt) => {{
+        $vm.registers[$target.index()] = $vm.registers[$value1.index()] $op $vm.registers[$value2.index()];
 
-            true
-        }
-    };
+        Ok(true)
+    }};
 }
 
 macro_rules! math_f64_op {
-    ($op:tt) => {
-        &|vm: &mut VM| {
-            let target = vm.read_u8() as usize;
-
-            vm.float_registers[target]
-                = vm.float_registers[vm.read_u8() as usize] $op vm.float_registers[vm.read_u8() as usize];
+    ($vm:ident, $target:ident, $value1:ident, $value2:ident, $op:tt) => {{
+        $vm.float_registers[$target.index()] = $vm.float_registers[$value1.index()] $op $vm.float_registers[$value2.index()];
 
-            true
-        }
-    };
+        Ok(true)
+    }};
 }
 
 macro_rules! condition_op {
-    ($op:tt) => {
-        &|vm: &mut VM| {
-            vm.equal_flag
-                = vm.registers[vm.read_u8() as usize] $op vm.registers[vm.read_u8() as usize];
+    ($vm:ident, $value1:ident, $value2:ident, $op:tt) => {{
+        $vm.equal_flag = $vm.registers[$value1.index()] $op $vm.registers[$value2.index()];
 
-            true
-        }
-    };
+        Ok(true)
+    }};
 }
 
 macro_rules! condition_f64_op {
-    (==) => {
-        &|vm: &mut VM| {
-            vm.equal_flag
-                = (vm.float_registers[vm.read_u8() as usize] - vm.float_registers[vm.read_u8() as usize]).abs() < f64::EPSILON;
+    ($vm:ident, $value1:ident, $value2:ident, ==) => {{
+        $vm.equal_flag = ($vm.float_registers[$value1.index()] - $vm.float_registers[$value2.index()]).abs() < f64::EPSILON;
 
-            true
-        }
-    };
-    (!=) => {
-        &|vm: &mut VM| {
-            vm.equal_flag
-                = (vm.float_registers[vm.read_u8() as usize] - vm.float_registers[vm.read_u8() as usize]).abs() > f64::EPSILON;
+        Ok(true)
+    }};
+    ($vm:ident, $value1:ident, $value2:ident, !=) => {{
+        $vm.equal_flag = ($vm.float_registers[$value1.index()] - $vm.float_registers[$value2.index()]).abs() > f64::EPSILON;
 
-            true
-        }
-    };
+        Ok(true)
+    }};
 
-    ($op:tt) => {
-        &|vm: &mut VM| {
-            vm.equal_flag
-                = vm.float_registers[vm.read_u8() as usize] $op vm.float_registers[vm.read_u8() as usize];
+    ($vm:ident, $value1:ident, $value2:ident, $op:tt) => {{
+        $vm.equal_flag = $vm.float_registers[$value1.index()] $op $vm.float_registers[$value2.index()];
 
-            true
-        }
-    };
+        Ok(true)
+    }};
+}
+
+// Jump distances and targets are stored in registers, so they must not be negative.
+fn jump_target(value: i32) -> Result<usize, TrapKind> {
+    if value < 0 {
+        return Err(TrapKind::InvalidJump);
+    }
+
+    Ok(value as usize)
+}
+
+// Stack slots are numbered in registers too, and the bottom of the stack is slot 0.
+fn stack_slot(value: i32) -> Result<usize, TrapKind> {
+    if value < 0 {
+        return Err(TrapKind::StackOutOfBounds(i64::from(value)));
+    }
+
+    Ok(value as usize)
 }
 
 opcodes! {
@@ -156,319 +212,499 @@ opcodes! {
         Halt = HLT {
             byte: 0x00,
             info: "Stop execution immediately.",
-            |_: &mut VM| false
+            |_vm| Ok(false)
         },
     
         // Integer register operations
         Set = SET {
-            byte: 0x01, // <$target> <byte 1> <byte 2>
+            byte: 0x01,
             info: "Set $target using constant bytes.",
-            |vm: &mut VM| {
-                let register = vm.read_u8() as usize;
+            |vm, target: Register, value: u16| {
+                vm.registers[target.index()] = i32::from(value);
 
-                vm.registers[register] = i32::from(vm.read_u16());
-
-                true
+                Ok(true)
             }
         },
         Load = LOAD {
-            byte: 0x02, // <$target> <$value>
+            byte: 0x02,
             info: "Set $target to $value.",
-            |vm: &mut VM| {
-                let register = vm.read_u8() as usize;
-                let pointer = vm.registers[vm.read_u8() as usize] as usize;
+            |vm, target: Register, pointer: Register| {
+                let pointer = vm.registers[pointer.index()] as usize;
 
-                vm.registers[register] = vm.fetch_heap_u32(pointer) as i32;
+                vm.registers[target.index()] = vm.heap.read_u32(pointer)? as i32;
+                vm.observe_heap_access(pointer, 4, Access::Read);
 
-                true
+                Ok(true)
             }
         },
         Store = STOR {
-            byte: 0x03, // <#target> <$value>
+            byte: 0x03,
             info: "Set #target to $value.",
-            |vm: &mut VM| {
-                let pointer = vm.read_u8() as usize;
-                let register = vm.read_u8() as usize;
-
-                vm.set_heap_u32(pointer, vm.registers[register] as u32);
+            |vm, target: Address, value: Register| {
+                vm.heap.write_u32(target.pointer(), vm.registers[value.index()] as u32)?;
+                vm.observe_heap_access(target.pointer(), 4, Access::Write);
 
-                true
+                Ok(true)
             }
         },
         Move = MOV {
-            byte: 0x04, // <$target> <$value>
+            byte: 0x04,
             info: "Set $target to $value.",
-            |vm: &mut VM| {
-                let target = vm.read_u8() as usize;
+            |vm, target: Register, value: Register| {
+                vm.registers[target.index()] = vm.registers[value.index()];
+
+                Ok(true)
+            }
+        },
+        Grow = GROW {
+            byte: 0x05,
+            info: "Grow the heap by $bytes. Sets $target to the old heap size, or -1 if the heap cannot grow.",
+            |vm, target: Register, bytes: Register| {
+                let bytes = vm.registers[bytes.index()];
+
+                let old_size = if bytes < 0 { None } else { vm.heap.grow(bytes as usize) };
 
-                vm.registers[target] = vm.registers[vm.read_u8() as usize];
+                vm.registers[target.index()] = old_size.map_or(-1, |size| size as i32);
 
-                true
+                Ok(true)
             }
         },
     
         Add = ADD {
-            byte: 0x10, // <$target> <$value1> <$value2>
+            byte: 0x10,
             info: "Set $target to $value1 + $value2.",
-            math_op!(+)
+            |vm, target: Register, value1: Register, value2: Register| math_op!(vm, target, value1, value2, wrapping_add)
         },
         Subtract = SUB {
-            byte: 0x11, // <$target> <$value1> <$value2>
+            byte: 0x11,
             info: "Set $target to $value1 - $value2.",
-            math_op!(-)
+            |vm, target: Register, value1: Register, value2: Register| math_op!(vm, target, value1, value2, wrapping_sub)
         },
         Multiply = MUL {
-            byte: 0x12, // <$target> <$value1> <$value2>
+            byte: 0x12,
             info: "Set $target to $value1 * $value2.",
-            math_op!(*)
+            |vm, target: Register, value1: Register, value2: Register| math_op!(vm, target, value1, value2, wrapping_mul)
         },
         Divide = DIV {
-            byte: 0x13, // <$target> <$value1> <$value2>
+            byte: 0x13,
             info: "Set $target to $value1 / $value2. Remainder in a dedicated register.",
-            |vm: &mut VM| {
-                let target = vm.read_u8() as usize;
-                let register1 = vm.registers[vm.read_u8() as usize];
-                let register2 = vm.registers[vm.read_u8() as usize];
+            |vm, target: Register, value1: Register, value2: Register| {
+                let register1 = vm.registers[value1.index()];
+                let register2 = vm.registers[value2.index()];
+
+                if register2 == 0 {
+                    return Err(TrapKind::DivideByZero);
+                }
 
-                vm.registers[target] = register1 / register2;
-                vm.remainder = (register1 % register2) as u32;
+                vm.registers[target.index()] = register1.wrapping_div(register2);
+                vm.remainder = register1.wrapping_rem(register2) as u32;
 
-                true
+                Ok(true)
             }
         },
     
         And = AND {
-            byte: 0x14, // <$target> <$value1> <$value2>
+            byte: 0x14,
             info: "Set $target to $value1 & $value2.",
-            math_op!(&)
+            |vm, target: Register, value1: Register, value2: Register| math_op!(vm, target, value1, value2, &)
         },
         Or = OR {
-            byte: 0x15, // <$target> <$value1> <$value2>
+            byte: 0x15,
             info: "Set $target to $value1 | $value2.",
-            math_op!(|)
+            |vm, target: Register, value1: Register, value2: Register| math_op!(vm, target, value1, value2, |)
         },
         XOR = XOR {
-            byte: 0x16, // <$target> <$value1> <$value2>
+            byte: 0x16,
             info: "Set $target to $value1 ^ $value2.",
-            math_op!(^)
+            |vm, target: Register, value1: Register, value2: Register| math_op!(vm, target, value1, value2, ^)
         },
         ShiftLeft = SHL {
-            byte: 0x17, // <$target> <$count>
+            byte: 0x17,
             info: "Bit shift $target $count left.",
-            |vm: &mut VM| {
-                let register = vm.read_u8() as usize;
-                let num_bits = vm.read_u8();
+            |vm, target: Register, count: u8| {
+                vm.registers[target.index()] = vm.registers[target.index()].wrapping_shl(count.into());
 
-                vm.registers[register] = vm.registers[register].wrapping_shl(num_bits.into());
-
-                true
+                Ok(true)
             }
         },
         ShiftRight = SHR {
-            byte: 0x18, // <$target> <$count>
+            byte: 0x18,
             info: "Bit shift $target $count right.",
-            |vm: &mut VM| {
-                let register = vm.read_u8() as usize;
-                let num_bits = vm.read_u8();
-
-                vm.registers[register] = vm.registers[register].wrapping_shr(num_bits.into());
+            |vm, target: Register, count: u8| {
+                vm.registers[target.index()] = vm.registers[target.index()].wrapping_shr(count.into());
 
-                true
+                Ok(true)
             }
         },
         
         Increment = INC {
-            byte: 0x19, // <$target>
+            byte: 0x19,
             info: "Increment $target by 1.",
-            |vm: &mut VM| {
-                vm.registers[vm.read_u8() as usize] += 1;
+            |vm, target: Register| {
+                vm.registers[target.index()] = vm.registers[target.index()].wrapping_add(1);
 
-                true
+                Ok(true)
             }
         },
         Decrement = DEC {
-            byte: 0x1A, // <$target>,
+            byte: 0x1A,
             info: "Decrement $target by 1.",
-            |vm: &mut VM| {
-                vm.registers[vm.read_u8() as usize] -= 1;
+            |vm, target: Register| {
+                vm.registers[target.index()] = vm.registers[target.index()].wrapping_sub(1);
 
-                true
+                Ok(true)
             }
         },
     
         Equal = EQ {
-            byte: 0x20, // <$value1> <$value2>
+            byte: 0x20,
             info: "Sets Z flag if $value1 == $value2.",
-            condition_op!(==)
+            |vm, value1: Register, value2: Register| condition_op!(vm, value1, value2, ==)
         },
         NotEqual = NEQ {
-            byte: 0x21, // <$value1> <$value2>
+            byte: 0x21,
             info: "Sets Z flag if $value1 != $value2.",
-            condition_op!(!=)
+            |vm, value1: Register, value2: Register| condition_op!(vm, value1, value2, !=)
         },
         GreaterThan = GT {
-            byte: 0x22, // <$value1> <$value2>
+            byte: 0x22,
             info: "Sets Z flag if $value1 > $value2.",
-            condition_op!(>)
+            |vm, value1: Register, value2: Register| condition_op!(vm, value1, value2, >)
         },
         LessThan = LT {
-            byte: 0x23, // <$value1> <$value2>
+            byte: 0x23,
             info: "Sets Z flag if $value1 < $value2.",
-            condition_op!(<)
+            |vm, value1: Register, value2: Register| condition_op!(vm, value1, value2, <)
         },
         GreaterThanOrEqual = GEQ {
-            byte: 0x24, // <$value1> <$value2>
+            byte: 0x24,
             info: "Sets Z flag if $value1 >= $value2.",
-            condition_op!(>=)
+            |vm, value1: Register, value2: Register| condition_op!(vm, value1, value2, >=)
         },
         LessThanOrEqual = LEQ {
-            byte: 0x25, // <$value> <$value>
+            byte: 0x25,
             info: "Sets Z flag if $value1 <= $value2.",
-            condition_op!(<=)
+            |vm, value1: Register, value2: Register| condition_op!(vm, value1, value2, <=)
         },
         
         // Floating point register operations
         SetF64 = SETF {
-            byte: 0x30, // <$target> <byte 1> <byte 2>
+            byte: 0x30,
             info: "Set $target using constant bytes.",
-            |vm: &mut VM| {
-                let register = vm.read_u8() as usize;
+            |vm, target: FloatRegister, value: u16| {
+                vm.float_registers[target.index()] = f64::from(value);
 
-                vm.float_registers[register] = f64::from(vm.read_u16());
-
-                true
+                Ok(true)
             }
         },
         LoadF64 = LOADF {
-            byte: 0x31, // <$target> <#value>
+            byte: 0x31,
             info: "Set $target to #value.",
-            |vm: &mut VM| {
-                let register = vm.read_u8() as usize;
-                let pointer = vm.registers[vm.read_u8() as usize] as usize;
+            |vm, target: FloatRegister, pointer: Register| {
+                let pointer = vm.registers[pointer.index()] as usize;
 
-                vm.float_registers[register] = vm.fetch_heap_u64(pointer) as f64;
+                vm.float_registers[target.index()] = vm.heap.read_u64(pointer)? as f64;
+                vm.observe_heap_access(pointer, 8, Access::Read);
 
-                true
+                Ok(true)
             }
         },
         StoreF64 = STORF {
-            byte: 0x32, // <#target> <$value>
+            byte: 0x32,
             info: "Set #target to $value.",
-            |vm: &mut VM| {
-                let pointer = vm.read_u8() as usize;
-                let register = vm.read_u8() as usize;
-
-                vm.set_heap_u64(pointer, vm.float_registers[register] as u64);
+            |vm, target: Address, value: FloatRegister| {
+                vm.heap.write_u64(target.pointer(), vm.float_registers[value.index()] as u64)?;
+                vm.observe_heap_access(target.pointer(), 8, Access::Write);
 
-                true
+                Ok(true)
             }
         },
         MoveF64 = MOVF {
-            byte: 0x33, // <$target> <$value>
+            byte: 0x33,
             info: "Set $target to $value.",
-            |vm: &mut VM| {
-                vm.float_registers[vm.read_u8() as usize] = vm.float_registers[vm.read_u8() as usize];
+            |vm, target: FloatRegister, value: FloatRegister| {
+                vm.float_registers[target.index()] = vm.float_registers[value.index()];
 
-                true
+                Ok(true)
             }
         },
 
         AddF64 = ADDF {
-            byte: 0x41, // <$target> <$value1> <$value2>
+            byte: 0x41,
             info: "Set $target to $value1 + $value2.",
-            math_f64_op!(+)
+            |vm, target: FloatRegister, value1: FloatRegister, value2: FloatRegister| math_f64_op!(vm, target, value1, value2, +)
         },
         SubtractF64 = SUBF {
-            byte: 0x42, // <$target> <$value1> <$value2>
+            byte: 0x42,
             info: "Set $target to $value1 - $value2.",
-            math_f64_op!(-)
+            |vm, target: FloatRegister, value1: FloatRegister, value2: FloatRegister| math_f64_op!(vm, target, value1, value2, -)
         },
         MultiplyF64 = MULF {
-            byte: 0x43, // <$target> <$value1> <$value2>
+            byte: 0x43,
             info: "Set $target to $value1 * $value2.",
-            math_f64_op!(*)
+            |vm, target: FloatRegister, value1: FloatRegister, value2: FloatRegister| math_f64_op!(vm, target, value1, value2, *)
         },
         DivideF64 = DIVF {
-            byte: 0x44, // <$target> <$value> <$value>
+            byte: 0x44,
             info: "Set $target to $value1 / $value2.",
-            math_f64_op!(/)
+            |vm, target: FloatRegister, value1: FloatRegister, value2: FloatRegister| math_f64_op!(vm, target, value1, value2, /)
         },
 
         EqualF64 = EQF {
-            byte: 0x51, // <$value> <$value>
+            byte: 0x51,
             info: "Sets Z flag if $value1 == $value2.",
-            condition_f64_op!(==)
+            |vm, value1: FloatRegister, value2: FloatRegister| condition_f64_op!(vm, value1, value2, ==)
         },
         NotEqualF64 = NEQF {
-            byte: 0x52, // <$value> <$value>
+            byte: 0x52,
             info: "Sets Z flag if $value1 != $value2.",
-            condition_f64_op!(!=)
+            |vm, value1: FloatRegister, value2: FloatRegister| condition_f64_op!(vm, value1, value2, !=)
         },
         GreaterThanF64 = GTF {
-            byte: 0x53, // <$value> <$value>
+            byte: 0x53,
             info: "Sets Z flag if $value1 > $value2.",
-            condition_f64_op!(>)
+            |vm, value1: FloatRegister, value2: FloatRegister| condition_f64_op!(vm, value1, value2, >)
         },
         LessThanF64 = LTF {
-            byte: 0x54, // <$value> <$value>
+            byte: 0x54,
             info: "Sets Z flag if $value1 < $value2.",
-            condition_f64_op!(<)
+            |vm, value1: FloatRegister, value2: FloatRegister| condition_f64_op!(vm, value1, value2, <)
         },
         GreaterThanOrEqualF64 = GEQF {
-            byte: 0x55, // <$value> <$value>
+            byte: 0x55,
             info: "Sets Z flag if $value1 >= $value2.",
-            condition_f64_op!(>=)
+            |vm, value1: FloatRegister, value2: FloatRegister| condition_f64_op!(vm, value1, value2, >=)
         },
         LessThanOrEqualF64 = LEQF {
-            byte: 0x56, // <$value> <$value>
+            byte: 0x56,
             info: "Sets Z flag if $value1 <= $value2.",
-            condition_f64_op!(<=)
+            |vm, value1: FloatRegister, value2: FloatRegister| condition_f64_op!(vm, value1, value2, <=)
         },
 
         Jump = JMP {
-            byte: 0x60, // <#byte>
+            byte: 0x60,
             info: "Jump to #byte.",
-            |vm: &mut VM| {
-                vm.pc = vm.registers[vm.read_u8() as usize] as usize;
+            |vm, byte: Register| {
+                vm.pc = jump_target(vm.registers[byte.index()])?;
 
-                true
+                Ok(true)
             }
         },
         JumpForward = JMPF {
-            byte: 0x61, // <$bytes>
+            byte: 0x61,
             info: "Jump forward $bytes.",
-            |vm: &mut VM| {
-                let value = vm.registers[vm.read_u8() as usize];
-                vm.pc += value as usize;
+            |vm, bytes: Register| {
+                let distance = jump_target(vm.registers[bytes.index()])?;
 
-                true
+                vm.pc = vm.pc.checked_add(distance).ok_or(TrapKind::InvalidJump)?;
+
+                Ok(true)
             }
         },
         JumpBackward = JMPB {
-            byte: 0x62, // <$bytes>
+            byte: 0x62,
             info: "Jump backward $bytes.",
-            |vm: &mut VM| {
-                let value = vm.registers[vm.read_u8() as usize];
-                vm.pc -= value as usize;
+            |vm, bytes: Register| {
+                let distance = jump_target(vm.registers[bytes.index()])?;
+
+                vm.pc = vm.pc.checked_sub(distance).ok_or(TrapKind::InvalidJump)?;
 
-                true
+                Ok(true)
             }
         },
         JumpIfEqual = JEQ {
-            byte: 0x63, // <$byte>
+            byte: 0x63,
             info: "If the Z flag is set, jump to $bytes.",
-            |vm: &mut VM| {
+            |vm, byte: Register| {
                 if vm.equal_flag {
-                    let register = vm.read_u8() as usize;
-    
-                    vm.pc = vm.registers[register] as usize;
-                } else {
-                    // Skip the bits we ignored since equality is false
-                    vm.pc += 1;
+                    vm.pc = jump_target(vm.registers[byte.index()])?;
                 }
 
-                true
+                Ok(true)
+            }
+        },
+        Call = CALL {
+            byte: 0x64,
+            info: "Call the subroutine at the constant byte offset. $8-$15 are restored on return.",
+            |vm, target: u16| {
+                vm.enter_frame(target as usize)?;
+
+                Ok(true)
+            }
+        },
+        CallRegister = CALLR {
+            byte: 0x65,
+            info: "Call the subroutine at $byte. $8-$15 are restored on return.",
+            |vm, byte: Register| {
+                let target = jump_target(vm.registers[byte.index()])?;
+
+                vm.enter_frame(target)?;
+
+                Ok(true)
+            }
+        },
+        Return = RET {
+            byte: 0x66,
+            info: "Return from the current subroutine.",
+            |vm| {
+                vm.leave_frame()?;
+
+                Ok(true)
+            }
+        },
+        Push = PUSH {
+            byte: 0x70,
+            info: "Push $value onto the stack.",
+            |vm, value: Register| {
+                vm.stack.push(vm.registers[value.index()] as i64 as u64)?;
+
+                Ok(true)
+            }
+        },
+        Pop = POP {
+            byte: 0x71,
+            info: "Pop the top of the stack into $target.",
+            |vm, target: Register| {
+                vm.registers[target.index()] = vm.stack.pop()? as i32;
+
+                Ok(true)
+            }
+        },
+        PushF64 = PUSHF {
+            byte: 0x72,
+            info: "Push $value onto the stack.",
+            |vm, value: FloatRegister| {
+                vm.stack.push(vm.float_registers[value.index()].to_bits())?;
+
+                Ok(true)
+            }
+        },
+        PopF64 = POPF {
+            byte: 0x73,
+            info: "Pop the top of the stack into $target.",
+            |vm, target: FloatRegister| {
+                vm.float_registers[target.index()] = f64::from_bits(vm.stack.pop()?);
+
+                Ok(true)
+            }
+        },
+        PushAll = PUSHA {
+            byte: 0x74,
+            info: "Push $0 through $15 onto the stack.",
+            |vm| {
+                vm.stack.check_push(vm.registers.len())?;
+
+                for i in 0..vm.registers.len() {
+                    vm.stack.push(vm.registers[i] as i64 as u64)?;
+                }
+
+                Ok(true)
+            }
+        },
+        PopAll = POPA {
+            byte: 0x75,
+            info: "Pop $15 through $0 from the stack.",
+            |vm| {
+                vm.stack.check_pop(vm.registers.len())?;
+
+                for i in (0..vm.registers.len()).rev() {
+                    vm.registers[i] = vm.stack.pop()? as i32;
+                }
+
+                Ok(true)
+            }
+        },
+        GetStackPointer = GETSP {
+            byte: 0x76,
+            info: "Set $target to the stack pointer: the number of slots in use.",
+            |vm, target: Register| {
+                vm.registers[target.index()] = vm.stack.sp() as i32;
+
+                Ok(true)
+            }
+        },
+        SetStackPointer = SETSP {
+            byte: 0x77,
+            info: "Set the stack pointer to $value, dropping the slots above it or zeroing the new ones.",
+            |vm, value: Register| {
+                let sp = vm.registers[value.index()];
+
+                if sp < 0 {
+                    return Err(TrapKind::StackUnderflow);
+                }
+
+                vm.stack.set_sp(sp as usize)?;
+
+                Ok(true)
+            }
+        },
+        LoadSlot = LDS {
+            byte: 0x78,
+            info: "Set $target to the stack slot numbered $slot, counting from the bottom.",
+            |vm, target: Register, slot: Register| {
+                vm.registers[target.index()] = vm.stack.get(stack_slot(vm.registers[slot.index()])?)? as i32;
+
+                Ok(true)
+            }
+        },
+        StoreSlot = STS {
+            byte: 0x79,
+            info: "Set the stack slot numbered $slot, counting from the bottom, to $value.",
+            |vm, slot: Register, value: Register| {
+                vm.stack.set(stack_slot(vm.registers[slot.index()])?, vm.registers[value.index()] as i64 as u64)?;
+
+                Ok(true)
+            }
+        },
+
+        Syscall = SYSCALL {
+            byte: 0x80,
+            info: "Call the host function registered under the constant id.",
+            |vm, id: u16| {
+                vm.call_host_fn(id)?;
+
+                Ok(true)
             }
         },
     }
-}
\ No newline at end of file
+}
+
+impl Opcode {
+    /// How the instruction is written in assembly, such as `STOR #target $value`. Registers are
+    /// marked with `$` and heap addresses with `#`, the same way `info` refers to them.
+    pub fn usage(&self) -> String {
+        let mut usage = self.instruction().to_string();
+
+        for (kind, name) in self.operands().iter().zip(self.operand_names()) {
+            let sigil = match kind {
+                OperandKind::Register | OperandKind::FloatRegister => "$",
+                OperandKind::Address => "#",
+                _ => "",
+            };
+
+            usage.push_str(&format!(" {}{}", sigil, name));
+        }
+
+        usage
+    }
+
+    /// A Markdown table describing every instruction, generated from `opcodes!` so it cannot go
+    /// out of date. The README holds a copy, which a test keeps in sync.
+    pub fn reference() -> String {
+        let mut reference = "| Instruction | Byte | Operands | Description |\n|---|---|---|---|\n".to_string();
+
+        for opcode in Opcode::all() {
+            let operands: Vec<&str> = opcode.operands().iter().map(|kind| kind.name()).collect();
+
+            reference.push_str(&format!(
+                "| `{}` | `{:#04x}` | {} | {} |\n",
+                opcode.usage(),
+                opcode.byte(),
+                operands.join(", "),
+                opcode.info().replace('|', "\\|"),
+            ));
+        }
+
+        reference
+    }
+}
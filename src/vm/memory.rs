@@ -0,0 +1,218 @@
+use byteorder::ByteOrder;
+use byteorder::LittleEndian;
+
+use crate::vm::trap::TrapKind;
+
+/// The largest a heap may grow to unless configured otherwise: 16 MiB.
+pub const DEFAULT_MAX_SIZE: usize = 16 * 1024 * 1024;
+
+/// Heap memory owned by a VM. It starts at a configured size and may grow up to a hard maximum,
+/// and every access is bounds checked. Values are stored little endian.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Memory {
+    bytes: Vec<u8>,
+    max_size: usize,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Memory::new(0, DEFAULT_MAX_SIZE)
+    }
+}
+
+impl From<Vec<u8>> for Memory {
+    fn from(bytes: Vec<u8>) -> Self {
+        Memory {
+            max_size: bytes.len().max(DEFAULT_MAX_SIZE),
+            bytes,
+        }
+    }
+}
+
+impl Memory {
+    /// Creates a zeroed heap of `initial_size` bytes which can never grow past `max_size` bytes.
+    pub fn new(initial_size: usize, max_size: usize) -> Memory {
+        assert!(initial_size <= max_size, "initial heap size exceeds its maximum size");
+
+        Memory {
+            bytes: vec![0; initial_size],
+            max_size,
+        }
+    }
+
+    /// The number of bytes currently in use.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Changes the maximum size of the heap. Fails if the heap is already larger than `max_size`.
+    pub fn set_max_size(&mut self, max_size: usize) -> bool {
+        if max_size < self.bytes.len() {
+            return false;
+        }
+
+        self.max_size = max_size;
+
+        true
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Grows the heap by `additional` zeroed bytes, returning the previous size. Returns `None`,
+    /// leaving the heap untouched, if that would exceed the maximum size.
+    pub fn grow(&mut self, additional: usize) -> Option<usize> {
+        let old_size = self.bytes.len();
+        let new_size = old_size.checked_add(additional)?;
+
+        if new_size > self.max_size {
+            return None;
+        }
+
+        self.bytes.resize(new_size, 0);
+
+        Some(old_size)
+    }
+
+
+    fn slice(&self, pointer: usize, size: usize) -> Result<&[u8], TrapKind> {
+        pointer.checked_add(size)
+            .and_then(|end| self.bytes.get(pointer..end))
+            .ok_or(TrapKind::HeapOutOfBounds { address: pointer, size })
+    }
+
+    fn slice_mut(&mut self, pointer: usize, size: usize) -> Result<&mut [u8], TrapKind> {
+        pointer.checked_add(size)
+            .and_then(move |end| self.bytes.get_mut(pointer..end))
+            .ok_or(TrapKind::HeapOutOfBounds { address: pointer, size })
+    }
+
+
+    pub fn read_u8(&self, pointer: usize) -> Result<u8, TrapKind> {
+        Ok(self.slice(pointer, 1)?[0])
+    }
+
+    pub fn write_u8(&mut self, pointer: usize, value: u8) -> Result<(), TrapKind> {
+        self.slice_mut(pointer, 1)?[0] = value;
+
+        Ok(())
+    }
+
+
+    pub fn read_u16(&self, pointer: usize) -> Result<u16, TrapKind> {
+        Ok(LittleEndian::read_u16(self.slice(pointer, 2)?))
+    }
+
+    pub fn write_u16(&mut self, pointer: usize, value: u16) -> Result<(), TrapKind> {
+        LittleEndian::write_u16(self.slice_mut(pointer, 2)?, value);
+
+        Ok(())
+    }
+
+
+    pub fn read_u32(&self, pointer: usize) -> Result<u32, TrapKind> {
+        Ok(LittleEndian::read_u32(self.slice(pointer, 4)?))
+    }
+
+    pub fn write_u32(&mut self, pointer: usize, value: u32) -> Result<(), TrapKind> {
+        LittleEndian::write_u32(self.slice_mut(pointer, 4)?, value);
+
+        Ok(())
+    }
+
+
+    pub fn read_u64(&self, pointer: usize) -> Result<u64, TrapKind> {
+        Ok(LittleEndian::read_u64(self.slice(pointer, 8)?))
+    }
+
+    pub fn write_u64(&mut self, pointer: usize, value: u64) -> Result<(), TrapKind> {
+        LittleEndian::write_u64(self.slice_mut(pointer, 8)?, value);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_u8_heap() {
+        let mut heap = Memory::from(vec![0, 0, 0, 0, 0, 69]);
+
+        heap.write_u8(1, 8).unwrap();
+
+        assert_eq!(heap.bytes(), &[0, 8, 0, 0, 0, 69]);
+
+        assert_eq!(heap.read_u32(1), Ok(8));
+    }
+
+    #[test]
+    fn set_u16_heap() {
+        let mut heap = Memory::from(vec![0, 0, 0, 0, 0, 69]);
+
+        heap.write_u16(1, 257).unwrap();
+
+        assert_eq!(heap.bytes(), &[0, 1, 1, 0, 0, 69]);
+
+        assert_eq!(heap.read_u32(1), Ok(257));
+    }
+
+    #[test]
+    fn set_u32_heap() {
+        let mut heap = Memory::from(vec![0, 0, 0, 0, 0, 69]);
+
+        heap.write_u32(1, 500).unwrap();
+
+        assert_eq!(heap.bytes(), &[0, 244, 1, 0, 0, 69]);
+
+        assert_eq!(heap.read_u32(1), Ok(500));
+    }
+
+    #[test]
+    fn set_u64_heap() {
+        let mut heap = Memory::from(vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 69]);
+
+        heap.write_u64(1, 2162110581051415215).unwrap();
+
+        assert_eq!(heap.bytes(), &[0, 175, 218, 174, 60, 30, 92, 1, 30, 69]);
+
+        assert_eq!(heap.read_u64(1), Ok(2162110581051415215u64));
+    }
+
+    #[test]
+    fn out_of_bounds_access() {
+        let mut heap = Memory::new(4, 4);
+
+        assert_eq!(heap.read_u32(0), Ok(0));
+        assert_eq!(heap.read_u32(1), Err(TrapKind::HeapOutOfBounds { address: 1, size: 4 }));
+        assert_eq!(heap.write_u8(4, 1), Err(TrapKind::HeapOutOfBounds { address: 4, size: 1 }));
+        assert_eq!(heap.read_u64(usize::MAX), Err(TrapKind::HeapOutOfBounds { address: usize::MAX, size: 8 }));
+    }
+
+    #[test]
+    fn grow_within_limit() {
+        let mut heap = Memory::new(2, 8);
+
+        assert_eq!(heap.grow(4), Some(2));
+        assert_eq!(heap.len(), 6);
+
+        assert_eq!(heap.grow(3), None);
+        assert_eq!(heap.len(), 6);
+
+        assert!(!heap.set_max_size(5));
+        assert!(heap.set_max_size(9));
+
+        assert_eq!(heap.grow(3), Some(6));
+        assert_eq!(heap.bytes(), &[0; 9]);
+    }
+}
@@ -1,11 +1,38 @@
+pub mod call;
+pub mod decode;
+pub mod frame;
+pub mod fuel;
+pub mod host;
 pub mod instructions;
+pub mod memory;
+pub mod observer;
+pub mod operand;
+pub mod snapshot;
+pub mod stack;
+pub mod trap;
+pub mod verifier;
 mod test;
 
-use std::convert::TryInto;
+use std::collections::{HashMap, HashSet};
 
-use crate::vm::instructions::Opcode;
+use crate::assembler::debug::DebugInfo;
+use crate::assembler::program::Program;
+use crate::vm::call::Export;
+use crate::vm::decode::DecodedProgram;
+use crate::vm::frame::{DEFAULT_MAX_CALL_DEPTH, Frame, SAVED_FLOAT_REGISTERS, SAVED_REGISTERS};
+use crate::vm::fuel::FuelCosts;
+use crate::vm::host::HostFn;
+use crate::vm::instructions::Instruction;
+use crate::vm::memory::Memory;
+use crate::vm::observer::{Access, VmObserver, Watchpoint};
+use crate::vm::stack::Stack;
+use crate::vm::trap::{ExitReason, Trap, TrapKind};
+use crate::vm::verifier::Diagnostic;
+
+pub const REGISTER_COUNT: usize = 16;
+
+pub const FLOAT_REGISTER_COUNT: usize = 32;
 
-#[derive(Default)]
 pub struct VM {
     // The program counter keeps track of how many instructions have been executed
     pub ic: usize,
@@ -13,168 +40,450 @@ pub struct VM {
     // The program counter keeps track of which byte is being executed
     pub pc: usize,
     
-    // The bytecode of our program
-    pub program: Vec<u8>,
+    // The bytecode of our program, changed through `set_program` and `program_mut`
+    program: Vec<u8>,
+
+    // Bumped whenever the program may have changed, so stale pre-decoded instructions are never run
+    program_version: u64,
     
     /// Array that simulates hardware registers
-    pub registers: [i32; 16],
+    pub registers: [i32; REGISTER_COUNT],
 
     // Contains the remainder of division operations
     pub remainder: u32,
 
-    pub float_registers: [f64; 32],
+    pub float_registers: [f64; FLOAT_REGISTER_COUNT],
 
     /// Contains the result of the last comparison operation
     pub equal_flag: bool,
 
-    pub heap: Vec<u8>,
+    /// Bounds checked heap memory, limited to a maximum size
+    pub heap: Memory,
+
+    /// Fuel left over from previous calls to `run_with_fuel`
+    pub fuel: u64,
+
+    /// The amount of fuel each opcode consumes
+    pub fuel_costs: FuelCosts,
+
+    /// Return addresses and saved registers of the subroutines currently being executed
+    pub call_stack: Vec<Frame>,
+
+    /// Calls nested deeper than this raise a trap
+    pub max_call_depth: usize,
+
+    /// Scratch storage for `PUSH` and `POP`
+    pub stack: Stack,
+
+    // Functions callable with `SYSCALL`, by id
+    host_fns: HashMap<u16, HostFn>,
+
+    /// Byte offsets `run` and `run_with_fuel` pause at before executing the instruction there
+    pub breakpoints: HashSet<usize>,
+
+    /// Heap ranges `run` and `run_with_fuel` pause at once they have been accessed
+    pub watchpoints: Vec<Watchpoint>,
+
+    // Instructions decoded ahead of time by `predecode`, along with the version of the program
+    // they were decoded from
+    decoded: Option<(u64, DecodedProgram)>,
+
+    // Notified of every instruction, heap access and trap
+    observer: Option<Box<dyn VmObserver>>,
+
+    // The access that hit a watchpoint during the current instruction
+    watchpoint_hit: Option<(usize, usize)>,
+
+    /// Where the program was assembled from, for symbolizing traps and traces
+    pub debug_info: Option<DebugInfo>,
+
+    /// Functions the host can run with `call`, by name
+    pub exports: Vec<Export>,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        VM {
+            ic: 0,
+            pc: 0,
+            program: vec![],
+            program_version: 0,
+            registers: [0; REGISTER_COUNT],
+            remainder: 0,
+            float_registers: [0.0; FLOAT_REGISTER_COUNT],
+            equal_flag: false,
+            heap: Memory::default(),
+            fuel: 0,
+            fuel_costs: FuelCosts::default(),
+            call_stack: vec![],
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            stack: Stack::default(),
+            host_fns: HashMap::new(),
+            breakpoints: HashSet::new(),
+            watchpoints: vec![],
+            decoded: None,
+            observer: None,
+            watchpoint_hit: None,
+            debug_info: None,
+            exports: vec![],
+        }
+    }
 }
 
 impl VM {
-    pub fn read_u8(&mut self) -> u8 {
-        self.pc += 1;
-        self.program[self.pc - 1]
+    // The bytecode being run.
+    pub fn program(&self) -> &[u8] {
+        &self.program
     }
-    
-    pub fn read_u16(&mut self) -> u16 {
-        self.pc += 2;
-        u16::from_ne_bytes(self.program[(self.pc - 2)..(self.pc)].try_into().expect("Mismatched byte count."))
+
+    // Replaces the bytecode. Instructions decoded from the old bytecode are dropped.
+    pub fn set_program(&mut self, program: Vec<u8>) {
+        *self.program_mut() = program;
     }
 
-    
-    pub fn fetch_heap_u8(&mut self, pointer: usize) -> u8 {
-        self.heap[pointer]
+    // The bytecode, for changing it in place. Instructions decoded ahead of time are dropped, since
+    // they may no longer match.
+    pub fn program_mut(&mut self) -> &mut Vec<u8> {
+        self.program_version += 1;
+        self.decoded = None;
+
+        &mut self.program
     }
-    
-    pub fn set_heap_u8(&mut self, pointer: usize, value: u8) {
-        self.heap[pointer] = value;
+
+    // Decodes the whole program ahead of time to speed up execution. Changing the program drops the
+    // decoded instructions until this is called again.
+    pub fn predecode(&mut self) {
+        self.decoded = Some((self.program_version, DecodedProgram::new(&self.program)));
     }
 
-    
-    pub fn fetch_heap_u16(&mut self, pointer: usize) -> u16 {
-        u16::from_ne_bytes(self.heap[pointer..pointer + 2].try_into().expect("Mismatched byte count."))
+    // Verifies the program before installing it. Verified programs are pre-decoded in full, so
+    // operands are never read or checked again while running.
+    pub fn load_verified(&mut self, program: Vec<u8>) -> Result<(), Vec<Diagnostic>> {
+        let diagnostics = verifier::verify(&program);
+
+        if !diagnostics.is_empty() {
+            return Err(diagnostics);
+        }
+
+        self.set_program(program);
+        self.predecode();
+
+        Ok(())
     }
-    
-    pub fn set_heap_u16(&mut self, pointer: usize, value: u16) {
-        let u8s: [u8; 2] = unsafe { std::mem::transmute(value.to_le()) };
 
-        self.heap[pointer] = u8s[0];
-        self.heap[pointer + 1] = u8s[1];
+    // Creates a VM ready to run a program loaded from a `.lux` file. The read only data is mapped
+    // onto the heap at address 0, followed by the zeroed `.bss`, and execution starts at the entry
+    // point. Debug info is kept to symbolize traps, and exports to `call` functions by name.
+    pub fn from_program(program: Program) -> VM {
+        let mut heap = program.read_only;
+
+        heap.resize(heap.len() + program.bss_size, 0);
+
+        let mut vm = VM::default();
+
+        vm.heap = Memory::from(heap);
+        vm.set_program(program.bytecode);
+        vm.pc = program.header.entry_point;
+        vm.debug_info = program.debug_info;
+        vm.exports = program.exports;
+
+        vm
     }
 
-    
-    pub fn fetch_heap_u32(&mut self, pointer: usize) -> u32 {
-        u32::from_ne_bytes(self.heap[pointer..pointer + 4].try_into().expect("Mismatched byte count."))
+    // Describes a trap, pointing at the source line the trapping instruction came from when the
+    // program has debug info.
+    pub fn describe_trap(&self, trap: &Trap) -> String {
+        match &self.debug_info {
+            Some(debug_info) => debug_info.symbolize(trap),
+            None => trap.to_string(),
+        }
     }
-    
-    pub fn set_heap_u32(&mut self, pointer: usize, value: u32) {
-        let u8s: [u8; 4] = unsafe { std::mem::transmute(value.to_le()) };
 
-        self.heap[pointer] = u8s[0];
-        self.heap[pointer + 1] = u8s[1];
-        self.heap[pointer + 2] = u8s[2];
-        self.heap[pointer + 3] = u8s[3];
+
+    // Registers a function that bytecode can call with `SYSCALL <id>`, replacing any function
+    // previously registered with the same id. See `vm::host` for the register convention.
+    pub fn register_host_fn<F>(&mut self, id: u16, function: F)
+        where F: FnMut(&mut VM) -> Result<(), String> + 'static
+    {
+        self.host_fns.insert(id, Box::new(function));
     }
 
-    
-    pub fn fetch_heap_u64(&mut self, pointer: usize) -> u64 {
-        u64::from_ne_bytes(self.heap[pointer..pointer + 8].try_into().expect("Mismatched byte count."))
+    pub fn call_host_fn(&mut self, id: u16) -> Result<(), TrapKind> {
+        // Take the function out while it runs, so it can be handed the VM
+        let mut function = self.host_fns.remove(&id).ok_or(TrapKind::UnknownHostFunction(id))?;
+
+        let result = function(self);
+
+        // Unless it replaced itself in the meantime
+        self.host_fns.entry(id).or_insert(function);
+
+        result.map_err(TrapKind::HostError)
     }
-    
-    pub fn set_heap_u64(&mut self, pointer: usize, value: u64) {
-        let u8s: [u8; 8] = unsafe { std::mem::transmute(value.to_le()) };
 
-        self.heap[pointer] = u8s[0];
-        self.heap[pointer + 1] = u8s[1];
-        self.heap[pointer + 2] = u8s[2];
-        self.heap[pointer + 3] = u8s[3];
-        self.heap[pointer + 4] = u8s[4];
-        self.heap[pointer + 5] = u8s[5];
-        self.heap[pointer + 6] = u8s[6];
-        self.heap[pointer + 7] = u8s[7];
+
+    // Installs an observer, replacing the previous one. See `vm::observer`.
+    pub fn set_observer<O: VmObserver + 'static>(&mut self, observer: O) {
+        self.observer = Some(Box::new(observer));
     }
 
+    pub fn take_observer(&mut self) -> Option<Box<dyn VmObserver>> {
+        self.observer.take()
+    }
 
-    // Loops as long as instructions can be executed.
-    pub fn run(&mut self) {
-        loop {
-            if !self.execute_instruction() {
-                break;
+    // Whether execution has to go through the observer and check breakpoints and watchpoints.
+    fn is_observed(&self) -> bool {
+        self.observer.is_some() || !self.breakpoints.is_empty() || !self.watchpoints.is_empty()
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        !self.breakpoints.is_empty() && self.breakpoints.contains(&self.pc)
+    }
+
+    // Called by instructions after every successful heap access.
+    #[inline(always)]
+    pub fn observe_heap_access(&mut self, address: usize, size: usize, access: Access) {
+        if self.observer.is_some() || !self.watchpoints.is_empty() {
+            self.notify_heap_access(address, size, access);
+        }
+    }
+
+    #[cold]
+    fn notify_heap_access(&mut self, address: usize, size: usize, access: Access) {
+        if let Some(observer) = &mut self.observer {
+            match access {
+                Access::Read => observer.on_heap_read(address, size),
+                Access::Write => observer.on_heap_write(address, size),
             }
         }
+
+        if self.watchpoints.iter().any(|watchpoint| watchpoint.matches(address, size, access)) {
+            self.watchpoint_hit = Some((address, size));
+        }
     }
 
-    // Executes one instruction. Meant to allow for more controlled execution of the VM.
-    pub fn run_once(&mut self) -> bool {
-        self.execute_instruction()
+
+    // Pushes a frame for a call to `target`, saving the callee saved registers.
+    pub fn enter_frame(&mut self, target: usize) -> Result<(), TrapKind> {
+        if self.call_stack.len() >= self.max_call_depth {
+            return Err(TrapKind::CallStackOverflow);
+        }
+
+        let mut frame = Frame {
+            return_address: self.pc,
+            registers: Default::default(),
+            float_registers: Default::default(),
+        };
+
+        frame.registers.copy_from_slice(&self.registers[SAVED_REGISTERS]);
+        frame.float_registers.copy_from_slice(&self.float_registers[SAVED_FLOAT_REGISTERS]);
+
+        self.call_stack.push(frame);
+
+        self.pc = target;
+
+        Ok(())
     }
 
-    fn execute_instruction(&mut self) -> bool {
-        if self.pc >= self.program.len() {
-            return false;
+    // Pops the current frame, restoring the saved registers and returning to the caller.
+    pub fn leave_frame(&mut self) -> Result<(), TrapKind> {
+        let frame = self.call_stack.pop().ok_or(TrapKind::CallStackUnderflow)?;
+
+        self.registers[SAVED_REGISTERS].copy_from_slice(&frame.registers);
+        self.float_registers[SAVED_FLOAT_REGISTERS].copy_from_slice(&frame.float_registers);
+
+        self.pc = frame.return_address;
+
+        Ok(())
+    }
+
+
+    // Loops as long as instructions can be executed. Pauses on breakpoints and watchpoints, except
+    // for a breakpoint on the instruction execution starts from, so a paused VM can be resumed.
+    pub fn run(&mut self) -> Result<ExitReason, Trap> {
+        if self.is_observed() {
+            return self.run_observed();
         }
 
-        self.ic += 1;
+        let decoded = self.decoded.take();
+
+        let result = self.run_loop(decoded.as_ref());
 
-        let opcode = self.read_u8();
+        // Unless a host function changed the program while it ran
+        if decoded.as_ref().map(|&(version, _)| version) == Some(self.program_version) {
+            self.decoded = decoded;
+        }
 
-        Opcode::call(self, opcode)
+        result
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    // Runs until the program stops, using pre-decoded instructions where they are available and
+    // decoding from the program's bytes everywhere else. Decoded instructions are abandoned as soon
+    // as the program changes.
+    fn run_loop(&mut self, decoded: Option<&(u64, DecodedProgram)>) -> Result<ExitReason, Trap> {
+        let version = decoded.map(|&(version, _)| version);
+        let mut decoded = decoded.map(|(_, decoded)| decoded);
+
+        let mut index = decoded.and_then(|decoded| decoded.index_of(self.pc));
+
+        loop {
+            let start = self.pc;
+
+            let (instruction, next, next_index) = match (decoded, index) {
+                (Some(decoded), Some(index)) => {
+                    let current = decoded.instructions[index];
+
+                    (current.instruction, current.next, current.next_index())
+                },
+                _ => {
+                    if start >= self.program.len() {
+                        return Ok(ExitReason::EndOfProgram);
+                    }
+
+                    match Instruction::decode(&self.program, start) {
+                        Ok((instruction, next)) => (instruction, next, None),
+                        Err(kind) => {
+                            self.ic += 1;
+
+                            return Err(self.trap(kind));
+                        },
+                    }
+                },
+            };
+
+            self.ic += 1;
+            self.pc = next;
+
+            match instruction.execute(self) {
+                Ok(true) => { },
+                Ok(false) => return Ok(ExitReason::Halted),
+                Err(kind) => {
+                    self.pc = start;
+
+                    return Err(self.trap(kind));
+                },
+            }
+
+            // A host function may have changed the program
+            if version != Some(self.program_version) {
+                decoded = None;
+                index = None;
+            }
 
-    fn get_test_vm() -> VM {
-        VM::default()
+            if let Some(decoded) = decoded {
+                // Only jumps need to map the program counter back to an instruction index
+                index = if self.pc == next && next_index.is_some() { next_index } else { decoded.index_of(self.pc) };
+            }
+        }
+    }
+
+    // The slow path of `run`, taken one instruction at a time.
+    fn run_observed(&mut self) -> Result<ExitReason, Trap> {
+        loop {
+            match self.execute_instruction()? {
+                ExitReason::Stepped if self.at_breakpoint() => return Ok(ExitReason::Breakpoint),
+                ExitReason::Stepped => continue,
+                reason => return Ok(reason),
+            }
+        }
     }
 
-    #[test]
-    fn set_u8_heap() {
-        let mut test_vm = get_test_vm();
+    // Adds `fuel` to the VM's budget and runs until it is spent. Stops before any instruction that
+    // costs more than the remaining fuel, so execution can be resumed with another call.
+    pub fn run_with_fuel(&mut self, fuel: u64) -> Result<ExitReason, Trap> {
+        self.fuel = self.fuel.saturating_add(fuel);
 
-        test_vm.heap = vec![0, 0, 0, 0, 0, 69];
-        test_vm.set_heap_u8(1, 8);
-        
-        assert_eq!(test_vm.heap, vec![0, 8, 0, 0, 0, 69]);
-        
-        assert_eq!(test_vm.fetch_heap_u32(1), 8);
+        loop {
+            if let Some(&opcode) = self.program.get(self.pc) {
+                let cost = self.fuel_costs.cost(opcode);
+
+                if cost > self.fuel {
+                    return Ok(ExitReason::OutOfFuel);
+                }
+
+                self.fuel -= cost;
+            }
+
+            match self.execute_instruction()? {
+                ExitReason::Stepped if self.at_breakpoint() => return Ok(ExitReason::Breakpoint),
+                ExitReason::Stepped => continue,
+                reason => return Ok(reason),
+            }
+        }
     }
 
-    #[test]
-    fn set_u16_heap() {
-        let mut test_vm = get_test_vm();
+    // Executes one instruction. Meant to allow for more controlled execution of the VM.
+    pub fn run_once(&mut self) -> Result<ExitReason, Trap> {
+        self.execute_instruction()
+    }
 
-        test_vm.heap = vec![0, 0, 0, 0, 0, 69];
-        test_vm.set_heap_u16(1, 257);
-        
-        assert_eq!(test_vm.heap, vec![0, 1, 1, 0, 0, 69]);
-        
-        assert_eq!(test_vm.fetch_heap_u32(1), 257);
+    fn execute_instruction(&mut self) -> Result<ExitReason, Trap> {
+        let result = self.decode_and_execute();
+
+        if let (Err(trap), Some(observer)) = (&result, &mut self.observer) {
+            observer.on_trap(trap);
+        }
+
+        result
     }
 
-    #[test]
-    fn set_u32_heap() {
-        let mut test_vm = get_test_vm();
+    fn decode_and_execute(&mut self) -> Result<ExitReason, Trap> {
+        if self.pc >= self.program.len() {
+            return Ok(ExitReason::EndOfProgram);
+        }
+
+        // Prefer the pre-decoded instruction
+        let decoded = self.decoded.as_ref().and_then(|(_, decoded)| decoded.get(self.pc));
+
+        let (instruction, next) = match decoded {
+            Some(decoded) => decoded,
+            None => match Instruction::decode(&self.program, self.pc) {
+                Ok(decoded) => decoded,
+                Err(kind) => {
+                    self.ic += 1;
 
-        test_vm.heap = vec![0, 0, 0, 0, 0, 69];
-        test_vm.set_heap_u32(1, 500);
-        
-        assert_eq!(test_vm.heap, vec![0, 244, 1, 0, 0, 69]);
-        
-        assert_eq!(test_vm.fetch_heap_u32(1), 500);
+                    return Err(self.trap(kind));
+                },
+            },
+        };
+
+        self.execute(instruction, next)
     }
 
-    #[test]
-    fn set_u64_heap() {
-        let mut test_vm = get_test_vm();
+    // Executes an instruction starting at the program counter, which is moved to `next` beforehand.
+    fn execute(&mut self, instruction: Instruction, next: usize) -> Result<ExitReason, Trap> {
+        let start = self.pc;
+
+        if let Some(observer) = &mut self.observer {
+            observer.before_instruction(start, instruction.opcode());
+        }
+
+        self.ic += 1;
+        self.pc = next;
+
+        let result = instruction.execute(self);
+        let watchpoint_hit = self.watchpoint_hit.take();
 
-        test_vm.heap = vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 69];
-        test_vm.set_heap_u64(1, 2162110581051415215);
-        
-        assert_eq!(test_vm.heap, vec![0, 175, 218, 174, 60, 30, 92, 1, 30, 69]);
-        
-        assert_eq!(test_vm.fetch_heap_u64(1), 2162110581051415215u64);
+        if let (Ok(_), Some(observer)) = (&result, &mut self.observer) {
+            observer.after_instruction(start, instruction.opcode());
+        }
+
+        match result {
+            Ok(true) => match watchpoint_hit {
+                Some((address, size)) => Ok(ExitReason::Watchpoint { address, size }),
+                None => Ok(ExitReason::Stepped),
+            },
+            Ok(false) => Ok(ExitReason::Halted),
+            Err(kind) => {
+                // Leave the VM pointing at the instruction that faulted
+                self.pc = start;
+
+                Err(self.trap(kind))
+            }
+        }
     }
-}
\ No newline at end of file
+
+    #[cold]
+    fn trap(&self, kind: TrapKind) -> Trap {
+        Trap { pc: self.pc, ic: self.ic, opcode: self.program[self.pc], kind }
+    }
+}
@@ -0,0 +1,166 @@
+use crate::vm::instructions::Opcode;
+use crate::vm::trap::Trap;
+
+/// The kind of heap access reported to observers and matched by watchpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Receives a callback for every step the VM takes. Every method does nothing by default, so
+/// implementations only override what they care about. Tracers, coverage tools and debuggers are
+/// all built on top of this.
+///
+/// While an observer is installed `run` takes a slower path through the VM, so it should be removed
+/// again with `VM::take_observer` once it is no longer needed.
+pub trait VmObserver {
+    /// Called before the instruction at `pc` is executed.
+    fn before_instruction(&mut self, _pc: usize, _opcode: Opcode) { }
+
+    /// Called after the instruction at `pc` was executed without trapping.
+    fn after_instruction(&mut self, _pc: usize, _opcode: Opcode) { }
+
+    /// Called after `size` bytes at `address` were read from the heap.
+    fn on_heap_read(&mut self, _address: usize, _size: usize) { }
+
+    /// Called after `size` bytes at `address` were written to the heap.
+    fn on_heap_write(&mut self, _address: usize, _size: usize) { }
+
+    /// Called when an instruction traps, before the trap is returned to the host.
+    fn on_trap(&mut self, _trap: &Trap) { }
+}
+
+/// Pauses the VM after an instruction accesses any of the `size` bytes starting at `address`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub address: usize,
+    pub size: usize,
+
+    /// Which kind of access to pause on, or `None` to pause on both
+    pub access: Option<Access>,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, address: usize, size: usize, access: Access) -> bool {
+        let overlaps = address < self.address.saturating_add(self.size) && self.address < address.saturating_add(size);
+
+        overlaps && self.access.is_none_or(|kind| kind == access)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::vm::VM;
+    use crate::vm::memory::Memory;
+    use crate::vm::trap::{ExitReason, TrapKind};
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Before(usize, Opcode),
+        After(usize, Opcode),
+        Read(usize, usize),
+        Write(usize, usize),
+        Trap(usize),
+    }
+
+    struct Recorder(Rc<RefCell<Vec<Event>>>);
+
+    impl VmObserver for Recorder {
+        fn before_instruction(&mut self, pc: usize, opcode: Opcode) {
+            self.0.borrow_mut().push(Event::Before(pc, opcode));
+        }
+
+        fn after_instruction(&mut self, pc: usize, opcode: Opcode) {
+            self.0.borrow_mut().push(Event::After(pc, opcode));
+        }
+
+        fn on_heap_read(&mut self, address: usize, size: usize) {
+            self.0.borrow_mut().push(Event::Read(address, size));
+        }
+
+        fn on_heap_write(&mut self, address: usize, size: usize) {
+            self.0.borrow_mut().push(Event::Write(address, size));
+        }
+
+        fn on_trap(&mut self, trap: &Trap) {
+            self.0.borrow_mut().push(Event::Trap(trap.pc));
+        }
+    }
+
+    fn get_test_vm() -> VM {
+        let mut test_vm = VM::default();
+
+        test_vm.program = vec![
+            Opcode::Set.byte(), 0, 7, 0,
+            Opcode::Store.byte(), 2, 0,
+            Opcode::Load.byte(), 1, 1,
+            Opcode::Divide.byte(), 2, 0, 3,
+        ];
+        test_vm.heap = Memory::new(8, 8);
+
+        test_vm
+    }
+
+    #[test]
+    fn observe_execution() {
+        let mut test_vm = get_test_vm();
+
+        let events = Rc::new(RefCell::new(vec![]));
+        test_vm.set_observer(Recorder(events.clone()));
+
+        let trap = test_vm.run().unwrap_err();
+
+        assert_eq!(trap.kind, TrapKind::DivideByZero);
+        assert_eq!(*events.borrow(), vec![
+            Event::Before(0, Opcode::Set),
+            Event::After(0, Opcode::Set),
+            Event::Before(4, Opcode::Store),
+            Event::Write(2, 4),
+            Event::After(4, Opcode::Store),
+            Event::Before(7, Opcode::Load),
+            Event::Read(0, 4),
+            Event::After(7, Opcode::Load),
+            Event::Before(10, Opcode::Divide),
+            Event::Trap(10),
+        ]);
+
+        assert!(test_vm.take_observer().is_some());
+    }
+
+    #[test]
+    fn pause_on_breakpoint() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.breakpoints.insert(7);
+
+        assert_eq!(test_vm.run(), Ok(ExitReason::Breakpoint));
+        assert_eq!(test_vm.pc, 7);
+        assert_eq!(test_vm.registers[1], 0);
+
+        // Resuming steps over the breakpoint the VM is paused on
+        assert_eq!(test_vm.run_with_fuel(1), Ok(ExitReason::OutOfFuel));
+        assert_eq!(test_vm.pc, 10);
+        assert_eq!(test_vm.registers[1], 7 << 16);
+    }
+
+    #[test]
+    fn pause_on_watchpoint() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.watchpoints.push(Watchpoint { address: 5, size: 1, access: Some(Access::Read) });
+        test_vm.watchpoints.push(Watchpoint { address: 5, size: 1, access: Some(Access::Write) });
+
+        // The store is paused on once it has completed
+        assert_eq!(test_vm.run(), Ok(ExitReason::Watchpoint { address: 2, size: 4 }));
+        assert_eq!(test_vm.pc, 7);
+        assert_eq!(test_vm.heap.read_u8(2), Ok(7));
+
+        // The load from 1..5 ends just before the watched byte
+        assert!(test_vm.run().is_err());
+    }
+}
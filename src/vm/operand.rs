@@ -0,0 +1,167 @@
+use std::fmt;
+
+use byteorder::ByteOrder;
+use byteorder::LittleEndian;
+
+use crate::vm::trap::TrapKind;
+use crate::vm::{FLOAT_REGISTER_COUNT, REGISTER_COUNT};
+
+/// What an operand refers to, which decides how the assembler reads it from source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    Register,
+    FloatRegister,
+    Imm8,
+    Imm16,
+    Address,
+}
+
+impl OperandKind {
+    /// The number of bytes the operand is encoded in.
+    pub fn size(self) -> usize {
+        match self {
+            OperandKind::Imm16 => 2,
+            _ => 1,
+        }
+    }
+
+    /// The largest value the operand can hold. The smallest is always 0.
+    pub fn max(self) -> u16 {
+        match self {
+            OperandKind::Register => REGISTER_COUNT as u16 - 1,
+            OperandKind::FloatRegister => FLOAT_REGISTER_COUNT as u16 - 1,
+            OperandKind::Imm8 | OperandKind::Address => u16::from(u8::MAX),
+            OperandKind::Imm16 => u16::MAX,
+        }
+    }
+
+    pub fn is_register(self) -> bool {
+        matches!(self, OperandKind::Register | OperandKind::FloatRegister)
+    }
+
+    /// A short name for the kind, as used in the instruction reference.
+    pub fn name(self) -> &'static str {
+        match self {
+            OperandKind::Register => "int register",
+            OperandKind::FloatRegister => "float register",
+            OperandKind::Imm8 => "imm8",
+            OperandKind::Imm16 => "imm16",
+            OperandKind::Address => "heap address",
+        }
+    }
+}
+
+impl fmt::Display for OperandKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OperandKind::Register => write!(f, "an integer register"),
+            OperandKind::FloatRegister => write!(f, "a float register"),
+            OperandKind::Imm8 => write!(f, "an 8-bit immediate"),
+            OperandKind::Imm16 => write!(f, "a 16-bit immediate"),
+            OperandKind::Address => write!(f, "a heap address"),
+        }
+    }
+}
+
+/// A value encoded after an opcode byte.
+pub trait Operand: Copy {
+    /// What the operand refers to, which also decides how many bytes it is encoded in.
+    const KIND: OperandKind;
+
+    /// Decodes the operand at `pos`, advancing it past the operand.
+    fn decode(bytes: &[u8], pos: &mut usize) -> Result<Self, TrapKind>;
+}
+
+fn read_u8(bytes: &[u8], pos: &mut usize) -> Result<u8, TrapKind> {
+    let byte = *bytes.get(*pos).ok_or(TrapKind::TruncatedInstruction)?;
+
+    *pos += 1;
+
+    Ok(byte)
+}
+
+/// An integer register, always below `REGISTER_COUNT` once decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Register(pub u8);
+
+impl Register {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Operand for Register {
+    const KIND: OperandKind = OperandKind::Register;
+
+    fn decode(bytes: &[u8], pos: &mut usize) -> Result<Self, TrapKind> {
+        let register = read_u8(bytes, pos)?;
+
+        if register as usize >= REGISTER_COUNT {
+            return Err(TrapKind::BadRegister(register));
+        }
+
+        Ok(Register(register))
+    }
+}
+
+/// A floating point register, always below `FLOAT_REGISTER_COUNT` once decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FloatRegister(pub u8);
+
+impl FloatRegister {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Operand for FloatRegister {
+    const KIND: OperandKind = OperandKind::FloatRegister;
+
+    fn decode(bytes: &[u8], pos: &mut usize) -> Result<Self, TrapKind> {
+        let register = read_u8(bytes, pos)?;
+
+        if register as usize >= FLOAT_REGISTER_COUNT {
+            return Err(TrapKind::BadRegister(register));
+        }
+
+        Ok(FloatRegister(register))
+    }
+}
+
+/// A constant heap address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Address(pub u8);
+
+impl Address {
+    pub fn pointer(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Operand for Address {
+    const KIND: OperandKind = OperandKind::Address;
+
+    fn decode(bytes: &[u8], pos: &mut usize) -> Result<Self, TrapKind> {
+        Ok(Address(read_u8(bytes, pos)?))
+    }
+}
+
+impl Operand for u8 {
+    const KIND: OperandKind = OperandKind::Imm8;
+
+    fn decode(bytes: &[u8], pos: &mut usize) -> Result<Self, TrapKind> {
+        read_u8(bytes, pos)
+    }
+}
+
+impl Operand for u16 {
+    const KIND: OperandKind = OperandKind::Imm16;
+
+    fn decode(bytes: &[u8], pos: &mut usize) -> Result<Self, TrapKind> {
+        let value = bytes.get(*pos..*pos + 2).ok_or(TrapKind::TruncatedInstruction)?;
+
+        *pos += 2;
+
+        Ok(LittleEndian::read_u16(value))
+    }
+}
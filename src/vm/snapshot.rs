@@ -0,0 +1,356 @@
+use std::io::Cursor;
+use std::io::Read;
+
+use byteorder::LittleEndian;
+use byteorder::ReadBytesExt;
+use byteorder::WriteBytesExt;
+
+use crate::assembler::header::EncodeError;
+use crate::assembler::program::{decode_debug_info, decode_exports, encode_debug_info, encode_exports};
+use crate::vm::{FLOAT_REGISTER_COUNT, REGISTER_COUNT, VM};
+use crate::vm::frame::Frame;
+use crate::vm::memory::Memory;
+use crate::vm::stack::Stack;
+
+pub const SNAPSHOT_MAGIC_NUMBER: [u8; 5] = [ 0x6c, 0x78, 0x73, 0x0d, 0x0a ];
+
+/// Version 1 added the exports and debug info of the program.
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SnapshotErrorKind {
+    MagicNumber,
+    OutdatedVersion,
+    Truncated,
+
+    /// The snapshot decoded, but describes a VM that cannot exist, such as a heap larger than its limit.
+    Invalid,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestoreSnapshotError {
+    pub kind: SnapshotErrorKind,
+}
+
+impl From<std::io::Error> for RestoreSnapshotError {
+    fn from(_: std::io::Error) -> Self {
+        RestoreSnapshotError { kind: SnapshotErrorKind::Truncated }
+    }
+}
+
+fn invalid() -> RestoreSnapshotError {
+    RestoreSnapshotError { kind: SnapshotErrorKind::Invalid }
+}
+
+// Vec<u8> implements Write infallibly, so unwrapping the writes below can never panic.
+fn write_u64(bytes: &mut Vec<u8>, value: usize) {
+    bytes.write_u64::<LittleEndian>(value as u64).unwrap();
+}
+
+fn write_bytes(bytes: &mut Vec<u8>, data: &[u8]) {
+    write_u64(bytes, data.len());
+    bytes.extend_from_slice(data);
+}
+
+fn read_usize(cursor: &mut Cursor<&[u8]>) -> Result<usize, RestoreSnapshotError> {
+    Ok(cursor.read_u64::<LittleEndian>()? as usize)
+}
+
+fn read_bytes(cursor: &mut Cursor<&[u8]>) -> Result<Vec<u8>, RestoreSnapshotError> {
+    let len = read_usize(cursor)?;
+
+    // Check the length against what is left, rather than trusting it with an allocation
+    if len > cursor.get_ref().len() - cursor.position() as usize {
+        return Err(RestoreSnapshotError { kind: SnapshotErrorKind::Truncated });
+    }
+
+    let mut data = vec![0; len];
+    cursor.read_exact(&mut data)?;
+
+    Ok(data)
+}
+
+impl VM {
+    /// Serializes the complete execution state of the VM: counters, registers, flags, heap,
+    /// program along with its exports and debug info, call stack, data stack and leftover fuel.
+    /// Host functions and fuel costs are part of the embedding program rather than the VM's state,
+    /// so they are not included. Fails if the exports or debug info cannot be encoded.
+    pub fn snapshot(&self) -> Result<Vec<u8>, EncodeError> {
+        let mut bytes = vec![];
+
+        bytes.extend_from_slice(&SNAPSHOT_MAGIC_NUMBER);
+        bytes.write_u16::<LittleEndian>(SNAPSHOT_VERSION).unwrap();
+
+        write_u64(&mut bytes, self.pc);
+        write_u64(&mut bytes, self.ic);
+
+        for register in &self.registers {
+            bytes.write_i32::<LittleEndian>(*register).unwrap();
+        }
+
+        bytes.write_u32::<LittleEndian>(self.remainder).unwrap();
+
+        for register in &self.float_registers {
+            bytes.write_f64::<LittleEndian>(*register).unwrap();
+        }
+
+        bytes.push(self.equal_flag as u8);
+
+        write_u64(&mut bytes, self.heap.max_size());
+        write_bytes(&mut bytes, self.heap.bytes());
+
+        write_bytes(&mut bytes, &self.program);
+
+        // Stored as in a `.lux` file
+        write_bytes(&mut bytes, &encode_exports(&self.exports)?);
+
+        match &self.debug_info {
+            Some(debug_info) => {
+                bytes.push(1);
+                write_bytes(&mut bytes, &encode_debug_info(debug_info)?);
+            },
+            None => bytes.push(0),
+        }
+
+        bytes.write_u64::<LittleEndian>(self.fuel).unwrap();
+
+        write_u64(&mut bytes, self.max_call_depth);
+        write_u64(&mut bytes, self.call_stack.len());
+
+        for frame in &self.call_stack {
+            write_u64(&mut bytes, frame.return_address);
+
+            for register in &frame.registers {
+                bytes.write_i32::<LittleEndian>(*register).unwrap();
+            }
+
+            for register in &frame.float_registers {
+                bytes.write_f64::<LittleEndian>(*register).unwrap();
+            }
+        }
+
+        write_u64(&mut bytes, self.stack.max_size());
+        write_u64(&mut bytes, self.stack.sp());
+
+        for slot in self.stack.slots() {
+            bytes.write_u64::<LittleEndian>(*slot).unwrap();
+        }
+
+        Ok(bytes)
+    }
+
+    /// Replaces the execution state of the VM with one produced by `snapshot`. Registered host
+    /// functions and fuel costs are kept. The VM is left untouched if the snapshot is rejected.
+    pub fn restore(&mut self, bytes: &[u8]) -> Result<(), RestoreSnapshotError> {
+        let mut cursor = Cursor::new(bytes);
+
+        let mut magic_number = [0; SNAPSHOT_MAGIC_NUMBER.len()];
+        cursor.read_exact(&mut magic_number)?;
+
+        if magic_number != SNAPSHOT_MAGIC_NUMBER {
+            return Err(RestoreSnapshotError { kind: SnapshotErrorKind::MagicNumber });
+        }
+
+        if cursor.read_u16::<LittleEndian>()? != SNAPSHOT_VERSION {
+            return Err(RestoreSnapshotError { kind: SnapshotErrorKind::OutdatedVersion });
+        }
+
+        let pc = read_usize(&mut cursor)?;
+        let ic = read_usize(&mut cursor)?;
+
+        let mut registers = [0; REGISTER_COUNT];
+        cursor.read_i32_into::<LittleEndian>(&mut registers)?;
+
+        let remainder = cursor.read_u32::<LittleEndian>()?;
+
+        let mut float_registers = [0.0; FLOAT_REGISTER_COUNT];
+        cursor.read_f64_into::<LittleEndian>(&mut float_registers)?;
+
+        let equal_flag = match cursor.read_u8()? {
+            0 => false,
+            1 => true,
+            _ => return Err(invalid()),
+        };
+
+        let heap_max_size = read_usize(&mut cursor)?;
+        let mut heap = Memory::from(read_bytes(&mut cursor)?);
+
+        if !heap.set_max_size(heap_max_size) {
+            return Err(invalid());
+        }
+
+        let program = read_bytes(&mut cursor)?;
+
+        let exports = decode_exports(&read_bytes(&mut cursor)?).ok_or_else(invalid)?;
+
+        if exports.iter().any(|export| export.offset >= program.len()) {
+            return Err(invalid());
+        }
+
+        let debug_info = match cursor.read_u8()? {
+            0 => None,
+            1 => Some(decode_debug_info(&read_bytes(&mut cursor)?).ok_or_else(invalid)?),
+            _ => return Err(invalid()),
+        };
+
+        let fuel = cursor.read_u64::<LittleEndian>()?;
+
+        let max_call_depth = read_usize(&mut cursor)?;
+        let frame_count = read_usize(&mut cursor)?;
+
+        if frame_count > max_call_depth {
+            return Err(invalid());
+        }
+
+        let mut call_stack = vec![];
+
+        for _ in 0..frame_count {
+            let mut frame = Frame {
+                return_address: read_usize(&mut cursor)?,
+                registers: Default::default(),
+                float_registers: Default::default(),
+            };
+
+            cursor.read_i32_into::<LittleEndian>(&mut frame.registers)?;
+            cursor.read_f64_into::<LittleEndian>(&mut frame.float_registers)?;
+
+            call_stack.push(frame);
+        }
+
+        let mut stack = Stack::new(read_usize(&mut cursor)?);
+        let slot_count = read_usize(&mut cursor)?;
+
+        if slot_count > stack.max_size() {
+            return Err(invalid());
+        }
+
+        for _ in 0..slot_count {
+            stack.push(cursor.read_u64::<LittleEndian>()?).map_err(|_| invalid())?;
+        }
+
+        if cursor.position() as usize != bytes.len() {
+            return Err(invalid());
+        }
+
+        self.pc = pc;
+        self.ic = ic;
+        self.registers = registers;
+        self.remainder = remainder;
+        self.float_registers = float_registers;
+        self.equal_flag = equal_flag;
+        self.heap = heap;
+        self.set_program(program);
+        self.exports = exports;
+        self.debug_info = debug_info;
+        self.fuel = fuel;
+        self.max_call_depth = max_call_depth;
+        self.call_stack = call_stack;
+        self.stack = stack;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::vm::call::Value;
+    use crate::vm::instructions::Opcode;
+    use crate::vm::trap::ExitReason;
+
+    fn get_test_vm() -> VM {
+        let mut test_vm = VM::default();
+
+        // Counts $0 up forever, calling a subroutine which pushes the count each time. $1 stays 0, so
+        // the jump goes back to the start
+        test_vm.program = vec![
+            Opcode::Increment.byte(), 0,
+            Opcode::Call.byte(), 7, 0,
+            Opcode::Jump.byte(), 1,
+            Opcode::Push.byte(), 0,
+            Opcode::Return.byte(),
+        ];
+        test_vm.heap = Memory::new(4, 16);
+        test_vm.float_registers[3] = 1.5;
+
+        test_vm
+    }
+
+    #[test]
+    fn snapshot_then_restore() {
+        let mut test_vm = get_test_vm();
+
+        assert_eq!(test_vm.run_with_fuel(12), Ok(ExitReason::OutOfFuel));
+        assert_eq!(test_vm.call_stack.len(), 1);
+
+        let snapshot = test_vm.snapshot().unwrap();
+
+        let mut restored_vm = VM::default();
+        restored_vm.restore(&snapshot).unwrap();
+
+        assert_eq!(restored_vm.snapshot().unwrap(), snapshot);
+        assert_eq!(restored_vm.pc, test_vm.pc);
+        assert_eq!(restored_vm.call_stack, test_vm.call_stack);
+        assert_eq!(restored_vm.heap, test_vm.heap);
+        assert_eq!(restored_vm.float_registers[3], 1.5);
+
+        // Both VMs carry on in lockstep
+        test_vm.run_with_fuel(20).unwrap();
+        restored_vm.run_with_fuel(20).unwrap();
+
+        assert_eq!(restored_vm.snapshot().unwrap(), test_vm.snapshot().unwrap());
+        assert_eq!(restored_vm.stack.sp(), 6);
+    }
+
+    #[test]
+    fn snapshot_exports_and_debug_info() {
+        let mut assembler = Assembler::default();
+
+        assembler.emit_debug_info = true;
+        assembler.compile_source("double.asm", ".export double \"i32 -> i32\"\ndouble:\n    ADD $0 $0 $0\n    RET").unwrap();
+
+        let snapshot = VM::from_program(assembler.result.clone()).snapshot().unwrap();
+
+        // Restoring into a fresh VM brings the exports and debug info along
+        let mut restored_vm = VM::default();
+        restored_vm.restore(&snapshot).unwrap();
+
+        assert_eq!(restored_vm.exports, assembler.result.exports);
+        assert_eq!(restored_vm.debug_info, assembler.result.debug_info);
+        assert_eq!(restored_vm.call("double", &[Value::I32(21)]), Ok(vec![Value::I32(42)]));
+
+        // Restoring into a VM that held another program replaces them
+        let mut restored_vm = VM::from_program(assembler.result);
+
+        restored_vm.restore(&get_test_vm().snapshot().unwrap()).unwrap();
+
+        assert!(restored_vm.exports.is_empty());
+        assert_eq!(restored_vm.debug_info, None);
+    }
+
+    #[test]
+    fn fail_on_invalid_snapshot() {
+        let snapshot = get_test_vm().snapshot().unwrap();
+
+        let mut test_vm = VM::default();
+
+        let mut bytes = snapshot.clone();
+        bytes[0] = 0;
+
+        assert_eq!(test_vm.restore(&bytes), Err(RestoreSnapshotError { kind: SnapshotErrorKind::MagicNumber }));
+
+        let mut bytes = snapshot.clone();
+        bytes[SNAPSHOT_MAGIC_NUMBER.len()] += 1;
+
+        assert_eq!(test_vm.restore(&bytes), Err(RestoreSnapshotError { kind: SnapshotErrorKind::OutdatedVersion }));
+
+        for len in 0..snapshot.len() {
+            assert_eq!(test_vm.restore(&snapshot[..len]), Err(RestoreSnapshotError { kind: SnapshotErrorKind::Truncated }));
+        }
+
+        // A rejected snapshot leaves the VM untouched
+        assert!(test_vm.program.is_empty());
+    }
+}
@@ -0,0 +1,95 @@
+use crate::vm::trap::TrapKind;
+
+/// The most slots the data stack may hold unless configured otherwise.
+pub const DEFAULT_MAX_STACK_SIZE: usize = 64 * 1024;
+
+/// The data stack used by `PUSH`, `POP` and friends. Every slot is 64 bits wide so it can hold the
+/// value of either an integer or a floating point register.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stack {
+    slots: Vec<u64>,
+    max_size: usize,
+}
+
+impl Default for Stack {
+    fn default() -> Self {
+        Stack::new(DEFAULT_MAX_STACK_SIZE)
+    }
+}
+
+impl Stack {
+    /// Creates an empty stack which can hold at most `max_size` slots.
+    pub fn new(max_size: usize) -> Stack {
+        Stack {
+            slots: vec![],
+            max_size,
+        }
+    }
+
+    /// The stack pointer: the number of slots currently in use.
+    pub fn sp(&self) -> usize {
+        self.slots.len()
+    }
+
+    // Moves the stack pointer, dropping the slots above it or zeroing the new ones below it.
+    pub fn set_sp(&mut self, sp: usize) -> Result<(), TrapKind> {
+        if sp > self.max_size {
+            return Err(TrapKind::StackOverflow);
+        }
+
+        self.slots.resize(sp, 0);
+
+        Ok(())
+    }
+
+    // The value of a slot below the stack pointer, counting from the bottom of the stack.
+    pub fn get(&self, slot: usize) -> Result<u64, TrapKind> {
+        self.slots.get(slot).copied().ok_or(TrapKind::StackOutOfBounds(slot as i64))
+    }
+
+    pub fn set(&mut self, slot: usize, value: u64) -> Result<(), TrapKind> {
+        let slot = self.slots.get_mut(slot).ok_or(TrapKind::StackOutOfBounds(slot as i64))?;
+
+        *slot = value;
+
+        Ok(())
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    pub fn slots(&self) -> &[u64] {
+        &self.slots
+    }
+
+    // Fails unless `count` more slots can be pushed.
+    pub fn check_push(&self, count: usize) -> Result<(), TrapKind> {
+        if self.max_size - self.slots.len() < count {
+            return Err(TrapKind::StackOverflow);
+        }
+
+        Ok(())
+    }
+
+    // Fails unless `count` slots can be popped.
+    pub fn check_pop(&self, count: usize) -> Result<(), TrapKind> {
+        if self.slots.len() < count {
+            return Err(TrapKind::StackUnderflow);
+        }
+
+        Ok(())
+    }
+
+    pub fn push(&mut self, value: u64) -> Result<(), TrapKind> {
+        self.check_push(1)?;
+
+        self.slots.push(value);
+
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<u64, TrapKind> {
+        self.slots.pop().ok_or(TrapKind::StackUnderflow)
+    }
+}
@@ -1,10 +1,16 @@
 #[cfg(test)]
 mod tests {
+    use crate::assembler::Assembler;
+    use crate::assembler::program::Program;
     use crate::vm::instructions::Opcode;
+    use crate::vm::memory::Memory;
+    use crate::vm::operand::OperandKind;
+    use crate::vm::stack::Stack;
+    use crate::vm::trap::{ExitReason, TrapKind};
     use crate::vm::VM;
 
     fn get_test_vm() -> VM {
-        return VM::default();
+        VM::default()
     }
 
     #[test]
@@ -21,7 +27,7 @@ mod tests {
             test_vm.registers[0] = $val1;
             test_vm.registers[1] = $val2;
             test_vm.program = vec![$opcode, 2, 0, 1];
-            test_vm.run_once();
+            test_vm.run_once().unwrap();
     
             assert_eq!(test_vm.registers[2], $result);
         };
@@ -34,13 +40,13 @@ mod tests {
             test_vm.registers[0] = $turuthyReg1;
             test_vm.registers[1] = $turuthyReg2;
             test_vm.program = vec![$opcode, 0, 1, $opcode, 0, 1];
-            test_vm.run_once();
+            test_vm.run_once().unwrap();
     
             assert_eq!(test_vm.equal_flag, true);
     
             test_vm.registers[0] = $falsyReg1;
             test_vm.registers[1] = $falsyReg2;
-            test_vm.run_once();
+            test_vm.run_once().unwrap();
     
             assert_eq!(test_vm.equal_flag, false);
         };
@@ -53,7 +59,7 @@ mod tests {
             test_vm.float_registers[0] = $val1;
             test_vm.float_registers[1] = $val2;
             test_vm.program = vec![$opcode, 2, 0, 1];
-            test_vm.run_once();
+            test_vm.run_once().unwrap();
     
             assert_eq!(test_vm.float_registers[2], $result);
         };
@@ -66,13 +72,13 @@ mod tests {
             test_vm.float_registers[0] = $turuthyReg1;
             test_vm.float_registers[1] = $turuthyReg2;
             test_vm.program = vec![$opcode, 0, 1, $opcode, 0, 1];
-            test_vm.run_once();
+            test_vm.run_once().unwrap();
     
             assert_eq!(test_vm.equal_flag, true);
     
             test_vm.float_registers[0] = $falsyReg1;
             test_vm.float_registers[1] = $falsyReg2;
-            test_vm.run_once();
+            test_vm.run_once().unwrap();
     
             assert_eq!(test_vm.equal_flag, false);
         };
@@ -84,7 +90,7 @@ mod tests {
       let mut test_vm = get_test_vm();
       
       test_vm.program = vec![Opcode::Halt.byte()];
-      test_vm.run();
+      test_vm.run().unwrap();
 
       assert_eq!(test_vm.pc, 1);
     }
@@ -95,7 +101,7 @@ mod tests {
         let mut test_vm = get_test_vm();
 
         test_vm.program = vec![Opcode::Set as u8, 0, 244, 1];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
 
         assert_eq!(test_vm.registers[0], 500);
     }
@@ -104,14 +110,46 @@ mod tests {
     fn load_opcode() {
         let mut test_vm = get_test_vm();
 
-        test_vm.heap = vec![0, 244, 1, 0, 0, 69];
+        test_vm.heap = Memory::from(vec![0, 244, 1, 0, 0, 69]);
         test_vm.registers[2] = 1;
         test_vm.program = vec![Opcode::Load as u8, 0, 2];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
 
         assert_eq!(test_vm.registers[0], 500);
     }
 
+    #[test]
+    fn store_opcode() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.heap = Memory::new(6, 6);
+        test_vm.registers[0] = 500;
+        test_vm.program = vec![Opcode::Store.byte(), 1, 0];
+        test_vm.run_once().unwrap();
+
+        assert_eq!(test_vm.heap.bytes(), &[0, 244, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn grow_opcode() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.heap = Memory::new(4, 8);
+        test_vm.registers[1] = 3;
+        test_vm.program = vec![Opcode::Grow.byte(), 0, 1, Opcode::Grow.byte(), 0, 1];
+        test_vm.run_once().unwrap();
+
+        // Reports the old size
+        assert_eq!(test_vm.registers[0], 4);
+        assert_eq!(test_vm.heap.len(), 7);
+
+        test_vm.run_once().unwrap();
+
+        // Growing past the maximum size fails without touching the heap
+        assert_eq!(test_vm.registers[0], -1);
+        assert_eq!(test_vm.heap.len(), 7);
+    }
+
     #[test]
     fn add_opcode() {
         math_op_test!(Opcode::Add.byte(),
@@ -140,7 +178,7 @@ mod tests {
         test_vm.registers[0] = 9;
         test_vm.registers[1] = 4;
         test_vm.program = vec![Opcode::Divide.byte(), 2, 0, 1];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
 
         assert_eq!(test_vm.registers[2], 2);
         assert_eq!(test_vm.remainder, 1);
@@ -156,11 +194,11 @@ mod tests {
         
         assert_eq!(test_vm.registers[0], 5);
 
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
 
         assert_eq!(test_vm.registers[0], 327680);
         
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
 
         assert_eq!(test_vm.registers[0], 655360);
     }
@@ -174,11 +212,11 @@ mod tests {
         
         assert_eq!(test_vm.registers[0], 655360);
 
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
 
         assert_eq!(test_vm.registers[0], 327680);
         
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
 
         assert_eq!(test_vm.registers[0], 5);
     }
@@ -190,11 +228,11 @@ mod tests {
 
         test_vm.registers[0] = 0;
         test_vm.program = vec![Opcode::Increment.byte(), 0, Opcode::Increment.byte(), 0];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
 
         assert_eq!(test_vm.registers[0], 1);
 
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
 
         assert_eq!(test_vm.registers[0], 2);
     }
@@ -205,11 +243,11 @@ mod tests {
 
         test_vm.registers[0] = 2;
         test_vm.program = vec![Opcode::Decrement.byte(), 0, Opcode::Decrement.byte(), 0];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
 
         assert_eq!(test_vm.registers[0], 1);
 
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
 
         assert_eq!(test_vm.registers[0], 0);
     }
@@ -269,7 +307,7 @@ mod tests {
         let mut test_vm = get_test_vm();
 
         test_vm.program = vec![Opcode::SetF64 as u8, 0, 244, 1, 0, 0, 0, 0, 0, 0];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
 
         assert_eq!(test_vm.float_registers[0], 500.0);
     }
@@ -278,10 +316,10 @@ mod tests {
     fn load_f64_opcode() {
         let mut test_vm = get_test_vm();
 
-        test_vm.heap = vec![0, 244, 1, 0, 0, 0, 0, 0, 0, 69];
+        test_vm.heap = Memory::from(vec![0, 244, 1, 0, 0, 0, 0, 0, 0, 69]);
         test_vm.registers[2] = 1;
         test_vm.program = vec![Opcode::LoadF64 as u8, 0, 2];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
 
         assert_eq!(test_vm.float_registers[0], 500.0);
     }
@@ -369,7 +407,7 @@ mod tests {
 
         test_vm.registers[0] = 4;
         test_vm.program = vec![Opcode::Jump.byte(), 0];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
 
         // We should have jumped to byte 4 (because register 0 is 4)
         assert_eq!(test_vm.pc, 4);
@@ -381,7 +419,7 @@ mod tests {
 
         test_vm.registers[0] = 2;
         test_vm.program = vec![Opcode::JumpForward.byte(), 0];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
         
         // We should have jumped forward 2 bytes (because register 0 is 2)
         assert_eq!(test_vm.pc, 4);
@@ -394,7 +432,7 @@ mod tests {
         test_vm.pc = 4;
         test_vm.registers[0] = 6;
         test_vm.program = vec![0, 0, 0, 0, Opcode::JumpBackward.byte(), 0];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
 
         // We should have jumped backward 6 bytes (because register 0 is 6)
         // The amount to jump is 6 because we also have to include the jump back instruction
@@ -408,21 +446,540 @@ mod tests {
         test_vm.registers[1] = 7;
         test_vm.equal_flag = true;
         test_vm.program = vec![Opcode::JumpIfEqual.byte(), 1];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
         
         // We should appear on byte seven, as that's where register 1 sends us
         assert_eq!(test_vm.pc, 7);
     }
 
+    #[test]
+    fn call_and_return_opcodes() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.registers[8] = 1;
+        test_vm.float_registers[16] = 1.0;
+        test_vm.program = vec![
+            Opcode::Call.byte(), 4, 0,      // Call the subroutine at byte 4
+            Opcode::Halt.byte(),
+
+            Opcode::Set.byte(), 8, 2, 0,    // Clobber a saved register
+            Opcode::SetF64.byte(), 16, 2, 0,
+            Opcode::Set.byte(), 0, 3, 0,    // Return 3 in $0
+            Opcode::Return.byte(),
+        ];
+
+        test_vm.run_once().unwrap();
+
+        assert_eq!(test_vm.pc, 4);
+        assert_eq!(test_vm.call_stack.len(), 1);
+        assert_eq!(test_vm.call_stack[0].return_address, 3);
+
+        assert_eq!(test_vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(test_vm.pc, 4);
+        assert!(test_vm.call_stack.is_empty());
+
+        assert_eq!(test_vm.registers[0], 3);
+        assert_eq!(test_vm.registers[8], 1);
+        assert_eq!(test_vm.float_registers[16], 1.0);
+    }
+
+    #[test]
+    fn call_register_opcode() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.registers[1] = 3;
+        test_vm.program = vec![Opcode::CallRegister.byte(), 1, Opcode::Halt.byte(), Opcode::Return.byte()];
+
+        assert_eq!(test_vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(test_vm.pc, 3);
+        assert_eq!(test_vm.ic, 3);
+    }
+
+    #[test]
+    fn call_stack_overflow_traps() {
+        let mut test_vm = get_test_vm();
+
+        // Recurse forever
+        test_vm.max_call_depth = 8;
+        test_vm.program = vec![Opcode::Call.byte(), 0, 0];
+
+        let trap = test_vm.run().unwrap_err();
+
+        assert_eq!(trap.kind, TrapKind::CallStackOverflow);
+        assert_eq!(trap.ic, 9);
+        assert_eq!(test_vm.call_stack.len(), 8);
+    }
+
+    #[test]
+    fn return_without_call_traps() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.program = vec![Opcode::Return.byte()];
+
+        assert_eq!(test_vm.run().unwrap_err().kind, TrapKind::CallStackUnderflow);
+    }
+
+    #[test]
+    fn push_and_pop_opcodes() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.registers[0] = -5;
+        test_vm.float_registers[0] = 2.5;
+        test_vm.program = vec![
+            Opcode::Push.byte(), 0,
+            Opcode::PushF64.byte(), 0,
+            Opcode::PopF64.byte(), 1,
+            Opcode::Pop.byte(), 1,
+        ];
+
+        test_vm.run_once().unwrap();
+        test_vm.run_once().unwrap();
+
+        assert_eq!(test_vm.stack.sp(), 2);
+
+        test_vm.run().unwrap();
+
+        assert_eq!(test_vm.stack.sp(), 0);
+        assert_eq!(test_vm.registers[1], -5);
+        assert_eq!(test_vm.float_registers[1], 2.5);
+    }
+
+    #[test]
+    fn push_all_and_pop_all_opcodes() {
+        let mut test_vm = get_test_vm();
+
+        for i in 0..16 {
+            test_vm.registers[i] = i as i32;
+        }
+
+        test_vm.program = vec![Opcode::PushAll.byte(), Opcode::PopAll.byte()];
+        test_vm.run_once().unwrap();
+
+        assert_eq!(test_vm.stack.sp(), 16);
+
+        test_vm.registers = [0; 16];
+        test_vm.run_once().unwrap();
+
+        assert_eq!(test_vm.stack.sp(), 0);
+        assert_eq!(test_vm.registers[3], 3);
+        assert_eq!(test_vm.registers[15], 15);
+    }
+
+    #[test]
+    fn stack_overflow_and_underflow_trap() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.stack = Stack::new(15);
+        test_vm.program = vec![Opcode::PushAll.byte()];
+
+        assert_eq!(test_vm.run().unwrap_err().kind, TrapKind::StackOverflow);
+
+        // Nothing was pushed by the faulting instruction
+        assert_eq!(test_vm.stack.sp(), 0);
+
+        test_vm.program = vec![Opcode::Pop.byte(), 0];
+
+        assert_eq!(test_vm.run().unwrap_err().kind, TrapKind::StackUnderflow);
+    }
+
+    #[test]
+    fn stack_pointer_opcodes() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.registers[0] = 7;
+        test_vm.registers[1] = 3;
+        test_vm.program = vec![
+            Opcode::Push.byte(), 0,
+            Opcode::SetStackPointer.byte(), 1,      // Make room for two more slots
+            Opcode::GetStackPointer.byte(), 2,
+            Opcode::StoreSlot.byte(), 2, 0,         // Out of bounds, as slot 3 is not in use
+        ];
+        test_vm.run_once().unwrap();
+        test_vm.run_once().unwrap();
+
+        assert_eq!(test_vm.stack.slots(), &[7, 0, 0]);
+
+        test_vm.run_once().unwrap();
+
+        assert_eq!(test_vm.registers[2], 3);
+        assert_eq!(test_vm.run_once().unwrap_err().kind, TrapKind::StackOutOfBounds(3));
+
+        // Spill a register to slot 2 and read it back
+        test_vm.registers[3] = 2;
+        test_vm.registers[4] = -9;
+        test_vm.pc = 0;
+        test_vm.program = vec![
+            Opcode::StoreSlot.byte(), 3, 4,
+            Opcode::LoadSlot.byte(), 5, 3,
+            Opcode::Pop.byte(), 6,
+        ];
+        test_vm.run().unwrap();
+
+        assert_eq!(test_vm.registers[5], -9);
+        assert_eq!(test_vm.registers[6], -9);
+        assert_eq!(test_vm.stack.sp(), 2);
+
+        // Dropping slots
+        test_vm.registers[0] = 0;
+        test_vm.pc = 0;
+        test_vm.program = vec![Opcode::SetStackPointer.byte(), 0];
+        test_vm.run().unwrap();
+
+        assert_eq!(test_vm.stack.sp(), 0);
+    }
+
+    #[test]
+    fn stack_pointer_out_of_bounds_traps() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.stack = Stack::new(4);
+        test_vm.registers[0] = 5;
+        test_vm.registers[1] = -1;
+
+        let mut trap = |program: Vec<u8>| {
+            test_vm.pc = 0;
+            test_vm.program = program;
+            test_vm.run().unwrap_err().kind
+        };
+
+        assert_eq!(trap(vec![Opcode::SetStackPointer.byte(), 0]), TrapKind::StackOverflow);
+        assert_eq!(trap(vec![Opcode::SetStackPointer.byte(), 1]), TrapKind::StackUnderflow);
+        assert_eq!(trap(vec![Opcode::LoadSlot.byte(), 2, 1]), TrapKind::StackOutOfBounds(-1));
+        assert_eq!(trap(vec![Opcode::LoadSlot.byte(), 2, 0]), TrapKind::StackOutOfBounds(5));
+
+        // The stack is left as it was
+        assert_eq!(test_vm.stack.sp(), 0);
+    }
+
     #[test]
     fn move_opcode() {
         let mut test_vm = get_test_vm();
 
         test_vm.registers[1] = 1;
         test_vm.program = vec![Opcode::Move.byte(), 0, 1];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
         
         // We should appear on byte seven, as that's where register 1 sends us
         assert_eq!(test_vm.registers[0], 1);
     }
-}
\ No newline at end of file
+
+
+    #[test]
+    fn run_reports_exit_reason() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.program = vec![Opcode::Increment.byte(), 0, Opcode::Halt.byte()];
+
+        assert_eq!(test_vm.run_once(), Ok(ExitReason::Stepped));
+        assert_eq!(test_vm.run(), Ok(ExitReason::Halted));
+
+        test_vm.program = vec![Opcode::Increment.byte(), 0];
+        test_vm.pc = 0;
+
+        assert_eq!(test_vm.run(), Ok(ExitReason::EndOfProgram));
+    }
+
+    #[test]
+    fn invalid_opcode_traps() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.program = vec![Opcode::Increment.byte(), 0, 0xFF];
+        
+        let trap = test_vm.run().unwrap_err();
+
+        assert_eq!(trap.kind, TrapKind::InvalidOpcode);
+        assert_eq!(trap.pc, 2);
+        assert_eq!(trap.ic, 2);
+        assert_eq!(trap.opcode, 0xFF);
+
+        // The state before the trap is still intact
+        assert_eq!(test_vm.pc, 2);
+        assert_eq!(test_vm.registers[0], 1);
+    }
+
+    #[test]
+    fn bad_register_traps() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.program = vec![Opcode::Add.byte(), 2, 0, 16];
+
+        assert_eq!(test_vm.run().unwrap_err().kind, TrapKind::BadRegister(16));
+        
+        test_vm.program = vec![Opcode::AddF64.byte(), 2, 0, 32];
+
+        assert_eq!(test_vm.run().unwrap_err().kind, TrapKind::BadRegister(32));
+    }
+
+    #[test]
+    fn heap_out_of_bounds_traps() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.heap = Memory::from(vec![0, 0, 0, 0]);
+        test_vm.registers[1] = 2;
+        test_vm.program = vec![Opcode::Load.byte(), 0, 1];
+
+        assert_eq!(test_vm.run().unwrap_err().kind, TrapKind::HeapOutOfBounds { address: 2, size: 4 });
+    }
+
+    #[test]
+    fn divide_by_zero_traps() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.registers[0] = 9;
+        test_vm.program = vec![Opcode::Divide.byte(), 2, 0, 1];
+
+        assert_eq!(test_vm.run().unwrap_err().kind, TrapKind::DivideByZero);
+    }
+
+    #[test]
+    fn truncated_instruction_traps() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.program = vec![Opcode::Set.byte(), 0, 1];
+
+        let trap = test_vm.run().unwrap_err();
+
+        assert_eq!(trap.kind, TrapKind::TruncatedInstruction);
+        assert_eq!(test_vm.pc, 0);
+    }
+
+    #[test]
+    fn jump_before_program_traps() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.registers[0] = 6;
+        test_vm.program = vec![Opcode::JumpBackward.byte(), 0];
+
+        assert_eq!(test_vm.run().unwrap_err().kind, TrapKind::InvalidJump);
+    }
+
+
+    #[test]
+    fn run_with_fuel_resumes() {
+        let mut test_vm = get_test_vm();
+
+        // An endless loop of increments
+        test_vm.program = vec![Opcode::Increment.byte(), 0, Opcode::Jump.byte(), 1];
+
+        assert_eq!(test_vm.run_with_fuel(5), Ok(ExitReason::OutOfFuel));
+        assert_eq!(test_vm.registers[0], 3);
+        assert_eq!(test_vm.pc, 2);
+        assert_eq!(test_vm.ic, 5);
+
+        assert_eq!(test_vm.run_with_fuel(2), Ok(ExitReason::OutOfFuel));
+        assert_eq!(test_vm.registers[0], 4);
+        assert_eq!(test_vm.pc, 2);
+
+        test_vm.program = vec![Opcode::Halt.byte()];
+        test_vm.pc = 0;
+
+        assert_eq!(test_vm.run_with_fuel(1), Ok(ExitReason::Halted));
+    }
+
+    #[test]
+    fn run_with_fuel_costs() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.fuel_costs.set(Opcode::Divide, 10);
+        test_vm.registers[1] = 1;
+        test_vm.program = vec![Opcode::Increment.byte(), 0, Opcode::Divide.byte(), 2, 0, 1];
+
+        assert_eq!(test_vm.run_with_fuel(5), Ok(ExitReason::OutOfFuel));
+        assert_eq!(test_vm.pc, 2);
+
+        // The leftover fuel carries over to the next call
+        assert_eq!(test_vm.fuel, 4);
+        assert_eq!(test_vm.run_with_fuel(6), Ok(ExitReason::EndOfProgram));
+        assert_eq!(test_vm.fuel, 0);
+        assert_eq!(test_vm.registers[2], 1);
+    }
+
+
+    #[test]
+    fn predecoded_program_runs_the_same() {
+        let program = vec![
+            Opcode::Set.byte(), 0, 10, 0,   // Loop 10 times
+            Opcode::Set.byte(), 1, 12, 0,   // Start of the loop
+            Opcode::Set.byte(), 2, 23, 0,   // End of the loop
+            Opcode::Equal.byte(), 0, 3,
+            Opcode::JumpIfEqual.byte(), 2,
+            Opcode::Decrement.byte(), 0,
+            Opcode::Increment.byte(), 4,
+            Opcode::Jump.byte(), 1,
+            Opcode::Halt.byte(),
+        ];
+
+        let mut test_vm = get_test_vm();
+
+        test_vm.program = program.clone();
+
+        assert_eq!(test_vm.run(), Ok(ExitReason::Halted));
+
+        let mut predecoded_vm = get_test_vm();
+
+        predecoded_vm.program = program;
+        predecoded_vm.predecode();
+
+        assert_eq!(predecoded_vm.run(), Ok(ExitReason::Halted));
+
+        assert_eq!(predecoded_vm.registers, test_vm.registers);
+        assert_eq!(predecoded_vm.registers[4], 10);
+        assert_eq!(predecoded_vm.pc, test_vm.pc);
+        assert_eq!(predecoded_vm.ic, test_vm.ic);
+    }
+
+    #[test]
+    fn predecoded_program_traps_the_same() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.registers[0] = 2;
+        test_vm.program = vec![Opcode::Jump.byte(), 0, 0xFF, Opcode::Halt.byte()];
+        test_vm.predecode();
+
+        // Jumping to the invalid byte still traps
+        let trap = test_vm.run().unwrap_err();
+
+        assert_eq!(trap.kind, TrapKind::InvalidOpcode);
+        assert_eq!(trap.pc, 2);
+        assert_eq!(trap.ic, 2);
+
+        // Jumping past it reaches the halt
+        test_vm.registers[0] = 3;
+        test_vm.pc = 0;
+
+        assert_eq!(test_vm.run(), Ok(ExitReason::Halted));
+    }
+
+    #[test]
+    fn changed_program_is_not_predecoded() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.set_program(vec![Opcode::Increment.byte(), 0, Opcode::Halt.byte()]);
+        test_vm.predecode();
+
+        // The same length as before, but different instructions
+        test_vm.set_program(vec![Opcode::Decrement.byte(), 0, Opcode::Halt.byte()]);
+
+        assert_eq!(test_vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(test_vm.registers[0], -1);
+
+        // Changed in place by a host function while running
+        test_vm.register_host_fn(1, |vm| {
+            vm.program_mut()[3] = Opcode::Decrement.byte();
+
+            Ok(())
+        });
+
+        test_vm.set_program(vec![Opcode::Syscall.byte(), 1, 0, Opcode::Increment.byte(), 0]);
+        test_vm.predecode();
+        test_vm.registers[0] = 0;
+        test_vm.pc = 0;
+
+        assert_eq!(test_vm.run(), Ok(ExitReason::EndOfProgram));
+        assert_eq!(test_vm.registers[0], -1);
+
+        // Until it is decoded again
+        assert!(test_vm.decoded.is_none());
+    }
+
+
+    #[test]
+    fn load_verified_program() {
+        let mut test_vm = get_test_vm();
+
+        let diagnostics = test_vm.load_verified(vec![Opcode::Increment.byte(), 16]).unwrap_err();
+
+        assert_eq!(diagnostics.len(), 1);
+        assert!(test_vm.program.is_empty());
+
+        test_vm.load_verified(vec![Opcode::Increment.byte(), 0, Opcode::Halt.byte()]).unwrap();
+
+        assert_eq!(test_vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(test_vm.registers[0], 1);
+    }
+
+
+    #[test]
+    fn syscall_opcode() {
+        let mut test_vm = get_test_vm();
+
+        // Adds its two arguments
+        test_vm.register_host_fn(7, |vm| {
+            vm.registers[0] += vm.registers[1];
+
+            Ok(())
+        });
+
+        test_vm.registers[0] = 2;
+        test_vm.registers[1] = 3;
+        test_vm.program = vec![Opcode::Syscall.byte(), 7, 0, Opcode::Syscall.byte(), 7, 0];
+        test_vm.run().unwrap();
+
+        assert_eq!(test_vm.registers[0], 8);
+    }
+
+    #[test]
+    fn syscall_errors_trap() {
+        let mut test_vm = get_test_vm();
+
+        test_vm.register_host_fn(1, |_| Err(String::from("no disk")));
+        test_vm.program = vec![Opcode::Syscall.byte(), 2, 0];
+
+        assert_eq!(test_vm.run().unwrap_err().kind, TrapKind::UnknownHostFunction(2));
+
+        test_vm.program = vec![Opcode::Syscall.byte(), 1, 0];
+
+        assert_eq!(test_vm.run().unwrap_err().kind, TrapKind::HostError(String::from("no disk")));
+    }
+
+    #[test]
+    fn describe_operands() {
+        assert_eq!(Opcode::Store.operands(), &[OperandKind::Address, OperandKind::Register]);
+        assert_eq!(Opcode::Store.operand_names(), &["target", "value"]);
+        assert_eq!(Opcode::Store.usage(), "STOR #target $value");
+        assert_eq!(Opcode::Set.usage(), "SET $target value");
+        assert_eq!(Opcode::Halt.usage(), "HLT");
+
+        for opcode in Opcode::all() {
+            let size: usize = opcode.operands().iter().map(|kind| kind.size()).sum();
+
+            assert_eq!(opcode.encoded_len(), 1 + size);
+            assert_eq!(opcode.operand_names().len(), opcode.operands().len());
+        }
+    }
+
+    #[test]
+    fn readme_lists_every_instruction() {
+        assert!(include_str!("../../README.md").contains(&Opcode::reference()), "README.md is out of date, copy in Opcode::reference()");
+    }
+
+    #[test]
+    fn boot_from_program() {
+        let mut assembler = Assembler::default();
+
+        assembler.compile("
+            .rodata
+            start: .word 5
+            .bss
+            total: .space 4
+
+            .text
+                HLT
+            main:
+                SET $1 start
+                LOAD $0 $1
+                INC $0
+                STOR total $0
+        ").unwrap();
+
+        let program = Program::load(&assembler.result.bytes().unwrap()[..]).unwrap();
+
+        let mut test_vm = VM::from_program(program);
+
+        assert_eq!(test_vm.pc, 1);
+        assert_eq!(test_vm.heap.len(), 12);
+
+        assert_eq!(test_vm.run(), Ok(ExitReason::EndOfProgram));
+        assert_eq!(test_vm.heap.read_u32(8), Ok(6));
+    }
+}
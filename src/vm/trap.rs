@@ -0,0 +1,111 @@
+use std::error::Error;
+use std::fmt;
+
+/// Why a call to `VM::run` or `VM::run_once` returned without trapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// A single instruction was executed and the VM can keep going. Only returned by `run_once`.
+    Stepped,
+
+    /// A `HLT` instruction was executed.
+    Halted,
+
+    /// The program counter ran past the last byte of the program.
+    EndOfProgram,
+
+    /// The fuel given to `run_with_fuel` ran out. The program counter is left on the next instruction.
+    OutOfFuel,
+
+    /// The program counter reached one of `VM::breakpoints`. The instruction there has not been executed yet.
+    Breakpoint,
+
+    /// The last instruction accessed `size` bytes at `address`, hitting one of `VM::watchpoints`.
+    Watchpoint { address: usize, size: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TrapKind {
+    /// The byte at the program counter is not a known opcode.
+    InvalidOpcode,
+
+    /// An operand referenced a register that does not exist.
+    BadRegister(u8),
+
+    /// A heap access of `size` bytes at `address` fell outside of the heap.
+    HeapOutOfBounds { address: usize, size: usize },
+
+    /// An integer division had a divisor of zero.
+    DivideByZero,
+
+    /// The program ended in the middle of an instruction's operands.
+    TruncatedInstruction,
+
+    /// A jump would have moved the program counter before the start of the program.
+    InvalidJump,
+
+    /// A call was made while the call stack was at its maximum depth.
+    CallStackOverflow,
+
+    /// A return was made without a matching call.
+    CallStackUnderflow,
+
+    /// A push was made while the data stack was full.
+    StackOverflow,
+
+    /// A pop was made while the data stack was empty.
+    StackUnderflow,
+
+    /// A data stack slot, given here, was accessed that is not below the stack pointer.
+    StackOutOfBounds(i64),
+
+    /// `SYSCALL` was made with an id no host function is registered under.
+    UnknownHostFunction(u16),
+
+    /// A host function returned an error.
+    HostError(String),
+}
+
+impl fmt::Display for TrapKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrapKind::InvalidOpcode => write!(f, "invalid opcode"),
+            TrapKind::BadRegister(register) => write!(f, "bad register ${}", register),
+            TrapKind::HeapOutOfBounds { address, size } => write!(f, "heap access of {} bytes at {:#x} is out of bounds", size, address),
+            TrapKind::DivideByZero => write!(f, "divide by zero"),
+            TrapKind::TruncatedInstruction => write!(f, "truncated instruction"),
+            TrapKind::InvalidJump => write!(f, "invalid jump"),
+            TrapKind::CallStackOverflow => write!(f, "call stack overflow"),
+            TrapKind::CallStackUnderflow => write!(f, "return without a matching call"),
+            TrapKind::StackOverflow => write!(f, "data stack overflow"),
+            TrapKind::StackUnderflow => write!(f, "data stack underflow"),
+            TrapKind::StackOutOfBounds(slot) => write!(f, "data stack slot {} is not in use", slot),
+            TrapKind::UnknownHostFunction(id) => write!(f, "no host function registered with id {}", id),
+            TrapKind::HostError(message) => write!(f, "host function failed: {}", message),
+        }
+    }
+}
+
+/// Raised when an instruction cannot be executed. The VM is left as it was before the faulting
+/// instruction started, so `pc` points at the instruction that trapped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trap {
+    /// Byte offset of the faulting instruction
+    pub pc: usize,
+
+    /// Instruction count at the time of the trap, including the faulting instruction
+    pub ic: usize,
+
+    /// The opcode byte of the faulting instruction
+    pub opcode: u8,
+
+    pub kind: TrapKind,
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "trap at {:#06x} (opcode {:#04x}, instruction {}): {}", self.pc, self.opcode, self.ic, self.kind)
+    }
+}
+
+impl Error for Trap { }
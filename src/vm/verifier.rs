@@ -0,0 +1,282 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::vm::REGISTER_COUNT;
+use crate::vm::instructions::{Instruction, Opcode};
+use crate::vm::trap::TrapKind;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// The byte is not a known opcode.
+    InvalidOpcode(u8),
+
+    /// The program ends in the middle of the instruction's operands.
+    TruncatedInstruction,
+
+    /// An operand references a register that does not exist.
+    BadRegister(u8),
+
+    /// A jump lands in the middle of an instruction.
+    MisalignedJump(usize),
+
+    /// A jump lands before the start or past the end of the program.
+    JumpOutOfBounds(i64),
+}
+
+/// A problem found by `verify`, along with the byte offset of the offending instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub offset: usize,
+    pub kind: DiagnosticKind,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#06x}: ", self.offset)?;
+
+        match self.kind {
+            DiagnosticKind::InvalidOpcode(byte) => write!(f, "invalid opcode {:#04x}", byte),
+            DiagnosticKind::TruncatedInstruction => write!(f, "truncated instruction"),
+            DiagnosticKind::BadRegister(register) => write!(f, "register ${} does not exist", register),
+            DiagnosticKind::MisalignedJump(target) => write!(f, "jump to {:#06x} lands inside an instruction", target),
+            DiagnosticKind::JumpOutOfBounds(target) => write!(f, "jump to {} is outside of the program", target),
+        }
+    }
+}
+
+/// Statically checks a program before it is run. Every instruction must be a known opcode with all
+/// of its operands present and registers in range, and every jump target that is statically known
+/// must land on an instruction boundary. Returns every problem found, ordered by offset.
+///
+/// A jump target is statically known when its register was set by `SET` (or copied from such a
+/// register with `MOV`) earlier in the same straight-line run of code. Runs end at jumps, calls and
+/// returns, as the code after them is reached from elsewhere, and at every instruction a known
+/// jump lands on, as it may be reached with other values in the registers.
+pub fn verify(program: &[u8]) -> Vec<Diagnostic> {
+    // Where runs of code meet. Finding a jump target splits a run, which may hide targets found
+    // before, so targets are collected until no new ones turn up. Keeping targets that disappear
+    // only makes less known, never more.
+    let mut merges = HashSet::new();
+
+    loop {
+        let (mut diagnostics, boundaries, jumps) = scan(program, &merges);
+
+        let count = merges.len();
+
+        merges.extend(jumps.iter().filter(|&&(_, target)| target >= 0).map(|&(_, target)| target as usize));
+
+        if merges.len() > count {
+            continue;
+        }
+
+        for (offset, target) in jumps {
+            let kind = if target < 0 || target as usize >= boundaries.len() {
+                DiagnosticKind::JumpOutOfBounds(target)
+            } else if !boundaries[target as usize] {
+                DiagnosticKind::MisalignedJump(target as usize)
+            } else {
+                continue;
+            };
+
+            diagnostics.push(Diagnostic { offset, kind });
+        }
+
+        diagnostics.sort_by_key(|diagnostic| diagnostic.offset);
+
+        return diagnostics;
+    }
+}
+
+// Decodes every instruction, forgetting known registers at `merges`. Returns the instructions that
+// failed to decode, whether an instruction starts at each offset, and the offsets of jumps along
+// with their known targets.
+fn scan(program: &[u8], merges: &HashSet<usize>) -> (Vec<Diagnostic>, Vec<bool>, Vec<(usize, i64)>) {
+    let mut diagnostics = vec![];
+
+    // Jumping to the very end is allowed
+    let mut boundaries = vec![false; program.len() + 1];
+    boundaries[program.len()] = true;
+
+    let mut jumps = vec![];
+
+    let mut known: [Option<i32>; REGISTER_COUNT] = [None; REGISTER_COUNT];
+
+    let mut pos = 0;
+
+    while pos < program.len() {
+        boundaries[pos] = true;
+
+        if merges.contains(&pos) {
+            known = [None; REGISTER_COUNT];
+        }
+
+        match Instruction::decode(program, pos) {
+            Ok((instruction, next)) => {
+                track_jumps(instruction, next, &mut known, |target| jumps.push((pos, target)));
+
+                pos = next;
+            },
+            Err(kind) => {
+                let kind = match kind {
+                    TrapKind::BadRegister(register) => DiagnosticKind::BadRegister(register),
+                    TrapKind::TruncatedInstruction => DiagnosticKind::TruncatedInstruction,
+                    _ => DiagnosticKind::InvalidOpcode(program[pos]),
+                };
+
+                diagnostics.push(Diagnostic { offset: pos, kind });
+
+                known = [None; REGISTER_COUNT];
+
+                // Skip the whole instruction if we know how long it is
+                pos = match Opcode::try_from(program[pos]) {
+                    Ok(opcode) => pos + opcode.encoded_len(),
+                    Err(_) => pos + 1,
+                };
+            },
+        }
+    }
+
+    (diagnostics, boundaries, jumps)
+}
+
+// Follows constants through the integer registers, reporting the target of every jump whose
+// target is known.
+fn track_jumps(instruction: Instruction, next: usize, known: &mut [Option<i32>; REGISTER_COUNT], mut jump: impl FnMut(i64)) {
+    let next = next as i64;
+
+    match instruction {
+        Instruction::Set { target, value } => known[target.index()] = Some(i32::from(value)),
+        Instruction::Move { target, value } => known[target.index()] = known[value.index()],
+
+        Instruction::JumpIfEqual { byte } => {
+            if let Some(target) = known[byte.index()] {
+                jump(i64::from(target));
+            }
+        },
+
+        Instruction::Jump { byte } | Instruction::CallRegister { byte } => {
+            if let Some(target) = known[byte.index()] {
+                jump(i64::from(target));
+            }
+
+            *known = [None; REGISTER_COUNT];
+        },
+        Instruction::JumpForward { bytes } => {
+            if let Some(distance) = known[bytes.index()] {
+                jump(next + i64::from(distance));
+            }
+
+            *known = [None; REGISTER_COUNT];
+        },
+        Instruction::JumpBackward { bytes } => {
+            if let Some(distance) = known[bytes.index()] {
+                jump(next - i64::from(distance));
+            }
+
+            *known = [None; REGISTER_COUNT];
+        },
+        Instruction::Call { target } => {
+            jump(i64::from(target));
+
+            *known = [None; REGISTER_COUNT];
+        },
+
+        Instruction::Halt { } | Instruction::Return { } | Instruction::PopAll { }
+            | Instruction::Syscall { .. } => *known = [None; REGISTER_COUNT],
+
+        Instruction::Load { target, .. } | Instruction::Grow { target, .. }
+            | Instruction::Add { target, .. } | Instruction::Subtract { target, .. }
+            | Instruction::Multiply { target, .. } | Instruction::Divide { target, .. }
+            | Instruction::And { target, .. } | Instruction::Or { target, .. } | Instruction::XOR { target, .. }
+            | Instruction::ShiftLeft { target, .. } | Instruction::ShiftRight { target, .. }
+            | Instruction::Increment { target } | Instruction::Decrement { target }
+            | Instruction::Pop { target } | Instruction::GetStackPointer { target }
+            | Instruction::LoadSlot { target, .. } => known[target.index()] = None,
+
+        _ => { },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_valid_program() {
+        let program = vec![
+            Opcode::Set.byte(), 0, 1, 0,    // Set $0 to 1
+            Opcode::JumpForward.byte(), 0,  // Jump forward $0 bytes
+            Opcode::Halt.byte(),
+
+            Opcode::Set.byte(), 0, 6, 0,    // Load $0 with 6: byte of the halt instruction
+            Opcode::Set.byte(), 6, 15, 0,   // Load $6 with 15: the start of the loop
+            Opcode::Equal.byte(), 1, 2,
+            Opcode::JumpIfEqual.byte(), 0,
+            Opcode::Increment.byte(), 1,
+            Opcode::Jump.byte(), 6,
+        ];
+
+        assert_eq!(verify(&program), vec![]);
+    }
+
+    #[test]
+    fn verify_reports_bad_instructions() {
+        let program = vec![
+            0xFF,
+            Opcode::Add.byte(), 2, 0, 16,
+            Opcode::MoveF64.byte(), 31, 32,
+            Opcode::Increment.byte(), 0,
+            Opcode::Set.byte(), 0,
+        ];
+
+        assert_eq!(verify(&program), vec![
+            Diagnostic { offset: 0, kind: DiagnosticKind::InvalidOpcode(0xFF) },
+            Diagnostic { offset: 1, kind: DiagnosticKind::BadRegister(16) },
+            Diagnostic { offset: 5, kind: DiagnosticKind::BadRegister(32) },
+            Diagnostic { offset: 10, kind: DiagnosticKind::TruncatedInstruction },
+        ]);
+    }
+
+    #[test]
+    fn verify_reports_bad_jumps() {
+        let program = vec![
+            Opcode::Set.byte(), 0, 2, 0,
+            Opcode::Move.byte(), 1, 0,
+            Opcode::JumpIfEqual.byte(), 1,  // Into the middle of the first instruction
+            Opcode::Set.byte(), 0, 20, 0,
+            Opcode::JumpBackward.byte(), 0, // Before the start of the program
+            Opcode::Call.byte(), 200, 0,    // Past the end of the program
+        ];
+
+        assert_eq!(verify(&program), vec![
+            Diagnostic { offset: 7, kind: DiagnosticKind::MisalignedJump(2) },
+            Diagnostic { offset: 13, kind: DiagnosticKind::JumpOutOfBounds(-5) },
+            Diagnostic { offset: 15, kind: DiagnosticKind::JumpOutOfBounds(200) },
+        ]);
+    }
+
+    #[test]
+    fn forget_registers_where_jumps_land() {
+        let program = vec![
+            Opcode::Set.byte(), 0, 1, 0,    // $0 is 1 when falling into the loop
+            Opcode::Increment.byte(), 2,    // The loop starts here
+            Opcode::JumpIfEqual.byte(), 0,  // Only valid once $0 has been set below
+            Opcode::Set.byte(), 0, 14, 0,   // $0 is 14 on every later iteration
+            Opcode::Set.byte(), 1, 4, 0,
+            Opcode::Jump.byte(), 1,         // Back to the start of the loop
+        ];
+
+        assert_eq!(verify(&program), vec![]);
+
+        // Without a jump back to the loop, `$0` can only be 1 there
+        let mut program = program;
+
+        program[14] = 3;
+
+        assert_eq!(verify(&program), vec![
+            Diagnostic { offset: 6, kind: DiagnosticKind::MisalignedJump(1) },
+            Diagnostic { offset: 16, kind: DiagnosticKind::MisalignedJump(3) },
+        ]);
+    }
+}